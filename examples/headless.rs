@@ -0,0 +1,27 @@
+// Lists the newest messages without starting the TUI.
+//
+// Uses the same config.toml / environment as the main binary, so it is a
+// quick way to check that the default account points at a working server:
+//
+//     cargo run --example headless -- 10
+
+use bps_mail::config::Config;
+use bps_mail::imap::ImapClient;
+use std::error::Error;
+
+fn main() -> Result<(), Box<dyn Error>>
+{
+    let count = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(10);
+
+    let cfg = Config::load(None)?;
+    let mut imap = ImapClient::connect(&cfg.imap, &cfg.imap_user, cfg.imap_pass.expose())?;
+
+    for ms in imap.fetch_inbox(count)?
+    {
+        println!("{:>8}  {}  {:<24}  {}", ms.uid, ms.date.format("%Y-%m-%d %H:%M"), ms.sender(), ms.subject);
+    }
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use crate::imap::models::MailSummary;
+use crate::imap::search::{SearchQuery, Term};
+
+use chrono::NaiveDate;
+use rusqlite::types::Value;
+
+/// Start of a matched word in `Hit::snippet`.
+pub const MATCH_START: char = '\u{2}';
+/// End of a matched word in `Hit::snippet`.
+pub const MATCH_END: char = '\u{3}';
+
+/// One search result: a message and the folder it is in.
+#[derive(Debug, Clone)]
+pub struct Hit
+{
+    pub folder: String,
+    pub summary: MailSummary,
+    /// Text around the best match from the local index, the matched words
+    /// between `MATCH_START` and `MATCH_END`. `None` for messages only the
+    /// server found.
+    pub snippet: Option<String>,
+}
+
+/// A snippet cut into `(text, matched)` pieces for highlighting.
+pub fn snippet_parts(snippet: &str) -> Vec<(&str, bool)>
+{
+    let mut parts = Vec::new();
+    let mut rest = snippet;
+    while let Some(start) = rest.find(MATCH_START)
+    {
+        parts.push((&rest[..start], false));
+        rest = &rest[start + MATCH_START.len_utf8()..];
+        let end = rest.find(MATCH_END).unwrap_or(rest.len());
+        parts.push((&rest[..end], true));
+        rest = rest.get(end + MATCH_END.len_utf8()..).unwrap_or_default();
+    }
+    parts.push((rest, false));
+    parts.retain(|(text, _)| !text.is_empty());
+    parts
+}
+
+/// The FTS5 `MATCH` expression for the words of `query`; `None` if it only
+/// has conditions the index does not hold (dates, status, size). Every
+/// value is a phrase whose last word may be a prefix, so `from:ali` finds
+/// alice.
+pub(crate) fn match_expression(query: &SearchQuery) -> Option<String>
+{
+    let phrase = |value: &str| format!("\"{}\"*", value.replace('"', "\"\""));
+    let terms: Vec<String> = query
+        .terms
+        .iter()
+        .filter_map(|term| match term
+        {
+            // A value without any letter or digit has no words to look up.
+            Term::From(v) | Term::To(v) | Term::Subject(v) | Term::Body(v) | Term::Text(v)
+                if !v.chars().any(char::is_alphanumeric) =>
+            {
+                None
+            }
+            Term::From(v) => Some(format!("{{sender sender_name}} : {}", phrase(v))),
+            Term::To(v) => Some(format!("recipients : {}", phrase(v))),
+            Term::Subject(v) => Some(format!("subject : {}", phrase(v))),
+            Term::Body(v) => Some(format!("body : {}", phrase(v))),
+            Term::Text(v) => Some(phrase(v)),
+            _ => None,
+        })
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" AND "))
+}
+
+/// SQL conditions on `messages m` for the terms the index does not cover,
+/// with their parameters in order.
+pub(crate) fn filters(query: &SearchQuery) -> (Vec<String>, Vec<Value>)
+{
+    let midnight = |d: &NaiveDate| Value::Integer(d.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc().timestamp());
+    let has = |flag: &str| format!("(' ' || m.flags || ' ') LIKE '% {} %'", flag);
+    let mut conditions = Vec::new();
+    let mut params = Vec::new();
+    for term in &query.terms
+    {
+        match term
+        {
+            Term::Since(d) =>
+            {
+                conditions.push("m.timestamp >= ?".to_string());
+                params.push(midnight(d));
+            }
+            Term::Before(d) =>
+            {
+                conditions.push("m.timestamp < ?".to_string());
+                params.push(midnight(d));
+            }
+            Term::Unread => conditions.push(format!("NOT {}", has("\\Seen"))),
+            Term::Read => conditions.push(has("\\Seen")),
+            Term::Flagged => conditions.push(has("\\Flagged")),
+            Term::Larger(n) =>
+            {
+                conditions.push("m.size > ?".to_string());
+                params.push(Value::Integer(i64::from(*n)));
+            }
+            _ => {}
+        }
+    }
+    (conditions, params)
+}
@@ -0,0 +1,7 @@
+pub mod index;
+pub mod store;
+pub mod sync;
+
+pub use index::{snippet_parts, Hit, MATCH_END, MATCH_START};
+pub use store::Cache;
+pub use sync::{sync_mailbox, SyncReport};
@@ -0,0 +1,445 @@
+use crate::cache::index::{filters, match_expression, Hit};
+use crate::config::data_dir;
+use crate::imap::models::{FlagUpdate, MailSummary, MailboxStatus, ThreadUpdate};
+use crate::imap::parser::{render_body, Headers};
+use crate::imap::search::SearchQuery;
+
+use chrono::DateTime;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Bumped whenever `SCHEMA`, or how headers are read into it, changes. A
+/// cache written with another version is dropped and rebuilt by the next
+/// sync rather than migrated.
+const VERSION: i64 = 6;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS mailboxes (
+        name           TEXT PRIMARY KEY,
+        uid_validity   INTEGER NOT NULL,
+        uid_next       INTEGER NOT NULL,
+        messages       INTEGER NOT NULL,
+        highest_modseq INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS messages (
+        mailbox      TEXT NOT NULL,
+        uid_validity INTEGER NOT NULL,
+        uid          INTEGER NOT NULL,
+        sender       TEXT NOT NULL,
+        sender_name  TEXT,
+        subject      TEXT NOT NULL,
+        date         TEXT NOT NULL,
+        timestamp    INTEGER NOT NULL,
+        size         INTEGER NOT NULL,
+        attachments  INTEGER NOT NULL,
+        flags        TEXT NOT NULL,
+        raw          BLOB,
+        recipients   TEXT,
+        body         TEXT,
+        message_id   TEXT,
+        refs         TEXT NOT NULL DEFAULT '',
+        rcpts        TEXT NOT NULL DEFAULT '',
+        thread_id    INTEGER,
+        PRIMARY KEY (mailbox, uid_validity, uid)
+    );
+    CREATE INDEX IF NOT EXISTS messages_by_date ON messages (mailbox, timestamp);
+
+    -- Full-text index over the text columns of `messages`, which the
+    -- triggers keep in step. `recipients` and `body` are filled in by
+    -- sync after the summary; a NULL `body` means not indexed yet.
+    CREATE VIRTUAL TABLE IF NOT EXISTS search USING fts5 (
+        sender, sender_name, subject, recipients, body,
+        content = 'messages', tokenize = 'unicode61 remove_diacritics 2'
+    );
+    CREATE TRIGGER IF NOT EXISTS messages_indexed AFTER INSERT ON messages BEGIN
+        INSERT INTO search (rowid, sender, sender_name, subject, recipients, body)
+        VALUES (new.rowid, new.sender, new.sender_name, new.subject, new.recipients, new.body);
+    END;
+    CREATE TRIGGER IF NOT EXISTS messages_unindexed AFTER DELETE ON messages BEGIN
+        INSERT INTO search (search, rowid, sender, sender_name, subject, recipients, body)
+        VALUES ('delete', old.rowid, old.sender, old.sender_name, old.subject, old.recipients, old.body);
+    END;
+    CREATE TRIGGER IF NOT EXISTS messages_reindexed
+    AFTER UPDATE OF sender, sender_name, subject, recipients, body ON messages BEGIN
+        INSERT INTO search (search, rowid, sender, sender_name, subject, recipients, body)
+        VALUES ('delete', old.rowid, old.sender, old.sender_name, old.subject, old.recipients, old.body);
+        INSERT INTO search (rowid, sender, sender_name, subject, recipients, body)
+        VALUES (new.rowid, new.sender, new.sender_name, new.subject, new.recipients, new.body);
+    END;
+    CREATE TABLE IF NOT EXISTS folders (
+        position INTEGER PRIMARY KEY,
+        name     TEXT NOT NULL
+    );
+";
+
+/// Per-account SQLite cache of message summaries, flags and downloaded
+/// messages, keyed by mailbox, UIDVALIDITY and UID, with a full-text index
+/// (SQLite FTS5) of their headers and the bodies that have been downloaded.
+///
+/// Each thread opens its own `Cache` on the same file; WAL mode lets the UI
+/// read while the sync thread writes.
+pub struct Cache
+{
+    conn: Connection,
+}
+
+impl Cache
+{
+    /// `<data_dir>/cache/<account>.sqlite`
+    pub fn default_path(account: &str) -> Option<PathBuf>
+    {
+        Some(data_dir()?.join("cache").join(format!("{}.sqlite", account)))
+    }
+
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>>
+    {
+        if let Some(dir) = path.parent()
+        {
+            fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version != VERSION
+        {
+            conn.execute_batch(
+                "DROP TABLE IF EXISTS search; DROP TABLE IF EXISTS messages;
+                 DROP TABLE IF EXISTS mailboxes; DROP TABLE IF EXISTS folders;",
+            )?;
+        }
+        conn.execute_batch(SCHEMA)?;
+        conn.pragma_update(None, "user_version", VERSION)?;
+        Ok(Self { conn })
+    }
+
+    /// State of `mailbox` as of the last sync.
+    pub fn mailbox_status(&self, mailbox: &str) -> rusqlite::Result<Option<MailboxStatus>>
+    {
+        self.conn
+            .query_row(
+                "SELECT uid_validity, uid_next, messages, highest_modseq FROM mailboxes WHERE name = ?1",
+                [mailbox],
+                |row| 
+                {
+                    Ok(MailboxStatus
+                    {
+                        uid_validity: row.get(0)?,
+                        uid_next: row.get(1)?,
+                        messages: row.get(2)?,
+                        highest_modseq: row.get::<_, Option<i64>>(3)?.map(|n| n as u64),
+                    })
+                },
+            )
+            .optional()
+    }
+
+    pub fn set_mailbox_status(&self, mailbox: &str, status: &MailboxStatus) -> rusqlite::Result<()>
+    {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO mailboxes (name, uid_validity, uid_next, messages, highest_modseq)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                mailbox,
+                status.uid_validity,
+                status.uid_next,
+                status.messages,
+                status.highest_modseq.map(|n| n as i64)
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets everything cached for `mailbox`, e.g. after UIDVALIDITY changed.
+    pub fn reset_mailbox(&self, mailbox: &str) -> rusqlite::Result<()>
+    {
+        self.conn.execute("DELETE FROM messages WHERE mailbox = ?1", [mailbox])?;
+        self.conn.execute("DELETE FROM mailboxes WHERE name = ?1", [mailbox])?;
+        Ok(())
+    }
+
+    /// Inserts or updates summaries; already downloaded messages are kept.
+    pub fn store_summaries(&self, mailbox: &str, uid_validity: u32, summaries: &[MailSummary]) -> rusqlite::Result<()>
+    {
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO messages (mailbox, uid_validity, uid, sender, sender_name, subject, date, timestamp,
+                                       size, attachments, flags, message_id, refs, rcpts)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+                 ON CONFLICT (mailbox, uid_validity, uid) DO UPDATE SET
+                     sender = excluded.sender, sender_name = excluded.sender_name,
+                     subject = excluded.subject, date = excluded.date,
+                     timestamp = excluded.timestamp, size = excluded.size,
+                     attachments = excluded.attachments, flags = excluded.flags,
+                     message_id = excluded.message_id, refs = excluded.refs, rcpts = excluded.rcpts",
+            )?;
+            for m in summaries
+            {
+                stmt.execute(params![
+                    mailbox,
+                    uid_validity,
+                    m.uid,
+                    m.from,
+                    m.from_name,
+                    m.subject,
+                    m.date.to_rfc3339(),
+                    m.date.timestamp(),
+                    m.size,
+                    m.has_attachments,
+                    m.flags.join(" "),
+                    m.message_id,
+                    m.references.join(" "),
+                    m.recipients.join(" ")
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Applies `(uid, flags)` pairs and returns how many rows actually changed.
+    pub fn update_flags(&self, mailbox: &str, changes: &[FlagUpdate]) -> rusqlite::Result<usize>
+    {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut changed = 0;
+        {
+            let mut stmt = tx.prepare_cached(
+                "UPDATE messages SET flags = ?3 WHERE mailbox = ?1 AND uid = ?2 AND flags <> ?3",
+            )?;
+            for (uid, flags) in changes
+            {
+                changed += stmt.execute(params![mailbox, uid, flags.join(" ")])?;
+            }
+        }
+        tx.commit()?;
+        Ok(changed)
+    }
+
+    /// Records the server's conversation of each `(uid, thread id)`.
+    pub fn set_thread_ids(&self, mailbox: &str, updates: &[ThreadUpdate]) -> rusqlite::Result<()>
+    {
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare_cached("UPDATE messages SET thread_id = ?3 WHERE mailbox = ?1 AND uid = ?2")?;
+            for (uid, thread) in updates
+            {
+                stmt.execute(params![mailbox, uid, *thread as i64])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Drops cached messages whose UID is not in `live`; returns how many.
+    pub fn retain_uids(&self, mailbox: &str, live: &HashSet<u32>) -> rusqlite::Result<usize>
+    {
+        let gone: Vec<u32> = self.uids(mailbox)?.into_iter().filter(|uid| !live.contains(uid)).collect();
+        self.remove(mailbox, &gone)?;
+        Ok(gone.len())
+    }
+
+    pub fn remove(&self, mailbox: &str, uids: &[u32]) -> rusqlite::Result<()>
+    {
+        let tx = self.conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare_cached("DELETE FROM messages WHERE mailbox = ?1 AND uid = ?2")?;
+            for uid in uids
+            {
+                stmt.execute(params![mailbox, uid])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Cached UIDs of `mailbox`, ascending.
+    pub fn uids(&self, mailbox: &str) -> rusqlite::Result<Vec<u32>>
+    {
+        let mut stmt = self.conn.prepare_cached("SELECT uid FROM messages WHERE mailbox = ?1 ORDER BY uid")?;
+        let rows = stmt.query_map([mailbox], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    pub fn count(&self, mailbox: &str) -> rusqlite::Result<usize>
+    {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM messages WHERE mailbox = ?1", [mailbox], |row| row.get::<_, i64>(0))
+            .map(|n| n as usize)
+    }
+
+    /// The newest `count` cached messages of `mailbox`, newest first.
+    pub fn summaries(&self, mailbox: &str, count: usize) -> rusqlite::Result<Vec<MailSummary>>
+    {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT uid, sender, sender_name, subject, date, size, attachments, flags, message_id, refs, rcpts,
+                    thread_id
+             FROM messages WHERE mailbox = ?1 ORDER BY timestamp DESC, uid DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![mailbox, count as i64], summary)?;
+        rows.collect()
+    }
+
+    /// The best `limit` cached matches of `query` in any mailbox, best
+    /// first, each with a snippet of the text that matched. Queries with
+    /// only dates, status or size are answered newest first, without
+    /// snippets.
+    pub fn search(&self, query: &SearchQuery, limit: usize) -> rusqlite::Result<Vec<Hit>>
+    {
+        let (mut conditions, mut params) = filters(query);
+        let columns = "m.uid, m.sender, m.sender_name, m.subject, m.date, m.size, m.attachments, m.flags, \
+                       m.message_id, m.refs, m.rcpts, m.thread_id, m.mailbox";
+        let sql = match match_expression(query)
+        {
+            Some(expression) =>
+            {
+                conditions.insert(0, "search MATCH ?".to_string());
+                params.insert(0, expression.into());
+                // Sender and subject words weigh more than the same word in the body
+                format!(
+                    "SELECT {}, snippet(search, -1, char(2), char(3), '…', 12)
+                     FROM search JOIN messages m ON m.rowid = search.rowid
+                     WHERE {} ORDER BY bm25(search, 4.0, 4.0, 8.0, 2.0, 1.0) LIMIT ?",
+                    columns,
+                    conditions.join(" AND ")
+                )
+            }
+            None if conditions.is_empty() => return Ok(Vec::new()),
+            None => format!(
+                "SELECT {}, NULL FROM messages m WHERE {} ORDER BY m.timestamp DESC, m.uid DESC LIMIT ?",
+                columns,
+                conditions.join(" AND ")
+            ),
+        };
+        params.push((limit as i64).into());
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(params), |row|
+        {
+            // One line for the message list
+            let snippet = row.get::<_, Option<String>>(13)?.map(|s| s.split_whitespace().collect::<Vec<_>>().join(" "));
+            Ok(Hit { summary: summary(row)?, folder: row.get(12)?, snippet })
+        })?;
+        rows.collect()
+    }
+
+    /// Cached flags of one message, `None` if it is not cached.
+    pub fn flags(&self, mailbox: &str, uid: u32) -> rusqlite::Result<Option<Vec<String>>>
+    {
+        self.conn
+            .query_row(
+                "SELECT flags FROM messages WHERE mailbox = ?1 AND uid = ?2",
+                params![mailbox, uid],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map(|flags| flags.map(|f| f.split_whitespace().map(String::from).collect()))
+    }
+
+    /// The full RFC 822 message, if it has been downloaded before.
+    pub fn raw(&self, mailbox: &str, uid: u32) -> rusqlite::Result<Option<Vec<u8>>>
+    {
+        self.conn
+            .query_row(
+                "SELECT raw FROM messages WHERE mailbox = ?1 AND uid = ?2",
+                params![mailbox, uid],
+                |row| row.get(0),
+            )
+            .optional()
+            .map(Option::flatten)
+    }
+
+    /// Keeps a downloaded message, and indexes its recipients and readable
+    /// body for `search`.
+    pub fn store_raw(&self, mailbox: &str, uid: u32, raw: &[u8]) -> rusqlite::Result<()>
+    {
+        let (recipients, body) = indexed_text(raw);
+        self.conn.execute(
+            "UPDATE messages SET raw = ?3, recipients = ?4, body = ?5 WHERE mailbox = ?1 AND uid = ?2",
+            params![mailbox, uid, raw, recipients, body],
+        )?;
+        Ok(())
+    }
+
+    /// Indexes recipients and readable body from the start of a message
+    /// (see `ImapClient::fetch_prefixes`). Unlike `store_raw` the message
+    /// is not kept, as it may be cut short; opening it still downloads it.
+    pub fn index_prefix(&self, mailbox: &str, uid: u32, prefix: &[u8]) -> rusqlite::Result<()>
+    {
+        let (recipients, body) = indexed_text(prefix);
+        self.conn.execute(
+            "UPDATE messages SET recipients = ?3, body = ?4 WHERE mailbox = ?1 AND uid = ?2",
+            params![mailbox, uid, recipients, body],
+        )?;
+        Ok(())
+    }
+
+    /// UIDs of cached messages whose body is not indexed yet, lowest first.
+    pub fn unindexed(&self, mailbox: &str) -> rusqlite::Result<Vec<u32>>
+    {
+        let mut stmt = self.conn.prepare_cached("SELECT uid FROM messages WHERE mailbox = ?1 AND body IS NULL ORDER BY uid")?;
+        let rows = stmt.query_map([mailbox], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    /// Folder names in sidebar order, as of the last sync.
+    pub fn folders(&self) -> rusqlite::Result<Vec<String>>
+    {
+        let mut stmt = self.conn.prepare_cached("SELECT name FROM folders ORDER BY position")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect()
+    }
+
+    pub fn set_folders(&self, names: &[String]) -> rusqlite::Result<()>
+    {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM folders", [])?;
+        {
+            let mut stmt = tx.prepare_cached("INSERT INTO folders (position, name) VALUES (?1, ?2)")?;
+            for (i, name) in names.iter().enumerate()
+            {
+                stmt.execute(params![i as i64, name])?;
+            }
+        }
+        tx.commit()
+    }
+}
+
+/// The `recipients` and `body` columns for a message.
+fn indexed_text(raw: &[u8]) -> (String, String)
+{
+    let headers = Headers::parse(raw).unwrap_or_default();
+    let recipients: Vec<String> =
+        ["To", "Cc"].iter().flat_map(|name| headers.addresses(name)).map(|a| a.to_string()).collect();
+    // A message that does not decode is still indexed, just not its body
+    // text, so sync does not ask for it again.
+    (recipients.join(", "), render_body(raw).unwrap_or_default())
+}
+
+/// A `MailSummary` from the first twelve columns of a `messages` row: uid,
+/// sender, sender_name, subject, date, size, attachments, flags,
+/// message_id, refs, rcpts and thread_id.
+fn summary(row: &Row) -> rusqlite::Result<MailSummary>
+{
+    let date: String = row.get(4)?;
+    let flags: String = row.get(7)?;
+    let refs: String = row.get(9)?;
+    let rcpts: String = row.get(10)?;
+    Ok(MailSummary
+    {
+        uid: row.get(0)?,
+        from: row.get(1)?,
+        from_name: row.get(2)?,
+        subject: row.get(3)?,
+        date: DateTime::parse_from_rfc3339(&date).map_err(|e| 
+        {
+            rusqlite::Error::FromSqlConversionFailure(4, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        size: row.get(5)?,
+        has_attachments: row.get(6)?,
+        flags: flags.split_whitespace().map(String::from).collect(),
+        message_id: row.get(8)?,
+        references: refs.split_whitespace().map(String::from).collect(),
+        recipients: rcpts.split_whitespace().map(String::from).collect(),
+        thread_id: row.get::<_, Option<i64>>(11)?.map(|n| n as u64),
+    })
+}
@@ -0,0 +1,153 @@
+use crate::cache::store::Cache;
+use crate::imap::models::MailboxStatus;
+use crate::imap::ImapClient;
+
+use std::collections::HashSet;
+use std::error::Error;
+
+/// How much of each message sync downloads to index its body.
+const INDEX_BYTES: usize = 64 * 1024;
+
+/// What a sync changed in the cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SyncReport
+{
+    /// Summaries downloaded (new arrivals plus older messages to fill the window).
+    pub fetched: usize,
+    pub flags_changed: usize,
+    pub expunged: usize,
+}
+
+/// Brings the cached copy of `mailbox` up to date and makes sure at least
+/// its newest `count` messages are cached.
+///
+/// The cache always holds the newest messages of a mailbox, so a sync only
+/// needs to:
+///
+/// 1. fetch UIDs from the cached UIDNEXT on (new mail),
+/// 2. refresh flags of cached messages, limited to those changed since the
+///    cached HIGHESTMODSEQ when the server supports CONDSTORE,
+/// 3. look for expunged messages, but only if EXISTS does not add up,
+/// 4. if fewer than `count` are cached, find the UIDs of the newest `count`
+///    with UID SEARCH and fetch those not cached,
+/// 5. ask for the server's threads (see `ImapClient::thread_ids`) if any
+///    of that changed which messages are cached,
+/// 6. index the bodies of cached messages that are not indexed yet, from
+///    their first `INDEX_BYTES`.
+///
+/// With CONDSTORE an unchanged mailbox costs a single STATUS. QRESYNC's
+/// VANISHED responses are not understood by the imap crate, so expunges are
+/// always found with UID SEARCH.
+pub fn sync_mailbox(imap: &mut ImapClient, cache: &Cache, mailbox: &str, count: usize) -> Result<SyncReport, Box<dyn Error>>
+{
+    let condstore = imap.has_capability("CONDSTORE")?;
+    let cached = cache.mailbox_status(mailbox)?;
+    let have = cache.count(mailbox)?;
+
+    let mut highest_modseq = None;
+    if condstore
+    {
+        let status = imap.status(mailbox, true)?;
+        let enough = have >= count || have >= status.messages as usize;
+        if cached == Some(status) && enough
+        {
+            return Ok(SyncReport::default());
+        }
+        highest_modseq = status.highest_modseq;
+    }
+
+    let selected = imap.select(mailbox)?;
+    let mut server = MailboxStatus
+    {
+        uid_validity: selected.uid_validity.unwrap_or(0),
+        uid_next: selected.uid_next.unwrap_or(0),
+        messages: selected.exists,
+        highest_modseq,
+    };
+
+    let mut report = SyncReport::default();
+    let lowest = cache.uids(mailbox)?.first().copied();
+    match (cached, lowest)
+    {
+        (Some(state), Some(lowest)) if state.uid_validity == server.uid_validity =>
+        {
+            // 1. New mail. `n:*` always matches the last message, even below n.
+            let mut new = Vec::new();
+            if server.uid_next == 0 || server.uid_next > state.uid_next
+            {
+                new = imap.fetch_summaries(&format!("{}:*", state.uid_next))?;
+                new.retain(|m| m.uid >= state.uid_next);
+                cache.store_summaries(mailbox, server.uid_validity, &new)?;
+                report.fetched += new.len();
+            }
+
+            // 2. Flag changes on what was already cached.
+            if state.uid_next > lowest
+            {
+                let since = if condstore { state.highest_modseq } else { None };
+                let changes = imap.fetch_flags(&format!("{}:{}", lowest, state.uid_next - 1), since)?;
+                report.flags_changed = cache.update_flags(mailbox, &changes)?;
+            }
+
+            // 3. Every message now in the mailbox was either counted last
+            // time or is one of `new`, so the numbers only differ if
+            // something was expunged.
+            if state.messages as usize + new.len() != server.messages as usize
+            {
+                let live = imap.search_uids(&format!("{}:*", lowest))?;
+                report.expunged = cache.retain_uids(mailbox, &live)?;
+            }
+        }
+        (Some(_), _) => cache.reset_mailbox(mailbox)?,
+        (None, _) => {}
+    }
+
+    // 4. Fill the window with older messages. The window is counted in
+    // sequence numbers, but what is missing from it is worked out by UID:
+    // the cached messages need not be one run at the top of the mailbox.
+    let have = cache.count(mailbox)?;
+    let want = count.min(server.messages as usize);
+    if have < want
+    {
+        let first = server.messages - want as u32 + 1;
+        let cached: HashSet<u32> = cache.uids(mailbox)?.into_iter().collect();
+        let mut missing: Vec<u32> = imap.uids_at(&format!("{}:*", first))?.into_iter().filter(|uid| !cached.contains(uid)).collect();
+        if !missing.is_empty()
+        {
+            missing.sort_unstable();
+            let older = imap.fetch_summaries(&missing.iter().map(u32::to_string).collect::<Vec<_>>().join(","))?;
+            cache.store_summaries(mailbox, server.uid_validity, &older)?;
+            report.fetched += older.len();
+        }
+    }
+
+    // 5. THREAD names a conversation by its lowest UID among the messages
+    // asked about, so the whole window is asked again, not just new mail.
+    if report.fetched > 0 || report.expunged > 0
+    {
+        if let Some(lowest) = cache.uids(mailbox)?.first()
+        {
+            let threads = imap.thread_ids(&format!("{}:*", lowest))?;
+            cache.set_thread_ids(mailbox, &threads)?;
+        }
+    }
+
+    // 6. Bodies, so `Cache::search` finds words in messages nobody has
+    // opened yet.
+    let unindexed = cache.unindexed(mailbox)?;
+    if !unindexed.is_empty()
+    {
+        let uid_set = unindexed.iter().map(u32::to_string).collect::<Vec<_>>().join(",");
+        for (uid, prefix) in imap.fetch_prefixes(&uid_set, INDEX_BYTES)?
+        {
+            cache.index_prefix(mailbox, uid, &prefix)?;
+        }
+    }
+
+    if server.uid_next == 0
+    {
+        server.uid_next = cache.uids(mailbox)?.last().map_or(1, |uid| uid + 1);
+    }
+    cache.set_mailbox_status(mailbox, &server)?;
+    Ok(report)
+}
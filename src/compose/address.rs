@@ -0,0 +1,48 @@
+use crate::smtp::SmtpError;
+
+use lettre::message::Mailbox;
+
+/// The entries of a recipient field as typed: split at commas (or
+/// semicolons, as Outlook writes them) that are not inside a quoted name,
+/// `<…>` or a `(comment)`. Entries are trimmed; empty ones are dropped.
+pub fn split_addresses(field: &str) -> Vec<&str>
+{
+    let mut entries = Vec::new();
+    let (mut quoted, mut escaped, mut angle, mut comment) = (false, false, false, 0usize);
+    let mut start = 0;
+    for (i, c) in field.char_indices()
+    {
+        if escaped
+        {
+            escaped = false;
+            continue;
+        }
+        match c
+        {
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '<' if !quoted => angle = true,
+            '>' if !quoted => angle = false,
+            '(' if !quoted => comment += 1,
+            ')' if !quoted => comment = comment.saturating_sub(1),
+            ',' | ';' if !quoted && !angle && comment == 0 =>
+            {
+                entries.push(&field[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    entries.push(&field[start..]);
+    entries.into_iter().map(str::trim).filter(|e| !e.is_empty()).collect()
+}
+
+/// Every address of a recipient field (`Name <a@x>, b@y`), or an error
+/// naming the first entry that is not one.
+pub fn mailboxes(field: &str) -> Result<Vec<Mailbox>, SmtpError>
+{
+    split_addresses(field)
+        .into_iter()
+        .map(|entry| entry.parse().map_err(|e| SmtpError::address(entry, e)))
+        .collect()
+}
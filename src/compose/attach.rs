@@ -0,0 +1,182 @@
+use crate::config::expand_home;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Above this many bytes of attachments a draft gets a warning: base64 makes
+/// them a third bigger on the wire, and many servers stop at 20–25 MB.
+pub const LARGE_ATTACHMENTS: u64 = 10 * 1024 * 1024;
+
+/// A file attached to a draft. It is read when the message is sent, so
+/// changes made in the meantime go out too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment
+{
+    pub path: PathBuf,
+    /// Size when it was attached.
+    pub size: u64,
+}
+
+impl Attachment
+{
+    /// The file at `path` (`~/` is expanded), which must exist and not be
+    /// a directory.
+    pub fn new(path: &str) -> io::Result<Self>
+    {
+        let path = expand_home(path.trim());
+        let meta = fs::metadata(&path)?;
+        if meta.is_dir()
+        {
+            return Err(io::Error::other(format!("{} is a directory", path.display())));
+        }
+        Ok(Self { path, size: meta.len() })
+    }
+
+    /// The name the recipient sees.
+    pub fn name(&self) -> String
+    {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "attachment".into())
+    }
+}
+
+/// What `total` bytes of attachments may run into, if anything.
+pub fn size_warning(total: u64) -> Option<String>
+{
+    (total > LARGE_ATTACHMENTS)
+        .then(|| format!("{:.1} MB of attachments: many servers refuse mail this large", total as f64 / 1_048_576.0))
+}
+
+/// The result of completing a path typed in the file picker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion
+{
+    /// The input, extended as far as all candidates agree.
+    pub line: String,
+    /// Entries of the directory that start with what was typed, sorted;
+    /// directories end in `/`.
+    pub candidates: Vec<String>,
+}
+
+/// Tab in the file picker: completes the last component of `input` from
+/// the entries of its directory (the current directory for a bare name).
+/// A single match is completed in full, a directory with its `/`. Hidden
+/// files are only offered once a `.` has been typed.
+pub fn complete_path(input: &str) -> Completion
+{
+    let (dir, prefix) = match input.rfind('/')
+    {
+        Some(slash) => input.split_at(slash + 1),
+        None => ("", input),
+    };
+    let listing = if dir.is_empty() { PathBuf::from(".") } else { expand_home(dir) };
+    let mut candidates: Vec<String> = fs::read_dir(&listing)
+        .map(|entries| entries.filter_map(Result::ok).filter_map(|entry| candidate(&entry, prefix)).collect())
+        .unwrap_or_default();
+    candidates.sort();
+
+    let first = candidates.first().map_or(prefix, String::as_str);
+    let common = candidates.iter().skip(1).fold(first, |common, name|
+    {
+        let len = common.chars().zip(name.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+        &common[..len]
+    });
+    Completion { line: format!("{}{}", dir, common), candidates }
+}
+
+/// `entry` as offered for `prefix`, if it is.
+fn candidate(entry: &fs::DirEntry, prefix: &str) -> Option<String>
+{
+    let name = entry.file_name().into_string().ok()?;
+    if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.'))
+    {
+        return None;
+    }
+    // Follows symlinks, so a link to a directory counts as one
+    if entry.path().is_dir()
+    {
+        Some(format!("{}/", name))
+    }
+    else
+    {
+        Some(name)
+    }
+}
+
+/// The MIME type of an attachment: from its extension, else from the
+/// first bytes of `data`, else `text/plain` for UTF-8 text and
+/// `application/octet-stream` for anything else.
+pub fn mime_type(path: &Path, data: &[u8]) -> &'static str
+{
+    let ext = path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+    let known = match ext.as_deref()
+    {
+        Some("txt" | "text" | "log") => Some("text/plain"),
+        Some("md" | "markdown") => Some("text/markdown"),
+        Some("csv") => Some("text/csv"),
+        Some("htm" | "html") => Some("text/html"),
+        Some("ics") => Some("text/calendar"),
+        Some("vcf") => Some("text/vcard"),
+        Some("eml") => Some("message/rfc822"),
+        Some("pdf") => Some("application/pdf"),
+        Some("json") => Some("application/json"),
+        Some("xml") => Some("application/xml"),
+        Some("zip") => Some("application/zip"),
+        Some("gz" | "tgz") => Some("application/gzip"),
+        Some("tar") => Some("application/x-tar"),
+        Some("7z") => Some("application/x-7z-compressed"),
+        Some("doc") => Some("application/msword"),
+        Some("docx") => Some("application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+        Some("xls") => Some("application/vnd.ms-excel"),
+        Some("xlsx") => Some("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+        Some("ppt") => Some("application/vnd.ms-powerpoint"),
+        Some("pptx") => Some("application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+        Some("odt") => Some("application/vnd.oasis.opendocument.text"),
+        Some("ods") => Some("application/vnd.oasis.opendocument.spreadsheet"),
+        Some("png") => Some("image/png"),
+        Some("jpg" | "jpeg") => Some("image/jpeg"),
+        Some("gif") => Some("image/gif"),
+        Some("webp") => Some("image/webp"),
+        Some("svg") => Some("image/svg+xml"),
+        Some("mp3") => Some("audio/mpeg"),
+        Some("ogg") => Some("audio/ogg"),
+        Some("wav") => Some("audio/wav"),
+        Some("mp4") => Some("video/mp4"),
+        Some("webm") => Some("video/webm"),
+        _ => None,
+    };
+    if let Some(known) = known
+    {
+        return known;
+    }
+    const MAGIC: [(&[u8], &str); 6] = [
+        (b"%PDF-", "application/pdf"),
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF8", "image/gif"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+    ];
+    if let Some((_, mime)) = MAGIC.iter().find(|(magic, _)| data.starts_with(magic))
+    {
+        return mime;
+    }
+    // A multi-byte character cut off at the end of the sample is still text
+    let sample = &data[..data.len().min(8192)];
+    let text = match std::str::from_utf8(sample)
+    {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    if text && !sample.contains(&0)
+    {
+        "text/plain"
+    }
+    else
+    {
+        "application/octet-stream"
+    }
+}
@@ -0,0 +1,68 @@
+use crate::compose::address::mailboxes;
+use crate::smtp::{with_attachments, with_forwarded, SmtpError};
+
+use lettre::message::Mailbox;
+use lettre::Message;
+use std::path::PathBuf;
+
+/// A message as written in Compose, recipients as typed: comma separated,
+/// with or without display names.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Draft
+{
+    pub to: String,
+    pub cc: String,
+    /// Sent to, but left out of the headers everyone gets.
+    pub bcc: String,
+    pub subject: String,
+    pub body: String,
+    /// Read when the message is built.
+    pub attachments: Vec<PathBuf>,
+    /// `Message-ID:` of the message this answers, without angle brackets.
+    pub in_reply_to: Option<String>,
+    /// The conversation so far, oldest first, ending with `in_reply_to`.
+    pub references: Vec<String>,
+    /// A raw message forwarded as an attachment.
+    pub forwarded: Option<Vec<u8>>,
+}
+
+impl Draft
+{
+    /// The message to send from `from`. Every recipient field must hold
+    /// valid addresses, and there must be at least one recipient.
+    pub fn message(&self, from: Mailbox) -> Result<Message, SmtpError>
+    {
+        let (to, cc, bcc) = (mailboxes(&self.to)?, mailboxes(&self.cc)?, mailboxes(&self.bcc)?);
+        if to.is_empty() && cc.is_empty() && bcc.is_empty()
+        {
+            return Err(SmtpError::NoRecipients);
+        }
+        let mut builder = Message::builder().from(from).subject(self.subject.as_str());
+        if let Some(id) = &self.in_reply_to
+        {
+            builder = builder.in_reply_to(format!("<{}>", id));
+        }
+        if !self.references.is_empty()
+        {
+            builder = builder.references(self.references.iter().map(|id| format!("<{}>", id)).collect::<Vec<_>>().join(" "));
+        }
+        for mailbox in to
+        {
+            builder = builder.to(mailbox);
+        }
+        for mailbox in cc
+        {
+            builder = builder.cc(mailbox);
+        }
+        // lettre puts Bcc in the envelope only, never in the headers
+        for mailbox in bcc
+        {
+            builder = builder.bcc(mailbox);
+        }
+        match &self.forwarded
+        {
+            Some(raw) => with_forwarded(builder, self.body.clone(), &self.attachments, raw),
+            None => with_attachments(builder, self.body.clone(), &self.attachments),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::compose::draft::Draft;
+use crate::compose::error::EditorError;
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+/// The editor to run: `$VISUAL`, else `$EDITOR`, else `vi`. It may come
+/// with arguments, e.g. `code --wait`.
+pub fn editor_command() -> String
+{
+    ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|command| !command.trim().is_empty())
+        .unwrap_or_else(|| "vi".into())
+}
+
+/// The file handed to the editor: a `To:`, `Cc:`, `Bcc:` and `Subject:`
+/// line, an empty line, then the body.
+pub fn draft_text(draft: &Draft) -> String
+{
+    format!("To: {}\nCc: {}\nBcc: {}\nSubject: {}\n\n{}\n", draft.to, draft.cc, draft.bcc, draft.subject, draft.body)
+}
+
+/// Reads back a file written by `draft_text` after editing: the header
+/// block ends at the first empty line, its names are case-insensitive,
+/// lines starting with a space or tab continue the previous header and a
+/// missing header is empty. Everything after the empty line is the body.
+/// On error `draft` is unchanged.
+pub fn read_draft(text: &str, draft: &mut Draft) -> Result<(), EditorError>
+{
+    let text = text.replace("\r\n", "\n");
+    let (head, body) = match text.strip_prefix('\n')
+    {
+        Some(body) => ("", body),
+        None => text.split_once("\n\n").unwrap_or((&text, "")),
+    };
+    let mut fields: [String; 4] = Default::default();
+    let mut last: Option<usize> = None;
+    for line in head.lines()
+    {
+        if let (Some(k), true) = (last, line.starts_with([' ', '\t']))
+        {
+            fields[k].push(' ');
+            fields[k].push_str(line.trim());
+            continue;
+        }
+        let (name, value) = line.split_once(':').ok_or_else(|| EditorError::Header(line.to_string()))?;
+        let k = ["to", "cc", "bcc", "subject"]
+            .iter()
+            .position(|known| name.trim().eq_ignore_ascii_case(known))
+            .ok_or_else(|| EditorError::Header(line.to_string()))?;
+        fields[k] = value.trim().to_string();
+        last = Some(k);
+    }
+    let [to, cc, bcc, subject] = fields;
+    *draft = Draft { to, cc, bcc, subject, body: body.trim_end().to_string(), ..draft.clone() };
+    Ok(())
+}
+
+/// Lets the user edit `draft` in `editor` (see `editor_command`): writes
+/// it to a temporary file only the user can read, runs the editor on it
+/// through `sh` and waits, then reads the result back with `read_draft`.
+/// The file is removed either way. The caller has to hand over the
+/// terminal for the time being.
+pub fn edit_draft(draft: &mut Draft, editor: &str) -> Result<(), EditorError>
+{
+    let path = temp_file(&draft_text(draft))?;
+    let result = run_editor(editor, &path);
+    let text = result.and_then(|()| Ok(fs::read_to_string(&path)?));
+    let _ = fs::remove_file(&path);
+    read_draft(&text?, draft)
+}
+
+fn run_editor(editor: &str, path: &Path) -> Result<(), EditorError>
+{
+    // `sh` splits the command into words like the shell would
+    let status = Command::new("sh").arg("-c").arg(format!("{} \"$1\"", editor)).arg("sh").arg(path).status()?;
+    if status.success()
+    {
+        Ok(())
+    }
+    else
+    {
+        Err(EditorError::Failed(status))
+    }
+}
+
+/// A new file in the temporary directory holding `text`. The `.eml`
+/// extension makes editors highlight it as mail.
+fn temp_file(text: &str) -> io::Result<PathBuf>
+{
+    for n in 0..
+    {
+        let path = env::temp_dir().join(format!("bps_mail-{}-{}.eml", process::id(), n));
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        match options.open(&path)
+        {
+            Ok(mut file) =>
+            {
+                file.write_all(text.as_bytes())?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("ran out of file names")
+}
@@ -0,0 +1,52 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::process::ExitStatus;
+
+/// Why a draft could not be edited in an external editor. The draft is
+/// left as it was in every case.
+#[derive(Debug)]
+pub enum EditorError
+{
+    /// The temporary file could not be written or read back, or the
+    /// editor could not be started.
+    Io(io::Error),
+    /// The editor exited with an error, e.g. after `:cq` in vim.
+    Failed(ExitStatus),
+    /// A line of the header block that is not `To:`, `Cc:`, `Bcc:` or
+    /// `Subject:`.
+    Header(String),
+}
+
+impl fmt::Display for EditorError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            EditorError::Io(e) => write!(f, "editor: {}", e),
+            EditorError::Failed(status) => write!(f, "the editor exited with {}; the draft is unchanged", status),
+            EditorError::Header(line) => write!(f, "not a header: '{}' (To, Cc, Bcc and Subject come before the empty line)", line),
+        }
+    }
+}
+
+impl Error for EditorError
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)>
+    {
+        match self
+        {
+            EditorError::Io(e) => Some(e),
+            EditorError::Failed(_) | EditorError::Header(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for EditorError
+{
+    fn from(e: io::Error) -> Self
+    {
+        EditorError::Io(e)
+    }
+}
@@ -0,0 +1,13 @@
+pub mod address;
+pub mod attach;
+pub mod draft;
+pub mod editor;
+pub mod error;
+pub mod reply;
+
+pub use address::{mailboxes, split_addresses};
+pub use attach::{complete_path, mime_type, size_warning, Attachment, Completion, LARGE_ATTACHMENTS};
+pub use draft::Draft;
+pub use editor::{draft_text, edit_draft, editor_command, read_draft};
+pub use error::EditorError;
+pub use reply::{attribution, forward, quote, reply, Response};
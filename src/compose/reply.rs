@@ -0,0 +1,150 @@
+use crate::compose::draft::Draft;
+use crate::imap::{Address, MailDetail};
+
+/// A draft made from a message that has been received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response
+{
+    /// To the sender, or wherever its `Reply-To:` points.
+    Reply,
+    /// To the sender and everyone else the message went to, except us.
+    ReplyAll,
+    /// The message quoted in the body, to be sent on to someone new.
+    Forward,
+    /// The whole message attached as it was received.
+    ForwardAttached,
+}
+
+/// A reply to `original`, with its body quoted below an attribution line
+/// and the threading headers set so other clients file it in the same
+/// conversation.
+///
+/// Replies go to `Reply-To:` if there is one, else to the sender; a reply
+/// to our own message goes to its recipients again. A reply to all goes to
+/// `Mail-Followup-To:` if there is one, else to those and everyone in `To:`
+/// and `Cc:` as well. `me` are our own addresses, which are left out, as
+/// is every address after its first appearance.
+pub fn reply(original: &MailDetail, all: bool, me: &[String]) -> Draft
+{
+    let mine = |a: &Address| me.iter().any(|m| m.eq_ignore_ascii_case(&a.address));
+    let (to, cc): (Vec<&Address>, Vec<&Address>) = if all && !original.followup_to.is_empty()
+    {
+        (original.followup_to.iter().collect(), Vec::new())
+    }
+    else
+    {
+        let sender = if original.reply_to.is_empty() { &original.from } else { &original.reply_to };
+        let to = if sender.iter().all(mine) { &original.to } else { sender };
+        let cc = if all { original.to.iter().chain(&original.cc).collect() } else { Vec::new() };
+        (to.iter().collect(), cc)
+    };
+
+    let mut seen: Vec<String> = Vec::new();
+    let mut list = |addresses: Vec<&Address>|
+    {
+        let mut kept = Vec::new();
+        for address in addresses
+        {
+            let lower = address.address.to_lowercase();
+            if !mine(address) && !seen.contains(&lower)
+            {
+                seen.push(lower);
+                kept.push(address.to_string());
+            }
+        }
+        kept.join(", ")
+    };
+    let (to, cc) = (list(to), list(cc));
+
+    // References: the original's, or for lack of them what it answered,
+    // then the original itself
+    let mut references = if original.references.is_empty() { original.in_reply_to.clone() } else { original.references.clone() };
+    references.extend(original.message_id.clone());
+
+    Draft
+    {
+        to,
+        cc,
+        subject: prefixed("Re:", &["re:"], &original.subject),
+        body: format!("{}\n{}\n\n", attribution(original), quote(&original.body)),
+        in_reply_to: original.message_id.clone(),
+        references,
+        ..Draft::default()
+    }
+}
+
+/// `original` to be forwarded: with its headers and body below a
+/// separator line, or with `raw`, the message as received, attached.
+pub fn forward(original: &MailDetail, raw: Option<Vec<u8>>) -> Draft
+{
+    let subject = prefixed("Fwd:", &["fwd:", "fw:"], &original.subject);
+    if raw.is_some()
+    {
+        return Draft { subject, forwarded: raw, ..Draft::default() };
+    }
+    let mut body = String::from("\n\n---------- Forwarded message ----------\n");
+    for (name, addresses) in [("From", &original.from), ("To", &original.to), ("Cc", &original.cc)]
+    {
+        if !addresses.is_empty()
+        {
+            let list: Vec<String> = addresses.iter().map(Address::to_string).collect();
+            body.push_str(&format!("{}: {}\n", name, list.join(", ")));
+        }
+        if name == "From"
+        {
+            if let Some(date) = original.date
+            {
+                body.push_str(&format!("Date: {}\n", date.to_rfc2822()));
+            }
+            body.push_str(&format!("Subject: {}\n", original.subject));
+        }
+    }
+    body.push('\n');
+    body.push_str(&original.body.lines().collect::<Vec<_>>().join("\n"));
+    Draft { subject, body, ..Draft::default() }
+}
+
+/// The line above a quoted message: `On Mon, 1 Jan 2024 at 10:00, Alice
+/// <alice@example.com> wrote:`.
+pub fn attribution(original: &MailDetail) -> String
+{
+    let sender = original.from.first().map_or_else(|| "Someone".to_string(), Address::to_string);
+    match original.date
+    {
+        Some(date) => format!("On {}, {} wrote:", date.format("%a, %-d %b %Y at %H:%M"), sender),
+        None => format!("{} wrote:", sender),
+    }
+}
+
+/// `body` with every line quoted by `> ` (`>` for lines already quoted and
+/// empty ones), leaving out the signature and trailing blank lines.
+pub fn quote(body: &str) -> String
+{
+    let lines: Vec<&str> = body.lines().take_while(|line| *line != "-- ").collect();
+    let end = lines.iter().rposition(|line| !line.trim().is_empty()).map_or(0, |last| last + 1);
+    lines[..end]
+        .iter()
+        .map(|line| match line.trim_end()
+        {
+            "" => ">".to_string(),
+            line if line.starts_with('>') => format!(">{}", line),
+            line => format!("> {}", line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `subject` with `prefix` in front, unless it starts with one of `seen`
+/// (lowercase) already.
+fn prefixed(prefix: &str, seen: &[&str], subject: &str) -> String
+{
+    let lower = subject.trim_start().to_lowercase();
+    if seen.iter().any(|s| lower.starts_with(s))
+    {
+        subject.to_string()
+    }
+    else
+    {
+        format!("{} {}", prefix, subject)
+    }
+}
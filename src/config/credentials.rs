@@ -0,0 +1,161 @@
+use crate::config::error::ConfigError;
+use crate::config::file::data_dir;
+use crate::config::secret::Secret;
+
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD as B64;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+const SALT_LEN: usize = 16;
+
+/// On-disk envelope of `credentials.enc`. The payload is a JSON map from
+/// account name to password, encrypted with ChaCha20-Poly1305 under a key
+/// derived from the master passphrase with Argon2id.
+#[derive(Serialize, Deserialize)]
+struct Envelope
+{
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// The decrypted credentials file, unlocked once at startup.
+pub struct CredentialStore
+{
+    path: PathBuf,
+    salt: [u8; SALT_LEN],
+    key: Zeroizing<[u8; 32]>,
+    entries: BTreeMap<String, Secret>,
+}
+
+impl CredentialStore
+{
+    /// `<data_dir>/credentials.enc`
+    pub fn default_path() -> Option<PathBuf>
+    {
+        Some(data_dir()?.join("credentials.enc"))
+    }
+
+    /// Decrypts the store at `path`, or starts an empty one if the file does
+    /// not exist yet (it is only written by `save`).
+    pub fn open(path: &Path, passphrase: &Secret) -> Result<Self, ConfigError>
+    {
+        let text = match fs::read_to_string(path)
+        {
+            Ok(t) => t,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound =>
+            {
+                let mut salt = [0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                return Ok(Self
+                {
+                    path: path.to_path_buf(),
+                    key: derive_key(passphrase, &salt)?,
+                    salt,
+                    entries: BTreeMap::new(),
+                });
+            }
+            Err(source) => return Err(ConfigError::Io { path: path.to_path_buf(), source }),
+        };
+
+        let corrupt = |what: &str| ConfigError::Secret(format!("{}: {}", path.display(), what));
+        let env: Envelope = serde_json::from_str(&text).map_err(|e| corrupt(&e.to_string()))?;
+        if env.version != 1
+        {
+            return Err(corrupt(&format!("unsupported version {}", env.version)));
+        }
+        let salt_bytes = B64.decode(&env.salt).map_err(|_| corrupt("bad salt"))?;
+        let nonce = B64.decode(&env.nonce).map_err(|_| corrupt("bad nonce"))?;
+        let ciphertext = B64.decode(&env.ciphertext).map_err(|_| corrupt("bad ciphertext"))?;
+        if salt_bytes.len() != SALT_LEN || nonce.len() != 12
+        {
+            return Err(corrupt("truncated header"));
+        }
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&salt_bytes);
+
+        let key = derive_key(passphrase, &salt)?;
+        let cipher = ChaCha20Poly1305::new(key.as_ref().into());
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+                .map_err(|_| ConfigError::Secret("wrong master passphrase or corrupted credentials file".into()))?,
+        );
+        let map: BTreeMap<String, String> =
+            serde_json::from_slice(&plaintext).map_err(|e| corrupt(&e.to_string()))?;
+
+        Ok(Self
+        {
+            path: path.to_path_buf(),
+            salt,
+            key,
+            entries: map.into_iter().map(|(k, v)| (k, Secret::new(v))).collect(),
+        })
+    }
+
+    pub fn get(&self, account: &str) -> Option<&Secret>
+    {
+        self.entries.get(account)
+    }
+
+    pub fn set(&mut self, account: &str, password: Secret)
+    {
+        self.entries.insert(account.to_string(), password);
+    }
+
+    /// Re-encrypts everything with a fresh nonce and writes it (mode 0600).
+    pub fn save(&self) -> Result<(), ConfigError>
+    {
+        let io_err = |source| ConfigError::Io { path: self.path.clone(), source };
+
+        let map: BTreeMap<&str, &str> = self.entries.iter().map(|(k, v)| (k.as_str(), v.expose())).collect();
+        let plaintext = Zeroizing::new(serde_json::to_vec(&map).map_err(|e| ConfigError::Secret(e.to_string()))?);
+        let cipher = ChaCha20Poly1305::new(self.key.as_ref().into());
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext.as_ref())
+            .map_err(|_| ConfigError::Secret("encryption failed".into()))?;
+
+        let env = Envelope
+        {
+            version: 1,
+            salt: B64.encode(self.salt),
+            nonce: B64.encode(nonce),
+            ciphertext: B64.encode(ciphertext),
+        };
+        if let Some(dir) = self.path.parent()
+        {
+            fs::create_dir_all(dir).map_err(io_err)?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&self.path).map_err(io_err)?;
+        file.write_all(serde_json::to_string_pretty(&env).unwrap_or_default().as_bytes())
+            .map_err(io_err)?;
+        Ok(())
+    }
+}
+
+fn derive_key(passphrase: &Secret, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, ConfigError>
+{
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.expose().as_bytes(), salt, key.as_mut())
+        .map_err(|e| ConfigError::Secret(format!("key derivation failed: {}", e)))?;
+    Ok(key)
+}
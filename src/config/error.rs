@@ -0,0 +1,70 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Everything that can go wrong while locating, parsing or validating the
+/// account configuration.
+#[derive(Debug)]
+pub enum ConfigError
+{
+    /// The config file exists but could not be read.
+    Io { path: PathBuf, source: io::Error },
+    /// The config file is not valid TOML or does not match the schema.
+    Parse { path: PathBuf, message: String },
+    /// A required environment variable is unset (env fallback only).
+    MissingVar(&'static str),
+    /// A field is present but its value is unusable.
+    Invalid { account: String, field: String, message: String },
+    /// `--account` (or `default_account`) names an account that is not defined.
+    UnknownAccount(String),
+    /// The config file defines no `[accounts.*]` table at all.
+    NoAccounts(PathBuf),
+    /// A `pass_cmd` failed or the encrypted credentials file could not be unlocked.
+    Secret(String),
+}
+
+impl ConfigError
+{
+    pub(crate) fn invalid(account: &str, field: &str, message: impl Into<String>) -> Self
+    {
+        ConfigError::Invalid
+        {
+            account: account.to_string(),
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            ConfigError::Io { path, source } => write!(f, "cannot read {}: {}", path.display(), source),
+            ConfigError::Parse { path, message } => write!(f, "{}: {}", path.display(), message),
+            ConfigError::MissingVar(var) => write!(f, "{} must be set (or create a config.toml)", var),
+            ConfigError::Invalid { account, field, message } =>
+            {
+                write!(f, "account '{}': {}: {}", account, field, message)
+            }
+            ConfigError::UnknownAccount(name) => write!(f, "no account named '{}' in the config", name),
+            ConfigError::NoAccounts(path) => write!(f, "{} defines no [accounts.<name>] tables", path.display()),
+            ConfigError::Secret(message) => write!(f, "credentials: {}", message),
+        }
+    }
+}
+
+impl Error for ConfigError
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)>
+    {
+        match self
+        {
+            ConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,410 @@
+use crate::config::credentials::CredentialStore;
+use crate::config::error::ConfigError;
+use crate::config::models::{Config, Folders, OAuth2Config, Security, ServerConfig, Timeouts};
+use crate::config::secret::{run_pass_cmd, Secret};
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The on-disk `config.toml`:
+///
+/// ```toml
+/// default_account = "work"
+///
+/// [accounts.work]
+/// email        = "me@work.example"
+/// display_name = "Me Myself"
+/// signature    = "-- \nMe"
+/// aliases      = ["me@example.com"]  # also ours, left out of replies to all
+/// user         = "me@work.example"   # login for both servers (defaults to email)
+/// pass_cmd     = "pass show mail/work"
+/// download_dir = "~/Mail attachments" # where attachments are saved
+///
+/// [accounts.work.imap]
+/// host     = "imap.fastmail.com"
+/// security = "tls"                   # tls | starttls | plain (localhost only)
+/// read_timeout = 60                  # seconds; connect_timeout defaults to 15
+///
+/// [accounts.work.smtp]
+/// host     = "smtp.fastmail.com"
+/// port     = 465
+/// security = "tls"
+///
+/// [accounts.work.folders]
+/// trash = "Trash"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile
+{
+    pub default_account: Option<String>,
+    #[serde(default)]
+    pub accounts: BTreeMap<String, AccountConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig
+{
+    pub email: String,
+    pub display_name: Option<String>,
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub pass_cmd: Option<String>,
+    pub download_dir: Option<String>,
+    pub imap: ServerSection,
+    pub smtp: ServerSection,
+    #[serde(default)]
+    pub folders: Folders,
+    pub oauth2: Option<OAuth2Config>,
+}
+
+/// `[accounts.<name>.imap]` / `[accounts.<name>.smtp]`. `user`, `password`
+/// and `pass_cmd` override the account-level values for this server only.
+/// Timeouts are in seconds.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerSection
+{
+    pub host: String,
+    pub port: Option<u16>,
+    pub security: Option<Security>,
+    pub connect_timeout: Option<u64>,
+    pub read_timeout: Option<u64>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub pass_cmd: Option<String>,
+}
+
+/// Where the config file is looked up:
+/// `$BPS_MAIL_CONFIG`, else `$XDG_CONFIG_HOME/bps_mail/config.toml`,
+/// else `~/.config/bps_mail/config.toml`.
+pub fn config_path() -> Option<PathBuf>
+{
+    if let Some(p) = env::var_os("BPS_MAIL_CONFIG")
+    {
+        return Some(PathBuf::from(p));
+    }
+    Some(xdg_dir("XDG_CONFIG_HOME", ".config")?.join("config.toml"))
+}
+
+/// Directory for state we write ourselves (OAuth tokens, ...):
+/// `$XDG_DATA_HOME/bps_mail`, else `~/.local/share/bps_mail`.
+pub fn data_dir() -> Option<PathBuf>
+{
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// Where attachments are saved unless an account says otherwise:
+/// `$XDG_DOWNLOAD_DIR`, else `~/Downloads`, else the current directory.
+pub fn download_dir() -> PathBuf
+{
+    match env::var_os("XDG_DOWNLOAD_DIR")
+    {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => env::var_os("HOME").map_or_else(|| PathBuf::from("."), |home| PathBuf::from(home).join("Downloads")),
+    }
+}
+
+/// `path` with a leading `~/` replaced by the home directory.
+pub fn expand_home(path: &str) -> PathBuf
+{
+    match (path.strip_prefix("~/"), env::var_os("HOME"))
+    {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf>
+{
+    let base = match env::var_os(var)
+    {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(fallback),
+    };
+    Some(base.join("bps_mail"))
+}
+
+impl ConfigFile
+{
+    pub fn load(path: &Path) -> Result<Self, ConfigError>
+    {
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Io
+        {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::parse(&text, path)
+    }
+
+    /// Parses `text`; `path` is only used in error messages.
+    pub fn parse(text: &str, path: &Path) -> Result<Self, ConfigError>
+    {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| ConfigError::Parse
+        {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        if file.accounts.is_empty()
+        {
+            return Err(ConfigError::NoAccounts(path.to_path_buf()));
+        }
+        Ok(file)
+    }
+
+    pub fn account_names(&self) -> impl Iterator<Item = &str>
+    {
+        self.accounts.keys().map(String::as_str)
+    }
+
+    /// Resolves and validates one account without consulting the encrypted
+    /// credentials file.
+    ///
+    /// `name` wins over `default_account`; with neither set the first account
+    /// (alphabetically) is used.
+    pub fn account(&self, name: Option<&str>) -> Result<Config, ConfigError>
+    {
+        self.account_with_store(name, || Ok(None))
+    }
+
+    /// Like `account`, but passwords not given by `password` / `pass_cmd` are
+    /// looked up in the store returned by `unlock`. `unlock` runs at most once,
+    /// and only if such a lookup is needed.
+    pub fn account_with_store<F>(&self, name: Option<&str>, unlock: F) -> Result<Config, ConfigError>
+    where
+        F: FnOnce() -> Result<Option<CredentialStore>, ConfigError>,
+    {
+        let name = match name.or(self.default_account.as_deref())
+        {
+            Some(n) => n,
+            None => self.account_names().next().ok_or_else(|| ConfigError::UnknownAccount(String::new()))?,
+        };
+        let acct = self
+            .accounts
+            .get(name)
+            .ok_or_else(|| ConfigError::UnknownAccount(name.to_string()))?;
+        let mut store = LazyStore { unlock: Some(unlock), store: None };
+        acct.resolve(name, &mut store)
+    }
+}
+
+/// Defers unlocking the credentials file (and its passphrase prompt) until a
+/// password is actually missing.
+struct LazyStore<F>
+{
+    unlock: Option<F>,
+    store: Option<CredentialStore>,
+}
+
+impl<F> LazyStore<F>
+where
+    F: FnOnce() -> Result<Option<CredentialStore>, ConfigError>,
+{
+    fn get(&mut self, keys: &[String]) -> Result<Option<Secret>, ConfigError>
+    {
+        if let Some(unlock) = self.unlock.take()
+        {
+            self.store = unlock()?;
+        }
+        let Some(store) = &self.store else { return Ok(None) };
+        Ok(keys.iter().find_map(|k| store.get(k)).cloned())
+    }
+}
+
+impl AccountConfig
+{
+    fn resolve<F>(&self, name: &str, store: &mut LazyStore<F>) -> Result<Config, ConfigError>
+    where
+        F: FnOnce() -> Result<Option<CredentialStore>, ConfigError>,
+    {
+        if !self.email.contains('@')
+        {
+            return Err(ConfigError::invalid(name, "email", format!("'{}' is not an email address", self.email)));
+        }
+
+        if let Some(oauth) = &self.oauth2
+        {
+            for (field, value) in [("oauth2.auth_url", &oauth.auth_url), ("oauth2.token_url", &oauth.token_url)]
+            {
+                if !value.starts_with("https://") && !value.starts_with("http://")
+                {
+                    return Err(ConfigError::invalid(name, field, format!("'{}' is not a URL", value)));
+                }
+            }
+        }
+
+        if let Some(alias) = self.aliases.iter().find(|alias| !alias.contains('@'))
+        {
+            return Err(ConfigError::invalid(name, "aliases", format!("'{}' is not an email address", alias)));
+        }
+
+        let download_dir = match self.download_dir.as_deref().map(str::trim)
+        {
+            None => download_dir(),
+            Some("") => return Err(ConfigError::invalid(name, "download_dir", "must not be empty")),
+            Some(dir) => expand_home(dir),
+        };
+
+        let default_user = self.user.clone().unwrap_or_else(|| self.email.clone());
+        // The account `pass_cmd` runs at most once, so a passphrase prompt
+        // behind it comes up once for both servers.
+        let mut account_pass = None;
+        let (imap_user, imap_pass) = self.login(name, &self.imap, "imap", &default_user, &mut account_pass, store)?;
+        let (smtp_user, smtp_pass) = self.login(name, &self.smtp, "smtp", &default_user, &mut account_pass, store)?;
+
+        Ok(Config
+        {
+            name: name.to_string(),
+            email: self.email.clone(),
+            display_name: self.display_name.clone(),
+            signature: self.signature.clone(),
+            aliases: self.aliases.clone(),
+            download_dir,
+            imap_user,
+            imap_pass,
+            smtp_user,
+            smtp_pass,
+            imap: self.imap.server(name, "imap", Security::Tls, ServerConfig::default_imap_port)?,
+            smtp: self.smtp.server(name, "smtp", Security::StartTls, ServerConfig::default_smtp_port)?,
+            folders: self.folders.clone(),
+            oauth2: self.oauth2.clone(),
+        })
+    }
+}
+
+impl AccountConfig
+{
+    /// User and password for one server. The password is taken from the first
+    /// of: server `password`, server `pass_cmd`, account `password`, account
+    /// `pass_cmd`, then the credentials store (`<name>.<which>`, then `<name>`).
+    /// `account_pass` keeps the output of the account `pass_cmd` once run.
+    fn login<F>(
+        &self,
+        name: &str,
+        section: &ServerSection,
+        which: &str,
+        default_user: &str,
+        account_pass: &mut Option<Secret>,
+        store: &mut LazyStore<F>,
+    ) -> Result<(String, Secret), ConfigError>
+    where
+        F: FnOnce() -> Result<Option<CredentialStore>, ConfigError>,
+    {
+        let user = section.user.clone().unwrap_or_else(|| default_user.to_string());
+        let pass_cmd = |cmd: &str, field: &str|
+        {
+            run_pass_cmd(cmd).map_err(|e| ConfigError::invalid(name, field, e))
+        };
+
+        let pass = if let Some(p) = &section.password
+        {
+            Secret::from(p.as_str())
+        }
+        else if let Some(cmd) = &section.pass_cmd
+        {
+            pass_cmd(cmd, &format!("{}.pass_cmd", which))?
+        }
+        else if let Some(p) = &self.password
+        {
+            Secret::from(p.as_str())
+        }
+        else if let Some(cmd) = &self.pass_cmd
+        {
+            match account_pass
+            {
+                Some(pass) => pass.clone(),
+                None => account_pass.insert(pass_cmd(cmd, "pass_cmd")?).clone(),
+            }
+        }
+        // OAuth2 accounts authenticate with a token, so no password is needed.
+        else if self.oauth2.is_some()
+        {
+            Secret::default()
+        }
+        else
+        {
+            store
+                .get(&[format!("{}.{}", name, which), name.to_string()])?
+                .ok_or_else(|| ConfigError::invalid(name, which, "no password configured"))?
+        };
+        Ok((user, pass))
+    }
+}
+
+impl ServerSection
+{
+    fn server(
+        &self,
+        account: &str,
+        which: &str,
+        default_security: Security,
+        default_port: fn(Security) -> u16,
+    ) -> Result<ServerConfig, ConfigError>
+    {
+        if self.host.trim().is_empty()
+        {
+            return Err(ConfigError::invalid(account, &format!("{}.host", which), "must not be empty"));
+        }
+        let security = self.security.unwrap_or(default_security);
+        let port = self.port.unwrap_or_else(|| default_port(security));
+        if port == 0
+        {
+            return Err(ConfigError::invalid(account, &format!("{}.port", which), "must not be 0"));
+        }
+        let seconds = |value: Option<u64>, field: &str, default: Duration| match value
+        {
+            None => Ok(default),
+            Some(0) => Err(ConfigError::invalid(account, &format!("{}.{}", which, field), "must be at least 1 second")),
+            Some(n) => Ok(Duration::from_secs(n)),
+        };
+        let defaults = Timeouts::default();
+        let timeouts = Timeouts
+        {
+            connect: seconds(self.connect_timeout, "connect_timeout", defaults.connect)?,
+            read: seconds(self.read_timeout, "read_timeout", defaults.read)?,
+        };
+        Ok(ServerConfig
+        {
+            host: self.host.trim().to_string(),
+            port,
+            security,
+            timeouts,
+        })
+    }
+}
+
+impl Config
+{
+    /// Loads the named account (or the default one) from `config.toml`,
+    /// falling back to the environment when no config file exists.
+    pub fn load(account: Option<&str>) -> Result<Self, ConfigError>
+    {
+        match config_path()
+        {
+            Some(path) if path.exists() => ConfigFile::load(&path)?.account_with_store(account, unlock_default_store),
+            _ => match account
+            {
+                Some(name) => Err(ConfigError::UnknownAccount(name.to_string())),
+                None => Config::from_env(),
+            },
+        }
+    }
+}
+
+/// Prompts for the master passphrase and decrypts the default credentials
+/// file, if there is one.
+fn unlock_default_store() -> Result<Option<CredentialStore>, ConfigError>
+{
+    let Some(path) = CredentialStore::default_path().filter(|p| p.exists()) else { return Ok(None) };
+    let passphrase = rpassword::prompt_password("Master passphrase: ")
+        .map_err(|e| ConfigError::Secret(format!("cannot read passphrase: {}", e)))?;
+    CredentialStore::open(&path, &Secret::new(passphrase)).map(Some)
+}
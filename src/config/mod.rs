@@ -1,2 +1,10 @@
+pub mod credentials;
+pub mod error;
+pub mod file;
 pub mod models;
-pub use models::Config;
+pub mod secret;
+pub use credentials::CredentialStore;
+pub use error::ConfigError;
+pub use file::{config_path, data_dir, download_dir, expand_home, ConfigFile};
+pub use models::{Config, Folders, OAuth2Config, Security, ServerConfig, Timeouts};
+pub use secret::Secret;
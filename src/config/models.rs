@@ -1,25 +1,276 @@
+use crate::config::error::ConfigError;
+use crate::config::file::download_dir;
+use crate::config::secret::{run_pass_cmd, Secret};
+
 use dotenvy::dotenv;
+use serde::Deserialize;
 use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How the connection to a mail server is secured.
+///
+///   • `Tls`      → implicit TLS from the first byte (IMAPS 993, SMTPS 465)
+///   • `StartTls` → plain connect, then upgrade with STARTTLS (IMAP 143, submission 587)
+///   • `Plain`    → no encryption at all; only accepted for localhost test servers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Security
+{
+    Tls,
+    StartTls,
+    Plain,
+}
+
+impl FromStr for Security
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s.trim().to_ascii_lowercase().as_str()
+        {
+            "tls" | "ssl" | "implicit" => Ok(Security::Tls),
+            "starttls" => Ok(Security::StartTls),
+            "plain" | "none" | "plaintext" => Ok(Security::Plain),
+            other => Err(format!("unknown security mode '{}' (expected tls, starttls or plain)", other)),
+        }
+    }
+}
+
+impl TryFrom<String> for Security
+{
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error>
+    {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Security
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let s = match self
+        {
+            Security::Tls => "tls",
+            Security::StartTls => "starttls",
+            Security::Plain => "plain",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Host, port and security mode of a single IMAP or SMTP endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig
+{
+    pub host: String,
+    pub port: u16,
+    pub security: Security,
+    pub timeouts: Timeouts,
+}
+
+/// How long to wait for a server before treating the connection as dead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts
+{
+    /// Opening the TCP connection.
+    pub connect: Duration,
+    /// Any single read or write once connected (IDLE sets its own).
+    pub read: Duration,
+}
 
+impl Default for Timeouts
+{
+    fn default() -> Self
+    {
+        Self { connect: Duration::from_secs(15), read: Duration::from_secs(60) }
+    }
+}
+
+impl ServerConfig
+{
+    /// Default IMAP port for the given security mode.
+    pub fn default_imap_port(security: Security) -> u16
+    {
+        match security
+        {
+            Security::Tls => 993,
+            Security::StartTls | Security::Plain => 143,
+        }
+    }
+
+    /// Default SMTP submission port for the given security mode.
+    pub fn default_smtp_port(security: Security) -> u16
+    {
+        match security
+        {
+            Security::Tls => 465,
+            Security::StartTls => 587,
+            Security::Plain => 25,
+        }
+    }
+
+    /// True when the host refers to the local machine, the only place
+    /// where an unencrypted connection is acceptable.
+    pub fn is_localhost(&self) -> bool
+    {
+        matches!(self.host.as_str(), "localhost" | "127.0.0.1" | "::1" | "[::1]")
+    }
+}
+
+/// Folder names for an account. `None` means "detect from the server".
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Folders
+{
+    pub inbox: String,
+    pub sent: Option<String>,
+    pub drafts: Option<String>,
+    pub trash: Option<String>,
+    pub archive: Option<String>,
+}
+
+impl Default for Folders
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            inbox: "INBOX".into(),
+            sent: None,
+            drafts: None,
+            trash: None,
+            archive: None,
+        }
+    }
+}
+
+/// `[accounts.<name>.oauth2]`: when present the account authenticates with
+/// XOAUTH2 instead of a password. For Gmail:
+///
+/// ```toml
+/// auth_url        = "https://accounts.google.com/o/oauth2/auth"
+/// token_url       = "https://oauth2.googleapis.com/token"
+/// device_auth_url = "https://oauth2.googleapis.com/device/code"
+/// scopes          = ["https://mail.google.com/"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OAuth2Config
+{
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub auth_url: String,
+    pub token_url: String,
+    /// Enables the device flow (code shown in the terminal) instead of a browser redirect.
+    pub device_auth_url: Option<String>,
+    /// Loopback redirect for the authorization-code flow; a free port is picked when unset.
+    pub redirect_url: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+/// One fully resolved account: everything `main` needs to connect and send.
 #[derive(Debug, Clone)]
 pub struct Config {
+    pub name: String,
+    pub email: String,
+    pub display_name: Option<String>,
+    pub signature: Option<String>,
+    /// Other addresses that reach this account.
+    pub aliases: Vec<String>,
+    /// Where attachments are saved.
+    pub download_dir: PathBuf,
     pub imap_user: String,
-    pub imap_pass: String,
+    pub imap_pass: Secret,
     pub smtp_user: String,
-    pub smtp_pass: String,
+    pub smtp_pass: Secret,
+    pub imap: ServerConfig,
+    pub smtp: ServerConfig,
+    pub folders: Folders,
+    pub oauth2: Option<OAuth2Config>,
 }
 
-impl Config 
+impl Config
 {
-    pub fn from_env() -> Self 
+    /// Reads the account from the environment (and `.env`).
+    ///
+    /// Passwords come from IMAP_PASS / SMTP_PASS, or from the output of
+    /// IMAP_PASS_CMD / SMTP_PASS_CMD (e.g. `pass show mail/work`).
+    /// Attachments are saved to DOWNLOAD_DIR, else the usual downloads folder.
+    ///
+    /// Server settings default to Gmail when `IMAP_HOST` / `SMTP_HOST` etc. are unset:
+    ///   IMAP_HOST, IMAP_PORT, IMAP_SECURITY (tls | starttls | plain)
+    ///   SMTP_HOST, SMTP_PORT, SMTP_SECURITY (tls | starttls | plain)
+    pub fn from_env() -> Result<Self, ConfigError>
     {
         dotenv().ok();
-        Self 
+
+        let imap_security = env_parse("IMAP_SECURITY", Security::Tls)?;
+        let smtp_security = env_parse("SMTP_SECURITY", Security::StartTls)?;
+        let smtp_user = env_required("SMTP_USER")?;
+
+        Ok(Self
         {
-            imap_user: env::var("IMAP_USER").expect("IMAP_USER must be set"),
-            imap_pass: env::var("IMAP_PASS").expect("IMAP_PASS must be set"),
-            smtp_user: env::var("SMTP_USER").expect("SMTP_USER must be set"),
-            smtp_pass: env::var("SMTP_PASS").expect("SMTP_PASS must be set"),
-        }
+            name: "default".into(),
+            email: smtp_user.clone(),
+            display_name: None,
+            signature: None,
+            aliases: Vec::new(),
+            download_dir: env::var_os("DOWNLOAD_DIR").map_or_else(download_dir, PathBuf::from),
+            imap_user: env_required("IMAP_USER")?,
+            imap_pass: env_secret("IMAP_PASS", "IMAP_PASS_CMD")?,
+            smtp_user,
+            smtp_pass: env_secret("SMTP_PASS", "SMTP_PASS_CMD")?,
+            imap: ServerConfig
+            {
+                host: env::var("IMAP_HOST").unwrap_or_else(|_| "imap.gmail.com".into()),
+                port: env_parse("IMAP_PORT", ServerConfig::default_imap_port(imap_security))?,
+                security: imap_security,
+                timeouts: Timeouts::default(),
+            },
+            smtp: ServerConfig
+            {
+                host: env::var("SMTP_HOST").unwrap_or_else(|_| "smtp.gmail.com".into()),
+                port: env_parse("SMTP_PORT", ServerConfig::default_smtp_port(smtp_security))?,
+                security: smtp_security,
+                timeouts: Timeouts::default(),
+            },
+            folders: Folders::default(),
+            oauth2: None,
+        })
+    }
+}
+
+fn env_required(var: &'static str) -> Result<String, ConfigError>
+{
+    env::var(var).map_err(|_| ConfigError::MissingVar(var))
+}
+
+fn env_secret(var: &'static str, cmd_var: &'static str) -> Result<Secret, ConfigError>
+{
+    if let Ok(cmd) = env::var(cmd_var)
+    {
+        return run_pass_cmd(&cmd).map_err(|e| ConfigError::invalid("default", cmd_var, e));
+    }
+    env_required(var).map(Secret::new)
+}
+
+fn env_parse<T>(var: &'static str, default: T) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match env::var(var)
+    {
+        Ok(v) => v
+            .parse()
+            .map_err(|e: T::Err| ConfigError::invalid("default", var, e.to_string())),
+        Err(_) => Ok(default),
     }
 }
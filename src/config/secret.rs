@@ -0,0 +1,93 @@
+use std::fmt;
+use std::process::{Command, Stdio};
+use zeroize::Zeroizing;
+
+/// A password or passphrase that is wiped from memory when dropped and never
+/// shows up in `Debug` output.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret
+{
+    pub fn new(value: String) -> Self
+    {
+        Secret(Zeroizing::new(value))
+    }
+
+    pub fn expose(&self) -> &str
+    {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for Secret
+{
+    fn from(value: String) -> Self
+    {
+        Secret::new(value)
+    }
+}
+
+impl From<&str> for Secret
+{
+    fn from(value: &str) -> Self
+    {
+        Secret::new(value.to_string())
+    }
+}
+
+impl fmt::Debug for Secret
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.write_str("Secret(***)")
+    }
+}
+
+/// Runs a `pass_cmd` such as `pass show mail/work` through the shell and
+/// returns the first line of its output.
+///
+/// stdin and stderr stay attached to the terminal so tools like gpg can ask
+/// for their own passphrase.
+pub fn run_pass_cmd(cmd: &str) -> Result<Secret, String>
+{
+    let output = shell(cmd)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| format!("cannot run `{}`: {}", cmd, e))?;
+    let stdout = Zeroizing::new(output.stdout);
+    if !output.status.success()
+    {
+        return Err(format!("`{}` failed ({})", cmd, output.status));
+    }
+
+    let text = std::str::from_utf8(&stdout).map_err(|_| format!("`{}` printed invalid UTF-8", cmd))?;
+    let first = text.lines().next().unwrap_or("");
+    if first.is_empty()
+    {
+        return Err(format!("`{}` printed nothing", cmd));
+    }
+    Ok(Secret::from(first))
+}
+
+#[cfg(not(windows))]
+fn shell(cmd: &str) -> Command
+{
+    let mut c = Command::new("sh");
+    c.arg("-c").arg(cmd);
+    c
+}
+
+#[cfg(windows)]
+fn shell(cmd: &str) -> Command
+{
+    let mut c = Command::new("cmd");
+    c.arg("/C").arg(cmd);
+    c
+}
@@ -1,172 +1,827 @@
-use crate::imap::models::{MailDetail, MailSummary};
-use crate::imap::parser::{find_html, find_plain};
+use crate::config::{Secret, ServerConfig};
+use crate::imap::error::ImapError;
+use crate::imap::folders::{guess_special_use, sort_folders};
+use crate::imap::models::{Flag, FlagUpdate, Folder, MailDetail, MailSummary, MailboxStatus, ThreadUpdate};
+use crate::imap::parser::{has_attachments, mail_detail, render_body, Headers};
+use crate::imap::search::SearchQuery;
+use crate::imap::stream::{Diverted, ImapStream};
+use crate::oauth::{xoauth2_response, TokenSource};
 
-use chrono::{DateTime, FixedOffset};
-use html2text::from_read;
-use mailparse::parse_mail;
-use native_tls::{TlsConnector, TlsStream};
-use std::error::Error;
-use std::net::TcpStream;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use imap::types::{Fetch, Mailbox, StatusAttribute, UnsolicitedResponse, ZeroCopy};
+use imap::extensions::idle::WaitOutcome;
 use imap::Session;
 
+/// How hard `ImapClient` tries to get a dropped connection back before a
+/// command fails.
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectPolicy
+{
+    /// Connection attempts per failed command; 0 turns reconnecting off.
+    pub attempts: u32,
+    /// Pause before the second attempt, doubled after each further failure.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for ReconnectPolicy
+{
+    fn default() -> Self
+    {
+        Self { attempts: 4, initial_delay: Duration::from_secs(1), max_delay: Duration::from_secs(30) }
+    }
+}
+
+/// What `ImapClient` logs in with, kept so it can log in again. OAuth2
+/// tokens are asked for on every login, since a stored one would have
+/// expired by the time a dropped connection needs it.
+enum Auth
+{
+    Password(Secret),
+    XOAuth2(TokenSource),
+}
+
+/// An IMAP session that survives dropped connections.
+///
+/// Every command runs through `run`: when it fails because the connection
+/// broke (reset, timeout, server hung up) the client logs in again, with
+/// backoff, re-selects its mailbox and repeats the command once. Commands the
+/// server merely refused are not retried, and neither is COPY, which is not
+/// safe to repeat (see `run_once`).
 pub struct ImapClient 
 {
-    session: Session<TlsStream<TcpStream>>,
+    session: Session<ImapStream>,
+    /// The session's stream hands untagged lines the imap crate cannot
+    /// parse over to this; see `run_diverted`.
+    diverted: Arc<Mutex<Diverted>>,
+    server: ServerConfig,
+    user: String,
+    auth: Auth,
+    reconnect: ReconnectPolicy,
+    /// Bumped on every reconnect.
+    generation: u32,
+    /// When the server last answered, for `keepalive`.
+    last_used: Instant,
+    /// Mailbox the fetch/delete methods operate on.
+    mailbox: String,
+    /// Whether `mailbox` is currently selected on the server.
+    selected: bool,
 }
 
 impl ImapClient 
 {
-    pub fn connect(user: &str, pass: &str) -> Result<Self, Box<dyn Error>> 
+    pub fn connect(server: &ServerConfig, user: &str, pass: &str) -> Result<Self, ImapError> 
     {
-        let domain = "imap.gmail.com";
-        let tls = TlsConnector::builder().build()?;
-        let client = imap::connect((domain, 993), domain, &tls)?;
-        let session = client.login(user, pass).map_err(|e| e.0)?;
-        Ok(Self { session })
+        Self::start(server, user, Auth::Password(Secret::from(pass)))
     }
 
-    pub fn fetch_inbox(&mut self, count: usize) -> Result<Vec<MailSummary>, Box<dyn Error>> 
+    /// Like `connect`, but authenticates with `AUTHENTICATE XOAUTH2` and an OAuth2 access token.
+    pub fn connect_xoauth2(server: &ServerConfig, user: &str, access_token: &str) -> Result<Self, ImapError>
     {
-        self.session.select("INBOX")?;
+        let token = Secret::from(access_token);
+        Self::connect_oauth2(server, user, Arc::new(move || Ok(token.expose().to_string())))
+    }
 
-        let all_fetches = self.session.fetch("1:*", "(UID INTERNALDATE)")?;
+    /// Like `connect_xoauth2`, but takes a fresh token from `tokens` for
+    /// every login, including the ones `reconnect` makes.
+    pub fn connect_oauth2(server: &ServerConfig, user: &str, tokens: TokenSource) -> Result<Self, ImapError>
+    {
+        Self::start(server, user, Auth::XOAuth2(tokens))
+    }
 
-        let mut uid_dates: Vec<(u32, DateTime<FixedOffset>)> = Vec::with_capacity(all_fetches.len());
-        for fetch in all_fetches.iter() 
+    /// Replaces the default `ReconnectPolicy`.
+    pub fn with_reconnect(mut self, policy: ReconnectPolicy) -> Self
+    {
+        self.reconnect = policy;
+        self
+    }
+
+    fn start(server: &ServerConfig, user: &str, auth: Auth) -> Result<Self, ImapError>
+    {
+        let (session, diverted) = Self::login(server, user, &auth)?;
+        Ok(Self
+        {
+            session,
+            diverted,
+            server: server.clone(),
+            user: user.to_string(),
+            auth,
+            reconnect: ReconnectPolicy::default(),
+            generation: 0,
+            last_used: Instant::now(),
+            mailbox: "INBOX".to_string(),
+            selected: false,
+        })
+    }
+
+    fn login(server: &ServerConfig, user: &str, auth: &Auth) -> Result<(Session<ImapStream>, Arc<Mutex<Diverted>>), ImapError>
+    {
+        let (stream, greeted) = ImapStream::connect(server)?;
+        let diverted = stream.diverted();
+        let mut client = imap::Client::new(stream);
+        if !greeted
+        {
+            client.read_greeting()?;
+        }
+        let session = match auth
         {
-            if let (Some(uid), Some(internal_date)) = (fetch.uid, fetch.internal_date()) 
+            Auth::Password(pass) => client.login(user, pass.expose()).map_err(|e| ImapError::auth(e.0))?,
+            Auth::XOAuth2(tokens) =>
             {
-                uid_dates.push((uid, internal_date));
+                let token = tokens().map_err(|e| ImapError::Auth(format!("no OAuth2 access token: {}", e)))?;
+                let token = Secret::new(token);
+                let auth = XOAuth2 { user, access_token: token.expose(), sent: Cell::new(false) };
+                client.authenticate("XOAUTH2", &auth).map_err(|e| ImapError::auth(e.0))?
             }
-        }
+        };
+        Ok((session, diverted))
+    }
 
-        uid_dates.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+    /// Runs `op`, reconnecting and running it once more if the connection
+    /// turned out to be dead.
+    fn run<T>(&mut self, mut op: impl FnMut(&mut Self) -> Result<T, ImapError>) -> Result<T, ImapError>
+    {
+        let result = match op(self)
+        {
+            Err(e) if e.is_disconnect() && self.reconnect.attempts > 0 =>
+            {
+                self.reconnect()?;
+                op(self)
+            }
+            other => other,
+        };
+        if result.is_ok()
+        {
+            self.last_used = Instant::now();
+        }
+        result
+    }
 
-        let newest_uids = uid_dates
-            .into_iter()
-            .take(count)
-            .map(|(uid, date)| (uid, date))
-            .collect::<Vec<(u32, DateTime<FixedOffset>)>>();
+    /// Like `run`, for commands that must not be sent twice, such as COPY: if
+    /// the connection breaks the server may have carried the command out
+    /// before it could answer, so the error is returned as it is. The next
+    /// command reconnects.
+    fn run_once<T>(&mut self, op: impl FnOnce(&mut Self) -> Result<T, ImapError>) -> Result<T, ImapError>
+    {
+        let result = op(self);
+        if result.is_ok()
+        {
+            self.last_used = Instant::now();
+        }
+        result
+    }
 
-        let mut list = Vec::with_capacity(newest_uids.len());
-        for (uid, date) in newest_uids.into_iter() 
+    /// Logs in again on a new connection, retrying with exponential backoff
+    /// while the server is unreachable. The mailbox is re-selected by the
+    /// next command that needs it.
+    pub fn reconnect(&mut self) -> Result<(), ImapError>
+    {
+        let mut delay = self.reconnect.initial_delay;
+        let mut last = ImapError::ConnectionLost;
+        for attempt in 0..self.reconnect.attempts.max(1)
         {
-            let resp = self.session.uid_fetch(
-                uid.to_string(),
-                "BODY.PEEK[HEADER.FIELDS (FROM DATE)]",
-            )?;
-            for fetch in resp.iter() 
+            if attempt > 0
+            {
+                thread::sleep(delay);
+                delay = (delay * 2).min(self.reconnect.max_delay);
+            }
+            match Self::login(&self.server, &self.user, &self.auth)
             {
-                if let Some(header_bytes) = fetch.header() 
+                Ok((session, diverted)) =>
                 {
-                    let header_text = String::from_utf8_lossy(header_bytes);
-                    let mut from_line = String::new();
-
-                    for raw_line in header_text.split("\r\n") 
-                    {
-                        let lower = raw_line.to_lowercase();
-                        if lower.starts_with("from:") 
-                        {
-                            let after = raw_line["From:".len()..].trim();
-                            if let Some(start) = after.find('<') {
-                                if let Some(end) = after[start + 1..].find('>') 
-                                {
-                                    from_line = after[start + 1..start + 1 + end].to_string();
-                                } 
-                                else 
-                                {
-                                    from_line = after[start + 1..].to_string();
-                                }
-                            } 
-                            else 
-                            {
-                                from_line = after.to_string();
-                            }
-                        }
-                    }
-
-                    list.push(MailSummary 
-                    {
-                        uid,
-                        from: from_line,
-                        date,
-                    });
+                    self.session = session;
+                    self.diverted = diverted;
+                    self.selected = false;
+                    self.generation += 1;
+                    self.last_used = Instant::now();
+                    return Ok(());
                 }
+                // Wrong credentials will not get better by retrying.
+                Err(e) if !e.is_disconnect() => return Err(e),
+                Err(e) => last = e,
             }
         }
+        Err(last)
+    }
+
+    /// Sends a NOOP if the connection has not been used for `interval`, so
+    /// servers that drop idle clients (Gmail after about 30 minutes) keep it
+    /// open, and a connection that died anyway is replaced before the next
+    /// real command needs it. Returns whether a NOOP was sent.
+    pub fn keepalive(&mut self, interval: Duration) -> Result<bool, ImapError>
+    {
+        if self.last_used.elapsed() < interval
+        {
+            return Ok(false);
+        }
+        // Raw NOOP for the same reason as in `wait_for_change`.
+        self.run(|c| Ok(c.session.run_command_and_read_response("NOOP")?))?;
+        Ok(true)
+    }
+
+    /// All mailboxes on the server, INBOX first and then by SPECIAL-USE role.
+    /// Roles the server does not announce are guessed from common names.
+    pub fn list_mailboxes(&mut self) -> Result<Vec<Folder>, ImapError>
+    {
+        let names = self.run(|c| Ok(c.session.list(Some(""), Some("*"))?))?;
+        let mut folders: Vec<Folder> = names.iter().map(Folder::from).collect();
+        guess_special_use(&mut folders);
+        sort_folders(&mut folders);
+        Ok(folders)
+    }
+
+    /// Makes `mailbox` the one the fetch/delete methods operate on and
+    /// returns its status (EXISTS, UIDVALIDITY, UIDNEXT, …).
+    pub fn select(&mut self, mailbox: &str) -> Result<Mailbox, ImapError>
+    {
+        // A failed SELECT leaves no mailbox selected on the server.
+        self.selected = false;
+        let status = self.run(|c| Ok(c.session.select(mailbox)?))?;
+        self.mailbox = mailbox.to_string();
+        self.selected = true;
+        Ok(status)
+    }
+
+    /// The currently selected mailbox (`INBOX` until `select` is called).
+    pub fn mailbox(&self) -> &str
+    {
+        &self.mailbox
+    }
 
+    fn ensure_selected(&mut self) -> Result<(), ImapError>
+    {
+        if !self.selected
+        {
+            self.session.select(&self.mailbox)?;
+            self.selected = true;
+        }
+        Ok(())
+    }
+
+    /// The newest `count` messages of the selected mailbox, newest first.
+    ///
+    /// "Newest" means the highest sequence numbers, i.e. the last messages to
+    /// arrive, so the window is known from the EXISTS count of the SELECT and
+    /// all headers come back in one FETCH instead of one per message. Within
+    /// that window the list is ordered by INTERNALDATE.
+    pub fn fetch_inbox(&mut self, count: usize) -> Result<Vec<MailSummary>, ImapError> 
+    {
+        let mailbox = self.mailbox.clone();
+        let exists = self.select(&mailbox)?.exists;
+        if exists == 0 || count == 0
+        {
+            return Ok(Vec::new());
+        }
+        let first = exists.saturating_sub(count as u32) + 1;
+
+        let mut list = self.fetch_range(first, exists)?;
+        list.sort_by_key(|m| Reverse(m.date));
         Ok(list)
     }
 
-    pub fn fetch_headers(&mut self, uid: u32) -> Result<(String, String, String), Box<dyn Error>> 
+    /// Summaries for the sequence numbers `first..=last` of the selected mailbox.
+    pub fn fetch_range(&mut self, first: u32, last: u32) -> Result<Vec<MailSummary>, ImapError>
     {
-        self.session.select("INBOX")?;
+        self.run(|c|
+        {
+            c.ensure_selected()?;
+            let fetches = c.session.fetch(format!("{}:{}", first, last), SUMMARY_QUERY)?;
+            Ok(summaries(&fetches))
+        })
+    }
 
-        let resp = self
-            .session
-            .uid_fetch(uid.to_string(), "BODY.PEEK[HEADER.FIELDS (FROM SUBJECT DATE)]")?;
-        for fetch in resp.iter() 
+    /// Summaries for a UID set such as `120:*` of the selected mailbox.
+    pub fn fetch_summaries(&mut self, uid_set: &str) -> Result<Vec<MailSummary>, ImapError>
+    {
+        self.run(|c|
         {
-            if let Some(header_bytes) = fetch.header() 
-            {
-                let header_text = String::from_utf8_lossy(header_bytes);
-                let mut from_val = String::new();
-                let mut subject_val = String::new();
-                let mut date_val = String::new();
+            c.ensure_selected()?;
+            let fetches = c.session.uid_fetch(uid_set, SUMMARY_QUERY)?;
+            Ok(summaries(&fetches))
+        })
+    }
+
+    /// Current flags for `uid_set`. With `changed_since` (CONDSTORE only)
+    /// just the messages whose flags changed after that mod-sequence.
+    pub fn fetch_flags(&mut self, uid_set: &str, changed_since: Option<u64>) -> Result<Vec<FlagUpdate>, ImapError>
+    {
+        let query = match changed_since
+        {
+            Some(modseq) => format!("(UID FLAGS) (CHANGEDSINCE {})", modseq),
+            None => "(UID FLAGS)".to_string(),
+        };
+        self.run(|c|
+        {
+            c.ensure_selected()?;
+            let fetches = c.session.uid_fetch(uid_set, &query)?;
+            Ok(fetches
+                .iter()
+                .filter_map(|f| Some((f.uid?, f.flags().iter().map(|x| x.to_string()).collect())))
+                .collect())
+        })
+    }
+
+    /// UIDs in `uid_set` that still exist in the selected mailbox.
+    pub fn search_uids(&mut self, uid_set: &str) -> Result<HashSet<u32>, ImapError>
+    {
+        self.run(|c|
+        {
+            c.ensure_selected()?;
+            Ok(c.session.uid_search(format!("UID {}", uid_set))?)
+        })
+    }
+
+    /// UIDs of the messages at sequence numbers `seq_set`, such as `120:*`
+    /// for the 120th message and everything after it.
+    pub fn uids_at(&mut self, seq_set: &str) -> Result<HashSet<u32>, ImapError>
+    {
+        self.run(|c|
+        {
+            c.ensure_selected()?;
+            Ok(c.session.uid_search(seq_set)?)
+        })
+    }
 
-                for raw_line in header_text.split("\r\n") 
+    /// UIDs of the messages in the selected mailbox that match `query`,
+    /// lowest first. Gmail gets the query in its own syntax via `X-GM-RAW`,
+    /// which searches the way its web interface does.
+    pub fn search(&mut self, query: &SearchQuery) -> Result<Vec<u32>, ImapError>
+    {
+        let literal_plus = !query.gmail().is_ascii() && self.has_capability("LITERAL+")?;
+        let criteria = if self.has_capability("X-GM-EXT-1")?
+        {
+            format!("X-GM-RAW {}", astring(&query.gmail(), literal_plus))
+        }
+        else
+        {
+            query.criteria(literal_plus)
+        };
+        let uids = self.run(|c|
+        {
+            c.ensure_selected()?;
+            c.with_literals(|session| session.uid_search(&criteria))
+        })?;
+        let mut uids: Vec<u32> = uids.into_iter().collect();
+        uids.sort_unstable();
+        Ok(uids)
+    }
+
+    /// The server's conversation for each message in `uid_set`: Gmail's
+    /// `X-GM-THRID` where the server has `X-GM-EXT-1`, else, with
+    /// `THREAD=REFERENCES`, the lowest UID of the thread the message is in
+    /// among `uid_set`. Empty when the server has neither, and `threads`
+    /// goes by the headers alone.
+    ///
+    /// The imap crate parses neither response, so both are read past it
+    /// with `run_diverted`.
+    pub fn thread_ids(&mut self, uid_set: &str) -> Result<Vec<ThreadUpdate>, ImapError>
+    {
+        let (command, keyword, parse): (String, &str, ThreadParser) = if self.has_capability("X-GM-EXT-1")?
+        {
+            (format!("UID FETCH {} (UID X-GM-THRID)", uid_set), "X-GM-THRID", gmail_thread_ids)
+        }
+        else if self.has_capability("THREAD=REFERENCES")?
+        {
+            (format!("UID THREAD REFERENCES UTF-8 UID {}", uid_set), "THREAD", thread_roots)
+        }
+        else
+        {
+            return Ok(Vec::new());
+        };
+        let untagged = self.run(|c|
+        {
+            c.ensure_selected()?;
+            c.run_diverted(&command, keyword)
+        })?;
+        Ok(parse(&untagged))
+    }
+
+    /// Sends `command` and returns the untagged lines containing `keyword`,
+    /// which the stream keeps from the imap crate so it does not choke on
+    /// them.
+    fn run_diverted(&mut self, command: &str, keyword: &str) -> Result<Vec<u8>, ImapError>
+    {
+        self.divert(Some(keyword));
+        let result = self.session.run_command_and_read_response(command);
+        let lines = self.divert(None);
+        result?;
+        Ok(lines)
+    }
+
+    /// Sets the keyword to divert by and takes what was diverted so far.
+    fn divert(&self, keyword: Option<&str>) -> Vec<u8>
+    {
+        let mut diverted = self.diverted.lock().unwrap_or_else(|e| e.into_inner());
+        diverted.keyword = keyword.map(str::to_string);
+        std::mem::take(&mut diverted.lines)
+    }
+
+    /// Runs `op`, whose command carries literals (see `astring`), with the
+    /// server's `+` continuation requests kept from the imap crate. The
+    /// crate writes a command in one go, so the literals follow without
+    /// waiting for the `+`.
+    fn with_literals<T>(&mut self, op: impl FnOnce(&mut Session<ImapStream>) -> imap::error::Result<T>) -> Result<T, ImapError>
+    {
+        let continuations = |c: &Self, on: bool| c.diverted.lock().unwrap_or_else(|e| e.into_inner()).continuations = on;
+        continuations(self, true);
+        let result = op(&mut self.session);
+        continuations(self, false);
+        Ok(result?)
+    }
+
+    /// `STATUS` of any mailbox without selecting it. HIGHESTMODSEQ is only
+    /// requested when `condstore` is set.
+    pub fn status(&mut self, mailbox: &str, condstore: bool) -> Result<MailboxStatus, ImapError>
+    {
+        let items = if condstore
+        {
+            "(MESSAGES UIDNEXT UIDVALIDITY HIGHESTMODSEQ)"
+        }
+        else
+        {
+            "(MESSAGES UIDNEXT UIDVALIDITY)"
+        };
+        self.run(|c| Ok(c.session.status(mailbox, items)?))?;
+
+        // The imap crate hands STATUS data to the unsolicited channel.
+        let mut status = MailboxStatus::default();
+        while let Ok(resp) = self.session.unsolicited_responses.try_recv()
+        {
+            let UnsolicitedResponse::Status { mailbox: name, attributes } = resp
+            else
+            {
+                continue;
+            };
+            if name != mailbox
+            {
+                continue;
+            }
+            for attr in attributes
+            {
+                match attr
                 {
-                    let lower = raw_line.to_lowercase();
-                    if lower.starts_with("from:") 
-                    {
-                        from_val = raw_line["From:".len()..].trim().to_string();
-                    } 
-                    else if lower.starts_with("subject:") 
-                    {
-                        subject_val = raw_line["Subject:".len()..].trim().to_string();
-                    } 
-                    else if lower.starts_with("date:") 
-                    {
-                        date_val = raw_line["Date:".len()..].trim().to_string();
-                    }
+                    StatusAttribute::Messages(n) => status.messages = n,
+                    StatusAttribute::UidNext(n) => status.uid_next = n,
+                    StatusAttribute::UidValidity(n) => status.uid_validity = n,
+                    StatusAttribute::HighestModSeq(n) => status.highest_modseq = Some(n),
+                    _ => {}
                 }
+            }
+        }
+        Ok(status)
+    }
 
-                return Ok((from_val, subject_val, date_val));
+    /// Blocks until the server reports a change to the selected mailbox or
+    /// `timeout` passes, and returns whether something changed.
+    ///
+    /// With `idle` this sits in IDLE; otherwise it sleeps for `timeout` and
+    /// then polls with NOOP.
+    ///
+    /// A reconnect counts as a change: whatever happened while the
+    /// connection was down went unreported.
+    pub fn wait_for_change(&mut self, idle: bool, timeout: Duration) -> Result<bool, ImapError>
+    {
+        let generation = self.generation;
+        let mut slept = false;
+        self.run(|c|
+        {
+            if c.generation != generation
+            {
+                return Ok(true);
+            }
+            c.ensure_selected()?;
+            if idle
+            {
+                let outcome = c.session.idle()?.wait_with_timeout(timeout)?;
+                return Ok(outcome == WaitOutcome::MailboxChanged);
+            }
+            if !slept
+            {
+                thread::sleep(timeout);
+                slept = true;
+            }
+            // `Session::noop` fails on unsolicited FETCH responses (flag changes
+            // made by other clients), so look at the raw untagged data instead.
+            let untagged = c.session.run_command_and_read_response("NOOP")?;
+            Ok(!untagged.is_empty())
+        })
+    }
+
+    /// Whether the server advertises `capability`, e.g. `CONDSTORE` or `IDLE`.
+    pub fn has_capability(&mut self, capability: &str) -> Result<bool, ImapError>
+    {
+        self.run(|c| Ok(c.session.capabilities()?.has_str(capability)))
+    }
+
+    pub fn fetch_headers(&mut self, uid: u32) -> Result<(String, String, String), ImapError> 
+    {
+        let resp = self.run(|c|
+        {
+            c.ensure_selected()?;
+            Ok(c.session.uid_fetch(uid.to_string(), "BODY.PEEK[HEADER.FIELDS (FROM SUBJECT DATE)]")?)
+        })?;
+        for fetch in resp.iter() 
+        {
+            if let Some(header_bytes) = fetch.header() 
+            {
+                let headers = Headers::parse(header_bytes)?;
+                let value = |name| headers.get(name).unwrap_or_default();
+                return Ok((value("From"), value("Subject"), value("Date")));
             }
         }
 
         Ok((String::new(), String::new(), String::new()))
     }
 
-    pub fn fetch_body(&mut self, uid: u32) -> Result<String, Box<dyn Error>> 
+    pub fn fetch_body(&mut self, uid: u32) -> Result<String, ImapError> 
     {
-        let resp = self.session.uid_fetch(uid.to_string(), "RFC822")?;
-        let raw = match resp.iter().next().and_then(|f| f.body()) 
+        match self.fetch_raw(uid)?
         {
-            Some(b) => b,
-            None => return Ok(String::new()),
-        };
+            Some(raw) => render_body(&raw),
+            None => Ok(String::new()),
+        }
+    }
+
+    /// Headers, text and MIME parts of a message, all from one fetch of
+    /// the raw message; `None` if there is no such UID.
+    pub fn fetch_detail(&mut self, uid: u32) -> Result<Option<MailDetail>, ImapError>
+    {
+        self.fetch_raw(uid)?.map(|raw| mail_detail(uid, &raw)).transpose()
+    }
+
+    /// The complete RFC 822 message. Fetched with `BODY.PEEK[]`, so unlike
+    /// `RFC822` it does not mark the message as read.
+    pub fn fetch_raw(&mut self, uid: u32) -> Result<Option<Vec<u8>>, ImapError>
+    {
+        self.run(|c|
+        {
+            c.ensure_selected()?;
+            let resp = c.session.uid_fetch(uid.to_string(), "BODY.PEEK[]")?;
+            Ok(resp.iter().next().and_then(|f| f.body()).map(<[u8]>::to_vec))
+        })
+    }
+
+    /// The first `limit` bytes of each message in `uid_set`, for indexing
+    /// bodies without downloading every attachment.
+    pub fn fetch_prefixes(&mut self, uid_set: &str, limit: usize) -> Result<Vec<(u32, Vec<u8>)>, ImapError>
+    {
+        let query = format!("(UID BODY.PEEK[]<0.{}>)", limit);
+        self.run(|c|
+        {
+            c.ensure_selected()?;
+            let fetches = c.session.uid_fetch(uid_set, &query)?;
+            Ok(fetches.iter().filter_map(|f| Some((f.uid?, f.body()?.to_vec()))).collect())
+        })
+    }
 
-        let mail = parse_mail(raw)?;
-        if let Some(txt) = find_plain(&mail)? 
+    /// Adds (`on`) or removes `flag` on a message and returns its flags
+    /// afterwards.
+    pub fn set_flag(&mut self, uid: u32, flag: Flag, on: bool) -> Result<Vec<String>, ImapError>
+    {
+        let query = format!("{}FLAGS ({})", if on { "+" } else { "-" }, flag.as_str());
+        let stored = self.run(|c|
+        {
+            c.ensure_selected()?;
+            let fetches = c.session.uid_store(uid.to_string(), &query)?;
+            Ok(fetches
+                .iter()
+                .find(|f| f.uid.is_none_or(|u| u == uid))
+                .map(|f| f.flags().iter().map(|x| x.to_string()).collect::<Vec<String>>()))
+        })?;
+        match stored
         {
-            return Ok(txt);
+            Some(flags) => Ok(flags),
+            // The server is not obliged to echo the new flags
+            None => Ok(self.fetch_flags(&uid.to_string(), None)?.into_iter().flat_map(|(_, flags)| flags).collect()),
         }
-        if let Some(html) = find_html(&mail)? 
+    }
+
+    /// Moves a message of the selected mailbox to `target`: `UID MOVE` where
+    /// the server has it, else `UID COPY` followed by removing the original.
+    ///
+    /// Returns the UID the message got in `target` when it can be known:
+    /// from the COPYUID code UIDPLUS servers send with MOVE, or, after a
+    /// copy, from `target`'s UIDNEXT if the copy was its only arrival.
+    pub fn move_message(&mut self, uid: u32, target: &str) -> Result<Option<u32>, ImapError>
+    {
+        if self.has_capability("MOVE")?
         {
-            let converted = from_read(html.as_bytes(), 80);
-            return Ok(converted);
+            // Raw, because `Session::uid_mv` drops the untagged COPYUID.
+            let command = format!("UID MOVE {} {}", uid, quote(target));
+            let untagged = self.run(|c|
+            {
+                c.ensure_selected()?;
+                Ok(c.session.run_command_and_read_response(&command)?)
+            })?;
+            return Ok(copied_uid(&untagged));
         }
-        Ok(String::from_utf8_lossy(raw).into_owned())
+        let before = self.status(target, false)?.uid_next;
+        // Separate runs: after a reconnect only the removal is repeated,
+        // never the COPY, which would leave a second copy in `target`.
+        self.run_once(|c|
+        {
+            c.ensure_selected()?;
+            Ok(c.session.uid_copy(uid.to_string(), target)?)
+        })?;
+        self.run(|c|
+        {
+            c.ensure_selected()?;
+            c.expunge_uid(uid)
+        })?;
+        let after = self.status(target, false)?.uid_next;
+        Ok((before != 0 && after == before + 1).then_some(before))
     }
 
-    pub fn delete_message(&mut self, uid: u32) -> Result<(), Box<dyn Error>> 
+    /// Deletes a message of the selected mailbox for good.
+    pub fn delete_message(&mut self, uid: u32) -> Result<(), ImapError> 
     {
-        self.session.select("INBOX")?;
-        self.session.uid_store(uid.to_string(), "+FLAGS (\\Deleted)")?;
-        self.session.expunge()?;
+        self.run(|c|
+        {
+            c.ensure_selected()?;
+            c.expunge_uid(uid)
+        })
+    }
+
+    /// Marks `uid` `\\Deleted` and expunges it. With UIDPLUS only that message
+    /// goes; without it a plain EXPUNGE also removes anything else another
+    /// client left marked `\\Deleted`.
+    fn expunge_uid(&mut self, uid: u32) -> Result<(), ImapError>
+    {
+        self.session.uid_store(uid.to_string(), "+FLAGS.SILENT (\\Deleted)")?;
+        if self.session.capabilities()?.has_str("UIDPLUS")
+        {
+            self.session.uid_expunge(uid.to_string())?;
+        }
+        else
+        {
+            self.session.expunge()?;
+        }
         Ok(())
     }
 }
 
+/// `text` as an IMAP string for a search: quoted if it is plain ASCII,
+/// else a literal, as quoted strings may not carry 8-bit characters
+/// (RFC 3501). With LITERAL+ the literal is the `{n+}` kind the server
+/// does not answer with a `+`; see `ImapClient::with_literals`.
+pub(crate) fn astring(text: &str, literal_plus: bool) -> String
+{
+    if text.is_ascii()
+    {
+        quote(text)
+    }
+    else
+    {
+        format!("{{{}{}}}\r\n{}", text.len(), if literal_plus { "+" } else { "" }, text)
+    }
+}
+
+/// `text` (a mailbox name, a search string) as an IMAP quoted string.
+pub(crate) fn quote(text: &str) -> String
+{
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The destination UID of a single-message `[COPYUID <validity> <src> <dst>]`
+/// in raw response data.
+fn copied_uid(response: &[u8]) -> Option<u32>
+{
+    let text = String::from_utf8_lossy(response);
+    let start = text.find("[COPYUID ")? + "[COPYUID ".len();
+    let code = &text[start..start + text[start..].find(']')?];
+    code.split(' ').nth(2)?.parse().ok()
+}
+
+/// Reads one of the raw responses `ImapClient::thread_ids` asks for.
+type ThreadParser = fn(&[u8]) -> Vec<ThreadUpdate>;
+
+/// `(uid, X-GM-THRID)` from raw `* n FETCH (X-GM-THRID t UID u)` lines,
+/// whichever order the two come in.
+fn gmail_thread_ids(response: &[u8]) -> Vec<ThreadUpdate>
+{
+    let text = String::from_utf8_lossy(response);
+    text.lines()
+        .filter_map(|line|
+        {
+            let items = line.get(line.find('(')? + 1..line.rfind(')')?)?;
+            let words: Vec<&str> = items.split_whitespace().collect();
+            let value = |name: &str| words.windows(2).find(|w| w[0].eq_ignore_ascii_case(name)).map(|w| w[1]);
+            Some((value("UID")?.parse().ok()?, value("X-GM-THRID")?.parse().ok()?))
+        })
+        .collect()
+}
+
+/// `(uid, lowest uid of its thread)` from a raw `* THREAD (1 2)(3 (4)(5))`
+/// response. Only the top-level groups matter; how replies nest inside one
+/// is left to the headers.
+fn thread_roots(response: &[u8]) -> Vec<ThreadUpdate>
+{
+    let text = String::from_utf8_lossy(response);
+    let mut updates = Vec::new();
+    for line in text.lines()
+    {
+        let Some(data) = line.strip_prefix("* THREAD")
+        else
+        {
+            continue;
+        };
+        let mut depth = 0;
+        let mut group: Vec<u32> = Vec::new();
+        for token in data.replace('(', " ( ").replace(')', " ) ").split_whitespace()
+        {
+            match token
+            {
+                "(" => depth += 1,
+                ")" =>
+                {
+                    depth -= 1;
+                    if depth == 0
+                    {
+                        let root = group.iter().copied().min().unwrap_or(0);
+                        updates.extend(group.drain(..).map(|uid| (uid, u64::from(root))));
+                    }
+                }
+                n => group.extend(n.parse::<u32>().ok()),
+            }
+        }
+    }
+    updates
+}
+
+const SUMMARY_QUERY: &str = "(UID FLAGS INTERNALDATE RFC822.SIZE \
+     BODY.PEEK[HEADER.FIELDS (FROM TO CC SUBJECT DATE CONTENT-TYPE MESSAGE-ID IN-REPLY-TO REFERENCES)])";
+
+fn summaries(fetches: &ZeroCopy<Vec<Fetch>>) -> Vec<MailSummary>
+{
+    let mut list = Vec::with_capacity(fetches.len());
+    for fetch in fetches.iter() 
+    {
+        let (Some(uid), Some(date)) = (fetch.uid, fetch.internal_date())
+        else
+        {
+            continue;
+        };
+        // Headers that do not parse leave the message listed without them
+        let headers = Headers::parse(fetch.header().unwrap_or_default()).unwrap_or_default();
+        let from = headers.addresses("From").into_iter().next();
+        let mut references = headers.message_ids("References");
+        for parent in headers.message_ids("In-Reply-To")
+        {
+            if !references.contains(&parent)
+            {
+                references.push(parent);
+            }
+        }
+        list.push(MailSummary
+        {
+            uid,
+            from_name: from.as_ref().and_then(|a| a.name.clone()),
+            from: from.map(|a| a.address).unwrap_or_default(),
+            subject: headers.get("Subject").unwrap_or_default(),
+            date,
+            size: fetch.size.unwrap_or(0),
+            has_attachments: headers.get("Content-Type").is_some_and(|v| has_attachments(&v)),
+            flags: fetch.flags().iter().map(|f| f.to_string()).collect(),
+            message_id: headers.message_ids("Message-ID").into_iter().next(),
+            references,
+            recipients: ["To", "Cc"].iter().flat_map(|name| headers.addresses(name)).map(|a| a.address).collect(),
+            thread_id: None,
+        });
+    }
+    list
+}
+
+/// SASL XOAUTH2 for `imap::Client::authenticate`.
+///
+/// On failure the server answers the initial response with a continuation
+/// carrying a JSON error; the client must then send an empty line so the
+/// server can finish with a tagged NO.
+struct XOAuth2<'a>
+{
+    user: &'a str,
+    access_token: &'a str,
+    sent: Cell<bool>,
+}
+
+impl imap::Authenticator for XOAuth2<'_>
+{
+    type Response = String;
+
+    fn process(&self, _challenge: &[u8]) -> String
+    {
+        if self.sent.replace(true)
+        {
+            String::new()
+        }
+        else
+        {
+            xoauth2_response(self.user, self.access_token)
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::TcpStream;
+
+/// Everything that can go wrong talking to an IMAP server or decoding what
+/// it sent.
+#[derive(Debug)]
+pub enum ImapError
+{
+    /// The connection could not be opened, timed out or broke.
+    Io(io::Error),
+    /// The TLS handshake or the TLS layer failed.
+    Tls(String),
+    /// Connecting was refused before any command: plaintext to a remote host,
+    /// an unexpected greeting, STARTTLS rejected.
+    Connect(String),
+    /// LOGIN or AUTHENTICATE was rejected.
+    Auth(String),
+    /// The server answered a command with NO or BAD, e.g. SELECT of a
+    /// mailbox that does not exist.
+    Rejected(String),
+    /// The server closed the connection.
+    ConnectionLost,
+    /// The server sent something the imap crate could not parse.
+    Protocol(String),
+    /// A message could not be decoded (broken MIME structure or charset).
+    Message(String),
+    /// A search typed by the user that does not parse.
+    Query(String),
+}
+
+impl ImapError
+{
+    /// Whether the connection is unusable after this error, as opposed to a
+    /// single command or message having failed. A failed TLS handshake is
+    /// not: a bad certificate stays bad, so reconnecting is no use. Once
+    /// TLS is up its failures come as `Io`.
+    pub fn is_disconnect(&self) -> bool
+    {
+        matches!(self, ImapError::Io(_) | ImapError::ConnectionLost)
+    }
+
+    /// Maps the error of a failed LOGIN/AUTHENTICATE: NO and BAD mean the
+    /// credentials were refused.
+    pub(crate) fn auth(e: imap::Error) -> Self
+    {
+        match e
+        {
+            imap::Error::No(msg) | imap::Error::Bad(msg) => ImapError::Auth(msg),
+            other => other.into(),
+        }
+    }
+}
+
+impl fmt::Display for ImapError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            ImapError::Io(e) => write!(f, "IMAP connection: {}", e),
+            ImapError::Tls(msg) => write!(f, "IMAP TLS: {}", msg),
+            ImapError::Connect(msg) => write!(f, "{}", msg),
+            ImapError::Auth(msg) => write!(f, "IMAP login rejected: {}", msg),
+            ImapError::Rejected(msg) => write!(f, "IMAP server said: {}", msg),
+            ImapError::ConnectionLost => write!(f, "IMAP server closed the connection"),
+            ImapError::Protocol(msg) => write!(f, "unexpected IMAP response: {}", msg),
+            ImapError::Message(msg) => write!(f, "cannot decode message: {}", msg),
+            ImapError::Query(msg) => write!(f, "invalid search: {}", msg),
+        }
+    }
+}
+
+impl Error for ImapError
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)>
+    {
+        match self
+        {
+            ImapError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<imap::Error> for ImapError
+{
+    fn from(e: imap::Error) -> Self
+    {
+        match e
+        {
+            imap::Error::Io(e) => ImapError::Io(e),
+            imap::Error::Tls(e) => ImapError::Tls(e.to_string()),
+            imap::Error::TlsHandshake(e) => ImapError::Tls(e.to_string()),
+            imap::Error::No(msg) | imap::Error::Bad(msg) => ImapError::Rejected(msg),
+            imap::Error::ConnectionLost => ImapError::ConnectionLost,
+            other => ImapError::Protocol(other.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for ImapError
+{
+    fn from(e: io::Error) -> Self
+    {
+        ImapError::Io(e)
+    }
+}
+
+impl From<native_tls::Error> for ImapError
+{
+    fn from(e: native_tls::Error) -> Self
+    {
+        ImapError::Tls(e.to_string())
+    }
+}
+
+impl From<native_tls::HandshakeError<TcpStream>> for ImapError
+{
+    fn from(e: native_tls::HandshakeError<TcpStream>) -> Self
+    {
+        ImapError::Tls(e.to_string())
+    }
+}
+
+impl From<mailparse::MailParseError> for ImapError
+{
+    fn from(e: mailparse::MailParseError) -> Self
+    {
+        ImapError::Message(e.to_string())
+    }
+}
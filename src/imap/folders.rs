@@ -0,0 +1,142 @@
+use crate::config::Folders;
+use crate::imap::models::{Folder, SpecialUse};
+
+use imap::types::{Name, NameAttribute};
+
+impl SpecialUse
+{
+    /// Maps a `LIST` attribute such as `\Sent` to its role.
+    pub fn from_attribute(attr: &str) -> Option<Self>
+    {
+        let role = match attr.to_ascii_lowercase().as_str()
+        {
+            "\\drafts" => SpecialUse::Drafts,
+            "\\sent" => SpecialUse::Sent,
+            "\\archive" => SpecialUse::Archive,
+            "\\all" => SpecialUse::All,
+            "\\flagged" => SpecialUse::Flagged,
+            "\\junk" => SpecialUse::Junk,
+            "\\trash" => SpecialUse::Trash,
+            _ => return None,
+        };
+        Some(role)
+    }
+
+    /// Guesses a role from the usual folder names for servers that do not
+    /// advertise SPECIAL-USE attributes.
+    pub fn guess(name: &str, delimiter: Option<&str>) -> Option<Self>
+    {
+        let leaf = match delimiter
+        {
+            Some(d) if !d.is_empty() => name.rsplit(d).next().unwrap_or(name),
+            _ => name,
+        };
+        let role = match leaf.to_ascii_lowercase().as_str()
+        {
+            "drafts" | "draft" => SpecialUse::Drafts,
+            "sent" | "sent items" | "sent mail" | "sent messages" => SpecialUse::Sent,
+            "archive" | "archives" => SpecialUse::Archive,
+            "all mail" => SpecialUse::All,
+            "junk" | "spam" | "junk e-mail" | "junk email" | "bulk mail" => SpecialUse::Junk,
+            "trash" | "deleted items" | "deleted messages" | "bin" => SpecialUse::Trash,
+            _ => return None,
+        };
+        Some(role)
+    }
+}
+
+impl From<&Name> for Folder
+{
+    fn from(name: &Name) -> Self
+    {
+        let mut special_use = None;
+        let mut selectable = true;
+        for attr in name.attributes()
+        {
+            match attr
+            {
+                NameAttribute::NoSelect => selectable = false,
+                NameAttribute::Custom(a) if a.eq_ignore_ascii_case("\\NonExistent") => selectable = false,
+                NameAttribute::Custom(a) => special_use = special_use.or(SpecialUse::from_attribute(a)),
+                _ => {}
+            }
+        }
+        Folder
+        {
+            name: name.name().to_string(),
+            delimiter: name.delimiter().map(String::from),
+            special_use,
+            selectable,
+        }
+    }
+}
+
+/// Fills in roles the server did not announce by guessing from folder names.
+/// A role is only guessed if no folder already carries it.
+pub fn guess_special_use(folders: &mut [Folder])
+{
+    for i in 0..folders.len()
+    {
+        if folders[i].special_use.is_some() || folders[i].is_inbox() || !folders[i].selectable
+        {
+            continue;
+        }
+        let Some(role) = SpecialUse::guess(&folders[i].name, folders[i].delimiter.as_deref())
+        else
+        {
+            continue;
+        };
+        if !folders.iter().any(|f| f.special_use == Some(role))
+        {
+            folders[i].special_use = Some(role);
+        }
+    }
+}
+
+/// Applies the folder names from the account's `[folders]` table, which win
+/// over whatever the server announced.
+pub fn apply_overrides(folders: &mut [Folder], config: &Folders)
+{
+    let overrides = [
+        (SpecialUse::Sent, &config.sent),
+        (SpecialUse::Drafts, &config.drafts),
+        (SpecialUse::Trash, &config.trash),
+        (SpecialUse::Archive, &config.archive),
+    ];
+    for (role, name) in overrides
+    {
+        let Some(name) = name
+        else
+        {
+            continue;
+        };
+        for folder in folders.iter_mut()
+        {
+            if folder.name == *name
+            {
+                folder.special_use = Some(role);
+            }
+            else if folder.special_use == Some(role)
+            {
+                folder.special_use = None;
+            }
+        }
+    }
+}
+
+/// INBOX first, then folders with a role (Drafts, Sent, Archive, …, Trash),
+/// then everything else alphabetically.
+pub fn sort_folders(folders: &mut [Folder])
+{
+    folders.sort_by(|a, b|
+    {
+        let rank = |f: &Folder| (!f.is_inbox(), f.special_use.is_none(), f.special_use);
+        rank(a).cmp(&rank(b)).then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+    });
+}
+
+/// The first folder with the given role.
+pub fn find_special_use(folders: &[Folder], role: SpecialUse) -> Option<&Folder>
+{
+    folders.iter().find(|f| f.special_use == Some(role))
+}
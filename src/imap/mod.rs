@@ -1,7 +1,22 @@
 pub mod client;
+pub mod error;
+pub mod folders;
 pub mod parser;
 pub mod models;
+pub mod search;
+pub mod stream;
+pub mod thread;
+pub mod watch;
 
-pub use client::ImapClient;
-pub use parser::{find_html, find_plain};
-pub use models::{MailDetail, MailSummary};
+pub use client::{ImapClient, ReconnectPolicy};
+pub use error::ImapError;
+pub use folders::{apply_overrides, find_special_use};
+pub use parser::{find_html, find_plain, has_attachments, mail_detail, part_data, render_body, Headers};
+pub use models::{
+    Address, Disposition, Flag, FlagUpdate, Folder, MailDetail, MailSummary, MailboxStatus, MimePart, SpecialUse,
+    ThreadUpdate,
+};
+pub use search::{SearchQuery, Term};
+pub use stream::ImapStream;
+pub use thread::threads;
+pub use watch::{spawn_watcher, WatchConfig, WatchEvent};
@@ -1,18 +1,231 @@
 use chrono::{DateTime, FixedOffset};
+use std::fmt;
 
 #[derive(Debug, Clone)]
 pub struct MailSummary 
 {
     pub uid: u32,
+    /// Bare sender address.
     pub from: String,
+    /// The sender's display name, if `From:` has one.
+    pub from_name: Option<String>,
+    pub subject: String,
     pub date: DateTime<FixedOffset>,
+    /// `RFC822.SIZE` in bytes.
+    pub size: u32,
+    /// Whether the message looks like it has attachments (see
+    /// `parser::has_attachments`).
+    pub has_attachments: bool,
+    /// Raw IMAP flags such as `\\Seen` or `$Label1`.
+    pub flags: Vec<String>,
+    /// `Message-ID:` without the angle brackets.
+    pub message_id: Option<String>,
+    /// The ids this message answers, oldest first: `References:`, then
+    /// `In-Reply-To:` if that is not among them.
+    pub references: Vec<String>,
+    /// Bare addresses from `To:` and `Cc:`.
+    pub recipients: Vec<String>,
+    /// The conversation the server puts the message in, where it says:
+    /// Gmail's `X-GM-THRID`, or the lowest UID of its `THREAD` (see
+    /// `ImapClient::thread_ids`).
+    pub thread_id: Option<u64>,
+}
+
+impl MailSummary
+{
+    pub fn has(&self, flag: Flag) -> bool
+    {
+        flag.is_in(&self.flags)
+    }
+
+    /// The display name if there is one, else the address.
+    pub fn sender(&self) -> &str
+    {
+        self.from_name.as_deref().unwrap_or(&self.from)
+    }
+}
+
+/// The system flags the client reads and sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag
+{
+    Seen,
+    Flagged,
+    Answered,
+    Draft,
+}
+
+impl Flag
+{
+    /// The flag as written in IMAP commands, e.g. `\\Seen`.
+    pub fn as_str(self) -> &'static str
+    {
+        match self
+        {
+            Flag::Seen => "\\Seen",
+            Flag::Flagged => "\\Flagged",
+            Flag::Answered => "\\Answered",
+            Flag::Draft => "\\Draft",
+        }
+    }
+
+    /// Whether raw `flags` include this one (flags are case-insensitive).
+    pub fn is_in(self, flags: &[String]) -> bool
+    {
+        flags.iter().any(|f| f.eq_ignore_ascii_case(self.as_str()))
+    }
 }
 
+/// One mailbox of an address header: `Alice <alice@example.com>` has the
+/// name `Alice` and the address `alice@example.com`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address
+{
+    pub name: Option<String>,
+    pub address: String,
+}
+
+impl fmt::Display for Address
+{
+    /// As written in a header; a name with punctuation is quoted.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match &self.name
+        {
+            Some(name) if name.contains(|c| ",;:<>@\"()[]\\.".contains(c)) =>
+            {
+                write!(f, "\"{}\" <{}>", name.replace('\\', "\\\\").replace('"', "\\\""), self.address)
+            }
+            Some(name) => write!(f, "{} <{}>", name, self.address),
+            None => f.write_str(&self.address),
+        }
+    }
+}
+
+/// A whole message, parsed from one `BODY.PEEK[]` (see
+/// `parser::mail_detail`).
 #[derive(Debug, Clone)]
 pub struct MailDetail 
 {
-    pub from: String,
+    pub uid: u32,
+    pub from: Vec<Address>,
+    pub to: Vec<Address>,
+    pub cc: Vec<Address>,
+    pub reply_to: Vec<Address>,
+    /// `Mail-Followup-To:`, where a mailing list subscriber wants replies
+    /// to all to go (usually the list without them).
+    pub followup_to: Vec<Address>,
     pub subject: String,
-    pub date: String,
+    /// `Date:`, if it is there and can be read.
+    pub date: Option<DateTime<FixedOffset>>,
+    /// `Message-ID:` without the angle brackets.
+    pub message_id: Option<String>,
+    pub in_reply_to: Vec<String>,
+    pub references: Vec<String>,
+    /// `List-Id:`, `List-Unsubscribe:` and the other `List-*` headers of
+    /// mailing list mail, as `(name, value)`.
+    pub list_headers: Vec<(String, String)>,
+    /// Readable text: the text/plain part, else the text/html part
+    /// converted to text.
     pub body: String,
+    /// The leaves of the MIME tree, in order: text parts, inline images,
+    /// attachments.
+    pub parts: Vec<MimePart>,
+}
+
+impl MailDetail
+{
+    pub fn attachments(&self) -> impl Iterator<Item = &MimePart>
+    {
+        self.parts.iter().filter(|p| p.is_attachment())
+    }
+}
+
+/// One leaf of a message's MIME tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimePart
+{
+    /// Position among the leaves, which `part_data` takes to get the
+    /// content.
+    pub index: usize,
+    /// Lowercase, without parameters, e.g. `image/png`.
+    pub content_type: String,
+    /// From `Content-Disposition: …; filename=` or `Content-Type: …; name=`.
+    pub filename: Option<String>,
+    /// Decoded size in bytes.
+    pub size: usize,
+    /// `Content-ID:` without the angle brackets, what HTML mail refers to
+    /// inline images by (`cid:…`).
+    pub content_id: Option<String>,
+    /// `None` without a `Content-Disposition:` header.
+    pub disposition: Option<Disposition>,
+}
+
+impl MimePart
+{
+    /// Meant to be saved rather than read: marked as an attachment, or a
+    /// named file that is not just the message text.
+    pub fn is_attachment(&self) -> bool
+    {
+        self.disposition == Some(Disposition::Attachment)
+            || (self.filename.is_some() && !self.content_type.starts_with("text/"))
+    }
+}
+
+/// `Content-Disposition:` of a part: shown with the message, or saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition
+{
+    Inline,
+    Attachment,
+}
+
+/// A mailbox as returned by `LIST`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Folder
+{
+    pub name: String,
+    /// Hierarchy delimiter, e.g. `/` or `.`; `None` for flat namespaces.
+    pub delimiter: Option<String>,
+    pub special_use: Option<SpecialUse>,
+    /// False for `\Noselect` / `\NonExistent` placeholders in the hierarchy.
+    pub selectable: bool,
+}
+
+impl Folder
+{
+    pub fn is_inbox(&self) -> bool
+    {
+        self.name.eq_ignore_ascii_case("INBOX")
+    }
+}
+
+/// RFC 6154 SPECIAL-USE roles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpecialUse
+{
+    Drafts,
+    Sent,
+    Archive,
+    All,
+    Flagged,
+    Junk,
+    Trash,
+}
+
+/// `(uid, flags)` as returned by `ImapClient::fetch_flags`.
+pub type FlagUpdate = (u32, Vec<String>);
+
+/// `(uid, thread id)` as returned by `ImapClient::thread_ids`.
+pub type ThreadUpdate = (u32, u64);
+
+/// Result of `STATUS`, used to decide how much of a mailbox needs syncing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MailboxStatus
+{
+    pub uid_validity: u32,
+    pub uid_next: u32,
+    pub messages: u32,
+    /// Only reported by CONDSTORE servers.
+    pub highest_modseq: Option<u64>,
 }
@@ -1,8 +1,14 @@
-use mailparse::ParsedMail;
-use std::error::Error;
+use html2text::from_read;
+use chrono::{DateTime, FixedOffset};
+use mailparse::{
+    addrparse_header, dateparse, parse_headers, parse_mail, DispositionType, MailAddr, MailHeader, MailHeaderMap,
+    ParsedMail, SingleInfo,
+};
+use crate::imap::error::ImapError;
+use crate::imap::models::{Address, Disposition, MailDetail, MimePart};
 
 
-pub fn find_plain(mail: &ParsedMail) -> Result<Option<String>, Box<dyn Error>> 
+pub fn find_plain(mail: &ParsedMail) -> Result<Option<String>, ImapError> 
 {
     if mail.ctype.mimetype.eq_ignore_ascii_case("text/plain") 
     {
@@ -17,7 +23,7 @@ pub fn find_plain(mail: &ParsedMail) -> Result<Option<String>, Box<dyn Error>>
     }
     Ok(None)
 }
-pub fn find_html(mail: &ParsedMail) -> Result<Option<String>, Box<dyn Error>> 
+pub fn find_html(mail: &ParsedMail) -> Result<Option<String>, ImapError> 
 {
     if mail.ctype.mimetype.eq_ignore_ascii_case("text/html") 
     {
@@ -33,3 +39,251 @@ pub fn find_html(mail: &ParsedMail) -> Result<Option<String>, Box<dyn Error>>
     Ok(None)
 }
 
+/// Decoded headers of a message, or of a `BODY[HEADER.FIELDS (…)]` fetch.
+/// Folded lines are joined and RFC 2047 encoded words (`=?UTF-8?B?…?=`)
+/// decoded from their charset; names are matched case-insensitively and
+/// the first header of a name wins.
+#[derive(Default)]
+pub struct Headers<'a>
+{
+    headers: Vec<MailHeader<'a>>,
+}
+
+impl<'a> Headers<'a>
+{
+    /// Parses the header block at the start of `raw`, up to the first blank
+    /// line; a whole message may be passed.
+    pub fn parse(raw: &'a [u8]) -> Result<Self, ImapError>
+    {
+        let (headers, _) = parse_headers(raw)?;
+        Ok(Self { headers })
+    }
+
+    pub fn get(&self, name: &str) -> Option<String>
+    {
+        self.headers.get_first_value(name).map(|value| value.trim().to_string())
+    }
+
+    /// The mailboxes of an address header such as `From:` or `Cc:`, the
+    /// members of a group (`team: a@x, b@y;`) included. A value that is
+    /// not a valid address list is kept whole as one address.
+    pub fn addresses(&self, name: &str) -> Vec<Address>
+    {
+        let Some(header) = self.headers.get_first_header(name)
+        else
+        {
+            return Vec::new();
+        };
+        let single = |info: SingleInfo| Address
+        {
+            name: info.display_name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty()),
+            address: info.addr,
+        };
+        match addrparse_header(header)
+        {
+            Ok(list) => list
+                .into_inner()
+                .into_iter()
+                .flat_map(|addr| match addr
+                {
+                    MailAddr::Single(info) => vec![single(info)],
+                    MailAddr::Group(group) => group.addrs.into_iter().map(single).collect(),
+                })
+                .collect(),
+            Err(_) =>
+            {
+                let value = header.get_value();
+                let value = value.trim();
+                if value.is_empty()
+                {
+                    Vec::new()
+                }
+                else
+                {
+                    vec![Address { name: None, address: value.to_string() }]
+                }
+            }
+        }
+    }
+
+    /// Every header as `(name, decoded value)`, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (String, String)> + '_
+    {
+        self.headers.iter().map(|h| (h.get_key(), h.get_value().trim().to_string()))
+    }
+
+    /// `Date:` with its offset; a date only `mailparse` can make sense of
+    /// is taken as UTC.
+    pub fn date(&self) -> Option<DateTime<FixedOffset>>
+    {
+        let value = self.get("Date")?;
+        DateTime::parse_from_rfc2822(&value).ok().or_else(||
+        {
+            let utc = DateTime::from_timestamp(dateparse(&value).ok()?, 0)?;
+            Some(utc.fixed_offset())
+        })
+    }
+
+    /// The ids of a `Message-ID:`, `In-Reply-To:` or `References:` header,
+    /// without angle brackets.
+    pub fn message_ids(&self, name: &str) -> Vec<String>
+    {
+        self.get(name).map(|value| message_ids(&value)).unwrap_or_default()
+    }
+}
+
+/// `<a@x> <b@y>` gives `a@x` and `b@y`.
+fn message_ids(value: &str) -> Vec<String>
+{
+    value
+        .split('<')
+        .skip(1)
+        .filter_map(|part| part.split_once('>'))
+        .map(|(id, _)| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect()
+}
+
+/// Whether a message with this `Content-Type:` likely has attachments.
+/// Before the body is downloaded only the top-level type is known, and
+/// `multipart/mixed` is what mail with attachments is sent as.
+pub fn has_attachments(content_type: &str) -> bool
+{
+    content_type
+        .split(';')
+        .next()
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("multipart/mixed"))
+}
+
+/// Readable text of a raw message: the text/plain part, else the text/html
+/// part converted to text, else the raw source.
+pub fn render_body(raw: &[u8]) -> Result<String, ImapError>
+{
+    readable(&parse_mail(raw)?, raw)
+}
+
+fn readable(mail: &ParsedMail, raw: &[u8]) -> Result<String, ImapError>
+{
+    if let Some(txt) = find_plain(mail)? 
+    {
+        return Ok(txt);
+    }
+    if let Some(html) = find_html(mail)? 
+    {
+        return Ok(from_read(html.as_bytes(), 80));
+    }
+    Ok(String::from_utf8_lossy(raw).into_owned())
+}
+
+/// Everything the view pane and replies need from a raw message.
+pub fn mail_detail(uid: u32, raw: &[u8]) -> Result<MailDetail, ImapError>
+{
+    let mail = parse_mail(raw)?;
+    let headers = Headers::parse(raw)?;
+    let mut leaves = Vec::new();
+    leaf_parts(&mail, &mut leaves);
+    let parts = leaves
+        .into_iter()
+        .enumerate()
+        .map(|(index, part)| mime_part(index, part, !std::ptr::eq(part, &mail)))
+        .collect::<Result<_, _>>()?;
+    Ok(MailDetail
+    {
+        uid,
+        from: headers.addresses("From"),
+        to: headers.addresses("To"),
+        cc: headers.addresses("Cc"),
+        reply_to: headers.addresses("Reply-To"),
+        followup_to: headers.addresses("Mail-Followup-To"),
+        subject: headers.get("Subject").unwrap_or_default(),
+        date: headers.date(),
+        message_id: headers.message_ids("Message-ID").into_iter().next(),
+        in_reply_to: headers.message_ids("In-Reply-To"),
+        references: headers.message_ids("References"),
+        list_headers: headers.iter().filter(|(name, _)| name.to_ascii_lowercase().starts_with("list-")).collect(),
+        body: readable(&mail, raw)?,
+        parts,
+    })
+}
+
+/// The decoded content of leaf `index` of a raw message (see
+/// `MimePart::index`), or `None` if it has fewer parts.
+pub fn part_data(raw: &[u8], index: usize) -> Result<Option<Vec<u8>>, ImapError>
+{
+    let mail = parse_mail(raw)?;
+    let mut leaves = Vec::new();
+    leaf_parts(&mail, &mut leaves);
+    match leaves.get(index)
+    {
+        Some(part) => Ok(Some(content(part, !std::ptr::eq(*part, &mail))?)),
+        None => Ok(None),
+    }
+}
+
+/// The parts of `mail` that are not multiparts themselves, depth first.
+fn leaf_parts<'a>(mail: &'a ParsedMail<'a>, leaves: &mut Vec<&'a ParsedMail<'a>>)
+{
+    if mail.subparts.is_empty()
+    {
+        leaves.push(mail);
+    }
+    for sub in &mail.subparts
+    {
+        leaf_parts(sub, leaves);
+    }
+}
+
+/// The decoded content of a leaf. `mailparse` leaves the line break before
+/// the next boundary on the end of a `nested` part, where it belongs to the
+/// boundary (RFC 2046), so it is taken off again; base64 ignores it anyway.
+fn content(part: &ParsedMail, nested: bool) -> Result<Vec<u8>, ImapError>
+{
+    let mut data = part.get_body_raw()?;
+    let base64 = part
+        .headers
+        .get_first_value("Content-Transfer-Encoding")
+        .is_some_and(|encoding| encoding.trim().eq_ignore_ascii_case("base64"));
+    if nested && !base64
+    {
+        if data.ends_with(b"\r\n")
+        {
+            data.truncate(data.len() - 2);
+        }
+        else if data.ends_with(b"\n")
+        {
+            data.pop();
+        }
+    }
+    Ok(data)
+}
+
+fn mime_part(index: usize, part: &ParsedMail, nested: bool) -> Result<MimePart, ImapError>
+{
+    let disposition = part.get_content_disposition();
+    let filename = disposition
+        .params
+        .get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    let content_id = part
+        .headers
+        .get_first_value("Content-ID")
+        .and_then(|value| message_ids(&value).into_iter().next().or_else(|| Some(value.trim().to_string())))
+        .filter(|id| !id.is_empty());
+    let disposition = match part.headers.get_first_header("Content-Disposition")
+    {
+        None => None,
+        Some(_) if disposition.disposition == DispositionType::Attachment => Some(Disposition::Attachment),
+        Some(_) => Some(Disposition::Inline),
+    };
+    Ok(MimePart
+    {
+        index,
+        content_type: part.ctype.mimetype.to_ascii_lowercase(),
+        filename,
+        size: content(part, nested)?.len(),
+        content_id,
+        disposition,
+    })
+}
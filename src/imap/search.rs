@@ -0,0 +1,199 @@
+use crate::imap::client::astring;
+use crate::imap::error::ImapError;
+
+use chrono::NaiveDate;
+
+/// One condition of a search. All conditions of a query must hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Term
+{
+    From(String),
+    To(String),
+    Subject(String),
+    Body(String),
+    /// Anywhere in the headers or the body (a word without a `key:`).
+    Text(String),
+    /// Received on or after this day.
+    Since(NaiveDate),
+    /// Received before this day.
+    Before(NaiveDate),
+    Unread,
+    Read,
+    Flagged,
+    /// Larger than this many bytes.
+    Larger(u32),
+}
+
+/// A search typed into the `/` prompt, e.g.
+/// `from:alice subject:"big invoice" after:2025-01-01 is:unread`.
+///
+/// | key                                | meaning                                  |
+/// |------------------------------------|------------------------------------------|
+/// | `from:` `to:`                      | address or name contains the value       |
+/// | `subject:` `body:`                 | subject or body text contains the value  |
+/// | `after:` `since:`                  | received on or after a `YYYY-MM-DD` date |
+/// | `before:`                          | received before a `YYYY-MM-DD` date      |
+/// | `is:unread` `is:read` `is:flagged` | by status                                |
+/// | `larger:`                          | size in bytes, or with a `k`/`m` suffix  |
+///
+/// Anything else is searched for in the whole message. Values with spaces
+/// go in double quotes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchQuery
+{
+    pub terms: Vec<Term>,
+}
+
+impl SearchQuery
+{
+    pub fn parse(query: &str) -> Result<Self, ImapError>
+    {
+        let mut terms = Vec::new();
+        for word in split_words(query)?
+        {
+            let (key, value) = match word.split_once(':')
+            {
+                Some((key, value)) if !value.is_empty() => (key.to_ascii_lowercase(), value.to_string()),
+                _ => (String::new(), word),
+            };
+            let term = match key.as_str()
+            {
+                "from" => Term::From(value),
+                "to" => Term::To(value),
+                "subject" => Term::Subject(value),
+                "body" => Term::Body(value),
+                "after" | "since" => Term::Since(date(&value)?),
+                "before" => Term::Before(date(&value)?),
+                "is" => match value.to_ascii_lowercase().as_str()
+                {
+                    "unread" => Term::Unread,
+                    "read" => Term::Read,
+                    "flagged" | "starred" => Term::Flagged,
+                    _ => return Err(ImapError::Query(format!("unknown status 'is:{}'", value))),
+                },
+                "larger" => Term::Larger(size(&value)?),
+                // `re:`, a URL, a time of day: just text
+                _ if key.is_empty() => Term::Text(value),
+                _ => Term::Text(format!("{}:{}", key, value)),
+            };
+            terms.push(term);
+        }
+        if terms.is_empty()
+        {
+            return Err(ImapError::Query("nothing to search for".into()));
+        }
+        Ok(Self { terms })
+    }
+
+    /// The query as `UID SEARCH` criteria (RFC 3501), e.g.
+    /// `FROM "alice" SINCE 1-Jan-2025 UNSEEN`. Words that are not ASCII go
+    /// in literals, of the LITERAL+ kind if `literal_plus` is set.
+    pub fn criteria(&self, literal_plus: bool) -> String
+    {
+        let mut out: Vec<String> = self
+            .terms
+            .iter()
+            .map(|term| match term
+            {
+                Term::From(s) => format!("FROM {}", astring(s, literal_plus)),
+                Term::To(s) => format!("TO {}", astring(s, literal_plus)),
+                Term::Subject(s) => format!("SUBJECT {}", astring(s, literal_plus)),
+                Term::Body(s) => format!("BODY {}", astring(s, literal_plus)),
+                Term::Text(s) => format!("TEXT {}", astring(s, literal_plus)),
+                Term::Since(d) => format!("SINCE {}", d.format("%-d-%b-%Y")),
+                Term::Before(d) => format!("BEFORE {}", d.format("%-d-%b-%Y")),
+                Term::Unread => "UNSEEN".to_string(),
+                Term::Read => "SEEN".to_string(),
+                Term::Flagged => "FLAGGED".to_string(),
+                Term::Larger(n) => format!("LARGER {}", n),
+            })
+            .collect();
+        if !out.iter().all(|c| c.is_ascii())
+        {
+            out.insert(0, "CHARSET UTF-8".to_string());
+        }
+        out.join(" ")
+    }
+
+    /// The query in Gmail's own search syntax, for `X-GM-RAW`.
+    pub fn gmail(&self) -> String
+    {
+        let phrase = |s: &str| if s.contains(' ') { format!("\"{}\"", s) } else { s.to_string() };
+        self.terms
+            .iter()
+            .map(|term| match term
+            {
+                Term::From(s) => format!("from:{}", phrase(s)),
+                Term::To(s) => format!("to:{}", phrase(s)),
+                Term::Subject(s) => format!("subject:{}", phrase(s)),
+                // Gmail has no body-only operator.
+                Term::Body(s) | Term::Text(s) => phrase(s),
+                Term::Since(d) => format!("after:{}", d.format("%Y/%m/%d")),
+                Term::Before(d) => format!("before:{}", d.format("%Y/%m/%d")),
+                Term::Unread => "is:unread".to_string(),
+                Term::Read => "is:read".to_string(),
+                Term::Flagged => "is:starred".to_string(),
+                Term::Larger(n) => format!("larger:{}", n),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Splits at spaces outside double quotes and drops the quotes, so
+/// `subject:"big invoice"` stays one word.
+fn split_words(query: &str) -> Result<Vec<String>, ImapError>
+{
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in query.chars()
+    {
+        match c
+        {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted =>
+            {
+                if !word.is_empty()
+                {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if quoted
+    {
+        return Err(ImapError::Query("unmatched '\"'".into()));
+    }
+    if !word.is_empty()
+    {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+fn date(value: &str) -> Result<NaiveDate, ImapError>
+{
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| ImapError::Query(format!("'{}' is not a date (YYYY-MM-DD)", value)))
+}
+
+fn size(value: &str) -> Result<u32, ImapError>
+{
+    let lower = value.to_ascii_lowercase();
+    let (digits, unit) = match lower.strip_suffix('k').or(lower.strip_suffix("kb"))
+    {
+        Some(digits) => (digits, 1024),
+        None => match lower.strip_suffix('m').or(lower.strip_suffix("mb"))
+        {
+            Some(digits) => (digits, 1024 * 1024),
+            None => (lower.as_str(), 1),
+        },
+    };
+    digits
+        .parse::<u32>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| ImapError::Query(format!("'{}' is not a size (e.g. 500k)", value)))
+}
@@ -0,0 +1,298 @@
+use crate::config::{Security, ServerConfig};
+use crate::imap::error::ImapError;
+
+use imap::extensions::idle::SetReadTimeout;
+use native_tls::{TlsConnector, TlsStream};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The transport underneath an IMAP session.
+///
+/// `imap::Session` is generic over its stream, so the client keeps one
+/// concrete type regardless of whether the account uses implicit TLS,
+/// STARTTLS or a plaintext connection to a local test server.
+///
+/// It also reads the server's lines on the crate's behalf, so untagged
+/// responses the crate cannot parse can be taken out first (see `Diverted`).
+#[derive(Debug)]
+pub struct ImapStream
+{
+    inner: Transport,
+    /// Applied to every read and write, so a connection that silently died
+    /// (e.g. dropped by a NAT box) fails instead of hanging forever.
+    read_timeout: Duration,
+    diverted: Arc<Mutex<Diverted>>,
+    /// Lines read from the server and let through, not yet handed out.
+    ready: Vec<u8>,
+    /// The start of a line whose end has not arrived yet.
+    partial: Vec<u8>,
+    /// Bytes of a `{n}` literal still to come, which are passed on as they
+    /// are rather than looked at as lines.
+    literal: usize,
+}
+
+/// Untagged responses the imap crate cannot parse, such as `* THREAD` or a
+/// FETCH with Gmail's `X-GM-THRID`: the crate gives up on the first line it
+/// does not understand and loses its place in the conversation. While a
+/// `keyword` is set, every untagged line containing it is kept here
+/// instead of being handed to the crate. The same goes for `+` continuation
+/// requests while `continuations` is set, which the crate only expects
+/// during APPEND and AUTHENTICATE.
+#[derive(Debug, Default)]
+pub struct Diverted
+{
+    pub keyword: Option<String>,
+    pub continuations: bool,
+    pub lines: Vec<u8>,
+}
+
+#[derive(Debug)]
+enum Transport
+{
+    Plain(TcpStream),
+    Tls(TlsStream<TcpStream>),
+}
+
+impl ImapStream
+{
+    /// Opens the TCP connection and secures it according to `server.security`.
+    ///
+    /// Returns the stream together with a flag telling whether the server
+    /// greeting has already been consumed (true after a STARTTLS upgrade).
+    pub fn connect(server: &ServerConfig) -> Result<(Self, bool), ImapError>
+    {
+        if server.security == Security::Plain && !server.is_localhost()
+        {
+            return Err(ImapError::Connect(format!(
+                "refusing unencrypted IMAP connection to non-local host {}",
+                server.host
+            )));
+        }
+        let tcp = connect_tcp(server)?;
+        let (inner, greeted) = match server.security
+        {
+            Security::Tls =>
+            {
+                let tls = TlsConnector::builder().build()?;
+                (Transport::Tls(tls.connect(&server.host, tcp)?), false)
+            }
+            Security::StartTls =>
+            {
+                let tls = TlsConnector::builder().build()?;
+                starttls_handshake(&tcp)?;
+                (Transport::Tls(tls.connect(&server.host, tcp)?), true)
+            }
+            Security::Plain => (Transport::Plain(tcp), false),
+        };
+        let stream = Self
+        {
+            inner,
+            read_timeout: server.timeouts.read,
+            diverted: Arc::default(),
+            ready: Vec::new(),
+            partial: Vec::new(),
+            literal: 0,
+        };
+        Ok((stream, greeted))
+    }
+
+    /// Where diverted lines end up; see `Diverted`.
+    pub fn diverted(&self) -> Arc<Mutex<Diverted>>
+    {
+        Arc::clone(&self.diverted)
+    }
+
+    /// Splits what was read into lines, keeping literals whole, and moves
+    /// the finished ones to `ready` unless they are diverted.
+    fn sift(&mut self, mut data: &[u8])
+    {
+        while !data.is_empty()
+        {
+            if self.literal > 0
+            {
+                let n = self.literal.min(data.len());
+                self.ready.extend_from_slice(&data[..n]);
+                self.literal -= n;
+                data = &data[n..];
+                continue;
+            }
+            let Some(end) = data.iter().position(|&b| b == b'\n')
+            else
+            {
+                self.partial.extend_from_slice(data);
+                return;
+            };
+            self.partial.extend_from_slice(&data[..=end]);
+            data = &data[end + 1..];
+            let line = std::mem::take(&mut self.partial);
+            self.literal = literal_length(&line);
+            if self.literal > 0 || !self.divert(&line)
+            {
+                self.ready.extend_from_slice(&line);
+            }
+        }
+    }
+
+    fn divert(&self, line: &[u8]) -> bool
+    {
+        let Ok(mut diverted) = self.diverted.lock() else { return false };
+        if diverted.continuations && line.starts_with(b"+")
+        {
+            return true;
+        }
+        let keyword = match &diverted.keyword
+        {
+            Some(keyword) if line.starts_with(b"* ") => keyword.to_ascii_uppercase(),
+            _ => return false,
+        };
+        if !String::from_utf8_lossy(line).to_ascii_uppercase().contains(&keyword)
+        {
+            return false;
+        }
+        diverted.lines.extend_from_slice(line);
+        true
+    }
+
+    fn tcp(&self) -> &TcpStream
+    {
+        match &self.inner
+        {
+            Transport::Plain(s) => s,
+            Transport::Tls(s) => s.get_ref(),
+        }
+    }
+}
+
+/// `n` if `line` ends with a `{n}` literal announcement.
+fn literal_length(line: &[u8]) -> usize
+{
+    let line = line.strip_suffix(b"\r\n").or_else(|| line.strip_suffix(b"\n")).unwrap_or(line);
+    let Some(open) = line.strip_suffix(b"}").and_then(|l| l.iter().rposition(|&b| b == b'{'))
+    else
+    {
+        return 0;
+    };
+    std::str::from_utf8(&line[open + 1..line.len() - 1]).ok().and_then(|n| n.trim_end_matches('+').parse().ok()).unwrap_or(0)
+}
+
+/// Connects to the first address of `server` that answers within the
+/// connect timeout, with read and write timeouts already set.
+fn connect_tcp(server: &ServerConfig) -> Result<TcpStream, ImapError>
+{
+    let mut last = None;
+    for addr in (server.host.as_str(), server.port).to_socket_addrs()?
+    {
+        match TcpStream::connect_timeout(&addr, server.timeouts.connect)
+        {
+            Ok(tcp) =>
+            {
+                tcp.set_read_timeout(Some(server.timeouts.read))?;
+                tcp.set_write_timeout(Some(server.timeouts.read))?;
+                return Ok(tcp);
+            }
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(ImapError::Io(last.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} did not resolve to any address", server.host))
+    })))
+}
+
+/// Reads the greeting and negotiates STARTTLS on a fresh plaintext socket.
+///
+/// This is done by hand rather than through `imap::Client::secure` because
+/// that returns a `Client<TlsStream<TcpStream>>`, not our `ImapStream`.
+fn starttls_handshake(tcp: &TcpStream) -> Result<(), ImapError>
+{
+    let mut reader = BufReader::new(tcp);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("* OK") && !line.starts_with("* PREAUTH")
+    {
+        return Err(ImapError::Connect(format!("unexpected IMAP greeting: {}", line.trim_end())));
+    }
+
+    let mut writer = tcp;
+    writer.write_all(b"a0 STARTTLS\r\n")?;
+    writer.flush()?;
+
+    loop
+    {
+        line.clear();
+        if reader.read_line(&mut line)? == 0
+        {
+            return Err(ImapError::ConnectionLost);
+        }
+        if let Some(status) = line.strip_prefix("a0 ")
+        {
+            if status.to_ascii_uppercase().starts_with("OK")
+            {
+                return Ok(());
+            }
+            return Err(ImapError::Connect(format!("STARTTLS rejected: {}", status.trim_end())));
+        }
+    }
+}
+
+impl Read for ImapStream
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        while self.ready.is_empty()
+        {
+            let mut chunk = [0; 8192];
+            let n = match &mut self.inner
+            {
+                Transport::Plain(s) => s.read(&mut chunk)?,
+                Transport::Tls(s) => s.read(&mut chunk)?,
+            };
+            if n == 0
+            {
+                // End of stream: whatever is left of a line goes out as it is.
+                self.ready.append(&mut self.partial);
+                break;
+            }
+            self.sift(&chunk[..n]);
+        }
+        let n = buf.len().min(self.ready.len());
+        buf[..n].copy_from_slice(&self.ready[..n]);
+        self.ready.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for ImapStream
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        match &mut self.inner
+        {
+            Transport::Plain(s) => s.write(buf),
+            Transport::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        match &mut self.inner
+        {
+            Transport::Plain(s) => s.flush(),
+            Transport::Tls(s) => s.flush(),
+        }
+    }
+}
+
+/// Lets `imap`'s IDLE handle wait with a timeout on our stream.
+///
+/// IDLE "clears" the timeout with `None` when it is done; that puts the
+/// configured read timeout back rather than letting reads block forever.
+impl SetReadTimeout for ImapStream
+{
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> imap::Result<()>
+    {
+        let timeout = timeout.unwrap_or(self.read_timeout);
+        self.tcp().set_read_timeout(Some(timeout)).map_err(imap::Error::Io)
+    }
+}
@@ -0,0 +1,156 @@
+use crate::imap::models::MailSummary;
+
+use std::collections::HashMap;
+
+/// Groups messages into conversations.
+///
+/// Messages the server has put in a conversation (`thread_id`, from Gmail's
+/// `X-GM-THRID` or the `THREAD` extension) are grouped by it. The others
+/// fall back to their headers: a message joins the thread of every message
+/// it names in `References:` or `In-Reply-To:`, and of every other message
+/// naming the same ids, so replies to a message that is not listed still
+/// end up together. A reply without either header (`Re: lunch` from some
+/// mobile clients) joins a message with the same subject, as long as one
+/// of the two was sent by someone the other was from or addressed to.
+///
+/// Returns index lists into `messages`, each thread and each message in
+/// it in the order of `messages`, which for a folder list means newest
+/// first.
+pub fn threads(messages: &[MailSummary]) -> Vec<Vec<usize>>
+{
+    let mut sets = Sets::new(messages.len());
+    let mut ids: HashMap<&str, usize> = HashMap::new();
+    let mut server: HashMap<u64, usize> = HashMap::new();
+    // Earlier messages by base subject, and whether each is a reply
+    // without references
+    let mut subjects: HashMap<String, Vec<(usize, bool)>> = HashMap::new();
+    for (i, m) in messages.iter().enumerate()
+    {
+        // Where the server has decided, the message's own references are
+        // not second-guessed; its id is still there for others to name.
+        let references = match m.thread_id
+        {
+            Some(thread) =>
+            {
+                let first = *server.entry(thread).or_insert(i);
+                sets.join(i, first);
+                &[][..]
+            }
+            None => &m.references[..],
+        };
+        for id in m.message_id.iter().chain(references)
+        {
+            let node = *ids.entry(id.as_str()).or_insert_with(|| sets.add());
+            sets.join(i, node);
+        }
+        let (subject, reply) = base_subject(&m.subject);
+        if subject.is_empty()
+        {
+            continue;
+        }
+        // A message the server placed is never loose, so two of those are
+        // not joined by subject.
+        let loose = reply && m.references.is_empty() && m.thread_id.is_none();
+        let earlier = subjects.entry(subject).or_default();
+        for &(j, other_loose) in earlier.iter()
+        {
+            if (loose || other_loose) && related(m, &messages[j])
+            {
+                sets.join(i, j);
+            }
+        }
+        earlier.push((i, loose));
+    }
+
+    let mut threads: Vec<Vec<usize>> = Vec::new();
+    let mut position: HashMap<usize, usize> = HashMap::new();
+    for i in 0..messages.len()
+    {
+        let root = sets.root(i);
+        match position.get(&root)
+        {
+            Some(&t) => threads[t].push(i),
+            None =>
+            {
+                position.insert(root, threads.len());
+                threads.push(vec![i]);
+            }
+        }
+    }
+    threads
+}
+
+/// Whether one of the two messages was sent by someone the other was from
+/// or addressed to.
+fn related(a: &MailSummary, b: &MailSummary) -> bool
+{
+    let involves = |m: &MailSummary, address: &str|
+    {
+        std::iter::once(&m.from).chain(&m.recipients).any(|p| p.eq_ignore_ascii_case(address))
+    };
+    involves(b, &a.from) || involves(a, &b.from)
+}
+
+/// The subject without `Re:`/`Fwd:` prefixes, lowercased, and whether it
+/// had one.
+fn base_subject(subject: &str) -> (String, bool)
+{
+    let mut rest = subject.trim();
+    let mut reply = false;
+    loop
+    {
+        let prefix = ["re:", "fwd:", "fw:", "aw:", "wg:"]
+            .iter()
+            .find(|p| rest.get(..p.len()).is_some_and(|head| head.eq_ignore_ascii_case(p)));
+        match prefix
+        {
+            Some(p) =>
+            {
+                rest = rest[p.len()..].trim_start();
+                reply = true;
+            }
+            None => return (rest.to_lowercase(), reply),
+        }
+    }
+}
+
+/// Union-find over messages (`0..count`) and the ids they mention.
+struct Sets
+{
+    parent: Vec<usize>,
+}
+
+impl Sets
+{
+    fn new(count: usize) -> Self
+    {
+        Self { parent: (0..count).collect() }
+    }
+
+    fn add(&mut self) -> usize
+    {
+        self.parent.push(self.parent.len());
+        self.parent.len() - 1
+    }
+
+    fn root(&mut self, mut node: usize) -> usize
+    {
+        while self.parent[node] != node
+        {
+            self.parent[node] = self.parent[self.parent[node]];
+            node = self.parent[node];
+        }
+        node
+    }
+
+    /// The root is always the lower node, so a thread's root is its first
+    /// message.
+    fn join(&mut self, a: usize, b: usize)
+    {
+        let (a, b) = (self.root(a), self.root(b));
+        if a != b
+        {
+            self.parent[a.max(b)] = a.min(b);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::imap::error::ImapError;
+use crate::imap::ImapClient;
+
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Timing for `spawn_watcher`.
+#[derive(Debug, Clone, Copy)]
+pub struct WatchConfig
+{
+    /// How long one IDLE lasts before it is renewed. Servers may drop
+    /// clients that stay idle for 30 minutes.
+    pub idle_renew: Duration,
+    /// NOOP polling interval for servers without IDLE.
+    pub poll_interval: Duration,
+    /// Pause before reconnecting after the connection failed.
+    pub retry_delay: Duration,
+}
+
+impl Default for WatchConfig
+{
+    fn default() -> Self
+    {
+        Self
+        {
+            idle_renew: Duration::from_secs(25 * 60),
+            poll_interval: Duration::from_secs(60),
+            retry_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+/// What the watcher reports to its callback.
+#[derive(Debug)]
+pub enum WatchEvent
+{
+    /// Messages arrived, were expunged or had their flags changed.
+    Changed,
+    /// The watch connection failed; it is retried after `retry_delay`.
+    Error(String),
+}
+
+/// Watches `mailbox` on a dedicated connection (IDLE where the server
+/// supports it, NOOP polling otherwise) and calls `on_event` for every
+/// change. The thread ends once `on_event` returns false.
+pub fn spawn_watcher<C, F>(connect: C, mailbox: String, config: WatchConfig, mut on_event: F) -> JoinHandle<()>
+where
+    C: Fn() -> Result<ImapClient, ImapError> + Send + 'static,
+    F: FnMut(WatchEvent) -> bool + Send + 'static,
+{
+    thread::spawn(move ||
+    {
+        let mut failed = false;
+        loop
+        {
+            match watch(&connect, &mailbox, &config, failed, &mut on_event)
+            {
+                Ok(()) => return,
+                Err(e) =>
+                {
+                    if !on_event(WatchEvent::Error(e.to_string()))
+                    {
+                        return;
+                    }
+                    failed = true;
+                    thread::sleep(config.retry_delay);
+                }
+            }
+        }
+    })
+}
+
+/// One connection's worth of watching. Returns Ok once `on_event` asks to stop.
+fn watch<C, F>(connect: &C, mailbox: &str, config: &WatchConfig, reconnected: bool, on_event: &mut F) -> Result<(), ImapError>
+where
+    C: Fn() -> Result<ImapClient, ImapError>,
+    F: FnMut(WatchEvent) -> bool,
+{
+    let mut imap = connect()?;
+    imap.select(mailbox)?;
+    let idle = imap.has_capability("IDLE")?;
+    let timeout = if idle { config.idle_renew } else { config.poll_interval };
+
+    // Anything may have happened while the connection was down.
+    if reconnected && !on_event(WatchEvent::Changed)
+    {
+        return Ok(());
+    }
+    loop
+    {
+        if imap.wait_for_change(idle, timeout)? && !on_event(WatchEvent::Changed)
+        {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,8 @@
+pub mod cache;
+pub mod compose;
+pub mod config;
+pub mod imap;
+pub mod oauth;
+pub mod smtp;
+pub mod ui;
+pub mod worker;
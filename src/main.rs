@@ -1,102 +1,93 @@
-mod config;
-mod imap;
-mod smtp;
-mod ui;
-
-use config::Config;
-use imap::ImapClient;
-use smtp::SmtpClient;
-use std::cell::RefCell;
+use bps_mail::cache::Cache;
+use bps_mail::config::{Config, CredentialStore, Secret};
+use bps_mail::oauth::OAuthClient;
+use bps_mail::ui::App;
+use bps_mail::worker::Worker;
 use std::error::Error;
-use std::rc::Rc;
-use ui::App;
 
-fn main() -> Result<(), Box<dyn Error>> 
+/// Command-line options: `[--account NAME] [--set-password ACCOUNT]`.
+#[derive(Default)]
+struct Args
 {
-    let cfg = Config::from_env();
-
-    let imap = Rc::new(RefCell::new(ImapClient::connect(
-        &cfg.imap_user,
-        &cfg.imap_pass,
-    )?));
-
-    let smtp = Rc::new(RefCell::new(SmtpClient::connect(
-        &cfg.smtp_user,
-        &cfg.smtp_pass,
-    )?));
-
-    let mut inbox_count: usize = 20;
-    let initial_items = 
-    {
-        let mut imap_ref = imap.borrow_mut();
-       let summaries = imap_ref.fetch_inbox(inbox_count)?;
-        summaries
-            .into_iter()
-            .map(|ms| 
-            {
-                let date_str = ms.date.format("%a, %e %b %Y %T %z").to_string();
-                (ms.uid, format!("{}    {}", ms.from, date_str))
-            })
-            .collect::<Vec<(u32, String)>>()
-    };
+    account: Option<String>,
+    set_password: Option<String>,
+}
 
-    let imap_for_view = Rc::clone(&imap);
-    let on_view = move |uid: u32| -> Result<String, Box<dyn Error>> 
+fn parse_args() -> Result<Args, Box<dyn Error>>
+{
+    const USAGE: &str = "usage: bps_mail [--account NAME] [--set-password ACCOUNT]";
+    let mut args = std::env::args().skip(1);
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next()
     {
-        let mut imap_ref = imap_for_view.borrow_mut();
-        let (from_val, subject_val, date_val) = imap_ref.fetch_headers(uid)?;
-        let body_text = imap_ref.fetch_body(uid)?;
-        let combined = format!(
-            "From: {}\nSubject: {}\nDate: {}\n\n{}",
-            from_val, subject_val, date_val, body_text
-        );
-        Ok(combined)
-    };
+        if arg == "--account" || arg == "-a"
+        {
+            parsed.account = Some(args.next().ok_or("--account needs an account name")?);
+        }
+        else if let Some(name) = arg.strip_prefix("--account=")
+        {
+            parsed.account = Some(name.to_string());
+        }
+        else if arg == "--set-password"
+        {
+            parsed.set_password = Some(args.next().ok_or("--set-password needs an account name")?);
+        }
+        else
+        {
+            return Err(format!("unknown argument '{}' ({})", arg, USAGE).into());
+        }
+    }
+    Ok(parsed)
+}
 
-    let imap_for_refresh = Rc::clone(&imap);
-    let on_refresh = move |new_count: usize| 
+/// Stores an account password in the encrypted credentials file, creating
+/// the file (and choosing its master passphrase) on first use.
+fn set_password(account: &str) -> Result<(), Box<dyn Error>>
+{
+    let path = CredentialStore::default_path().ok_or("cannot locate a data directory (is $HOME set?)")?;
+    let passphrase = Secret::new(rpassword::prompt_password("Master passphrase: ")?);
+    if !path.exists()
     {
-        let mut imap_ref = imap_for_refresh.borrow_mut();
-        let summaries = imap_ref.fetch_inbox(new_count)?;
-        Ok(summaries
-            .into_iter()
-            .map(|ms| {
-                let date_str = ms.date.format("%a, %e %b %Y %T %z").to_string();
-                (ms.uid, format!("{}    {}", ms.from, date_str))
-            })
-            .collect::<Vec<(u32, String)>>())
-    };
+        let again = Secret::new(rpassword::prompt_password("Repeat master passphrase: ")?);
+        if again != passphrase
+        {
+            return Err("passphrases do not match".into());
+        }
+    }
+    let mut store = CredentialStore::open(&path, &passphrase)?;
+    let password = Secret::new(rpassword::prompt_password(format!("Password for '{}': ", account))?);
+    store.set(account, password);
+    store.save()?;
+    println!("Stored password for '{}' in {}", account, path.display());
+    Ok(())
+}
 
-    let imap_for_delete = Rc::clone(&imap);
-    let on_delete = move |uid: u32| 
+fn main() -> Result<(), Box<dyn Error>> 
+{
+    let args = parse_args()?;
+    if let Some(account) = &args.set_password
     {
-        let mut imap_ref = imap_for_delete.borrow_mut();
-        imap_ref.delete_message(uid)
-    };
+        return set_password(account);
+    }
+    let cfg = Config::load(args.account.as_deref())?;
 
-    let smtp_for_send = Rc::clone(&smtp);
-    let on_send = move |to: &str, subject: &str, body: &str| 
+    // OAuth2 accounts may need to print an authorization URL, so the first
+    // token is obtained before the TUI takes over the terminal. Later ones
+    // are refreshed by the worker's connections as they expire.
+    let tokens = match OAuthClient::for_account(&cfg)?
     {
-        let email = lettre::Message::builder()
-            .from(cfg.smtp_user.parse()?)
-            .to(to.parse()?)
-            .subject(subject)
-            .body(body.to_string())?;
-        let smtp_ref = smtp_for_send.borrow();
-        smtp_ref.send(email)?;
-        Ok(())
+        Some(oauth) =>
+        {
+            oauth.access_token()?;
+            Some(oauth.into_source())
+        }
+        None => None,
     };
 
-    let mut app = App::new
-    (
-        initial_items,
-        on_view,
-        on_send,
-        on_refresh,
-        on_delete,
-        inbox_count,
-        String::new(),
-    );
-    app.run()
+    // The UI starts from the local cache; the worker's threads fill it in
+    // and do every other network round trip in the background.
+    let cache_path = Cache::default_path(&cfg.name).ok_or("cannot locate a data directory (is $HOME set?)")?;
+    let inbox = cfg.folders.inbox.clone();
+    let worker = Worker::start(cfg, tokens, cache_path)?;
+    App::new(worker, inbox, 20).run()
 }
-
@@ -0,0 +1,278 @@
+use crate::config::{data_dir, Config, OAuth2Config};
+use crate::oauth::token::StoredToken;
+use crate::oauth::TokenSource;
+
+use oauth2::basic::{BasicClient, BasicErrorResponseType, BasicTokenResponse};
+use oauth2::reqwest::http_client;
+use oauth2::url::Url;
+use oauth2::{
+    AuthType, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, DeviceAuthorizationUrl,
+    PkceCodeChallenge, RedirectUrl, RefreshToken, RequestTokenError, Scope,
+    StandardDeviceAuthorizationResponse, TokenResponse, TokenUrl,
+};
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const REAUTHORIZE: &str = "the OAuth2 authorization has expired; restart bps_mail to authorize again";
+
+/// Obtains, refreshes and persists the XOAUTH2 access token of one account.
+pub struct OAuthClient
+{
+    config: OAuth2Config,
+    token_path: PathBuf,
+}
+
+impl OAuthClient
+{
+    pub fn new(config: OAuth2Config, token_path: PathBuf) -> Self
+    {
+        Self { config, token_path }
+    }
+
+    /// Client for an account with an `[oauth2]` table, or `None` for password accounts.
+    pub fn for_account(cfg: &Config) -> Result<Option<Self>, Box<dyn Error>>
+    {
+        let Some(oauth) = &cfg.oauth2 else { return Ok(None) };
+        let dir = data_dir().ok_or("cannot locate a data directory for OAuth tokens (is $HOME set?)")?;
+        let path = dir.join("tokens").join(format!("{}.json", cfg.name));
+        Ok(Some(Self::new(oauth.clone(), path)))
+    }
+
+    /// Returns a usable access token: the stored one while it is fresh, else a
+    /// refreshed one, else one from an interactive authorization.
+    pub fn access_token(&self) -> Result<String, Box<dyn Error>>
+    {
+        self.token(true)
+    }
+
+    /// Shares the client between connections. Calls are serialized so two
+    /// threads finding the token stale do not both refresh it, and they never
+    /// fall back to an interactive authorization: the TUI owns the terminal
+    /// by then, so a rejected refresh token is reported as an error instead.
+    pub fn into_source(self) -> TokenSource
+    {
+        let client = Mutex::new(self);
+        Arc::new(move ||
+        {
+            let client = client.lock().map_err(|_| "OAuth2 token source poisoned")?;
+            client.token(false).map_err(|e| e.to_string().into())
+        })
+    }
+
+    fn token(&self, interactive: bool) -> Result<String, Box<dyn Error>>
+    {
+        let stored = StoredToken::load(&self.token_path)?;
+        if let Some(tok) = stored.as_ref().filter(|t| t.is_fresh())
+        {
+            return Ok(tok.access_token.clone());
+        }
+
+        let token = match stored.and_then(|t| t.refresh_token)
+        {
+            Some(rt) => match self.refresh(&rt)
+            {
+                Ok(tok) => tok,
+                // The refresh token was revoked or expired: start over.
+                Err(RefreshError::InvalidGrant) if interactive => self.authorize()?,
+                Err(RefreshError::InvalidGrant) => return Err(REAUTHORIZE.into()),
+                Err(RefreshError::Other(e)) => return Err(e),
+            },
+            None if interactive => self.authorize()?,
+            None => return Err(REAUTHORIZE.into()),
+        };
+        token.save(&self.token_path)?;
+        Ok(token.access_token)
+    }
+
+    /// Exchanges a refresh token for a new access token. Providers that do not
+    /// rotate refresh tokens omit it from the response, so the old one is kept.
+    pub fn refresh(&self, refresh_token: &str) -> Result<StoredToken, RefreshError>
+    {
+        let client = self.client(None).map_err(RefreshError::Other)?;
+        let resp = client
+            .exchange_refresh_token(&RefreshToken::new(refresh_token.to_string()))
+            .add_scopes(self.scopes())
+            .request(http_client)
+            .map_err(|e| match e
+            {
+                RequestTokenError::ServerResponse(ref r) if *r.error() == BasicErrorResponseType::InvalidGrant =>
+                {
+                    RefreshError::InvalidGrant
+                }
+                other => RefreshError::Other(other.into()),
+            })?;
+        Ok(stored(&resp, Some(refresh_token)))
+    }
+
+    /// Runs the device flow when `device_auth_url` is configured, otherwise the
+    /// authorization-code flow with a loopback redirect.
+    pub fn authorize(&self) -> Result<StoredToken, Box<dyn Error>>
+    {
+        if self.config.device_auth_url.is_some()
+        {
+            self.device_flow(|uri, code|
+            {
+                eprintln!("To authorize bps_mail, visit {} and enter the code {}", uri, code);
+            })
+        }
+        else
+        {
+            self.authorization_code_flow()
+        }
+    }
+
+    /// RFC 8628 device authorization: `show(verification_uri, user_code)` is
+    /// called once, then the token endpoint is polled until the user approves.
+    pub fn device_flow(&self, show: impl FnOnce(&str, &str)) -> Result<StoredToken, Box<dyn Error>>
+    {
+        let client = self.client(None)?;
+        let details: StandardDeviceAuthorizationResponse = client
+            .exchange_device_code()?
+            .add_scopes(self.scopes())
+            .request(http_client)?;
+        show(details.verification_uri(), details.user_code().secret());
+
+        let resp = client
+            .exchange_device_access_token(&details)
+            .request(http_client, std::thread::sleep, None)?;
+        Ok(stored(&resp, None))
+    }
+
+    /// Authorization-code flow with PKCE: prints the consent URL and waits for
+    /// the browser to be redirected to a listener on the loopback interface.
+    pub fn authorization_code_flow(&self) -> Result<StoredToken, Box<dyn Error>>
+    {
+        let listener = match &self.config.redirect_url
+        {
+            Some(url) =>
+            {
+                let url = Url::parse(url)?;
+                let host = url.host_str().unwrap_or("127.0.0.1").to_string();
+                TcpListener::bind((host.as_str(), url.port().unwrap_or(80)))?
+            }
+            None => TcpListener::bind("127.0.0.1:0")?,
+        };
+        let redirect = match &self.config.redirect_url
+        {
+            Some(url) => url.clone(),
+            None => format!("http://127.0.0.1:{}/", listener.local_addr()?.port()),
+        };
+
+        let client = self.client(Some(redirect))?;
+        let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+        let (auth_url, csrf) = client
+            .authorize_url(CsrfToken::new_random)
+            .add_scopes(self.scopes())
+            .set_pkce_challenge(challenge)
+            .url();
+        eprintln!("To authorize bps_mail, open this URL in your browser:\n\n{}\n", auth_url);
+
+        let (code, state) = wait_for_redirect(&listener)?;
+        if state != *csrf.secret()
+        {
+            return Err("OAuth2 redirect carried an unexpected state parameter".into());
+        }
+
+        let resp = client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(verifier)
+            .request(http_client)?;
+        Ok(stored(&resp, None))
+    }
+
+    fn scopes(&self) -> Vec<Scope>
+    {
+        self.config.scopes.iter().cloned().map(Scope::new).collect()
+    }
+
+    fn client(&self, redirect: Option<String>) -> Result<BasicClient, Box<dyn Error>>
+    {
+        let mut client = BasicClient::new(
+            ClientId::new(self.config.client_id.clone()),
+            self.config.client_secret.clone().map(ClientSecret::new),
+            AuthUrl::new(self.config.auth_url.clone())?,
+            Some(TokenUrl::new(self.config.token_url.clone())?),
+        )
+        .set_auth_type(AuthType::RequestBody);
+        if let Some(url) = &self.config.device_auth_url
+        {
+            client = client.set_device_authorization_url(DeviceAuthorizationUrl::new(url.clone())?);
+        }
+        if let Some(url) = redirect
+        {
+            client = client.set_redirect_uri(RedirectUrl::new(url)?);
+        }
+        Ok(client)
+    }
+}
+
+/// Why a refresh failed; `InvalidGrant` means a new authorization is needed.
+#[derive(Debug)]
+pub enum RefreshError
+{
+    InvalidGrant,
+    Other(Box<dyn Error>),
+}
+
+impl std::fmt::Display for RefreshError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            RefreshError::InvalidGrant => write!(f, "refresh token was rejected (invalid_grant)"),
+            RefreshError::Other(e) => write!(f, "token refresh failed: {}", e),
+        }
+    }
+}
+
+impl Error for RefreshError {}
+
+fn stored(resp: &BasicTokenResponse, previous_refresh: Option<&str>) -> StoredToken
+{
+    StoredToken
+    {
+        access_token: resp.access_token().secret().clone(),
+        refresh_token: resp
+            .refresh_token()
+            .map(|t| t.secret().clone())
+            .or_else(|| previous_refresh.map(String::from)),
+        expires_at: resp
+            .expires_in()
+            .map(|d| chrono::Utc::now().timestamp() + d.as_secs() as i64),
+    }
+}
+
+/// Accepts one HTTP request on the redirect listener and pulls `code` and
+/// `state` out of its query string.
+fn wait_for_redirect(listener: &TcpListener) -> Result<(String, String), Box<dyn Error>>
+{
+    let (stream, _) = listener.accept()?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let target = request_line.split_whitespace().nth(1).ok_or("malformed OAuth2 redirect")?;
+    let url = Url::parse(&format!("http://localhost{}", target))?;
+    let param = |name: &str| url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned());
+
+    let mut writer = &stream;
+    let reply = "You can close this window and return to bps_mail.";
+    write!(
+        writer,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        reply.len(),
+        reply
+    )?;
+
+    if let Some(err) = param("error")
+    {
+        return Err(format!("authorization denied: {}", err).into());
+    }
+    let code = param("code").ok_or("OAuth2 redirect did not include a code")?;
+    let state = param("state").unwrap_or_default();
+    Ok((code, state))
+}
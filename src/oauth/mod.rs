@@ -0,0 +1,20 @@
+pub mod client;
+pub mod token;
+
+pub use client::{OAuthClient, RefreshError};
+pub use token::StoredToken;
+
+use std::error::Error;
+use std::sync::Arc;
+
+/// Hands out a usable access token each time it is called. Every IMAP login
+/// and SMTP send of an OAuth2 account asks it again, so an expired token is
+/// refreshed instead of replayed.
+pub type TokenSource = Arc<dyn Fn() -> Result<String, Box<dyn Error + Send + Sync>> + Send + Sync>;
+
+/// The SASL XOAUTH2 initial response (before base64), as used by both
+/// IMAP `AUTHENTICATE XOAUTH2` and SMTP `AUTH XOAUTH2`.
+pub fn xoauth2_response(user: &str, access_token: &str) -> String
+{
+    format!("user={}\x01auth=Bearer {}\x01\x01", user, access_token)
+}
@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Seconds before the real expiry at which a token is treated as stale, so a
+/// token is never handed out moments before the server starts rejecting it.
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+/// An access token as persisted in `<data_dir>/tokens/<account>.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredToken
+{
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// Unix timestamp; `None` when the server did not say.
+    pub expires_at: Option<i64>,
+}
+
+impl StoredToken
+{
+    pub fn is_fresh(&self) -> bool
+    {
+        match self.expires_at
+        {
+            Some(at) => at - EXPIRY_MARGIN_SECS > chrono::Utc::now().timestamp(),
+            None => true,
+        }
+    }
+
+    /// Returns `Ok(None)` when no token has been stored yet.
+    pub fn load(path: &Path) -> Result<Option<Self>, Box<dyn Error>>
+    {
+        match fs::read_to_string(path)
+        {
+            Ok(text) => Ok(Some(serde_json::from_str(&text)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes the token, readable by the current user only.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>>
+    {
+        if let Some(dir) = path.parent()
+        {
+            fs::create_dir_all(dir)?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+}
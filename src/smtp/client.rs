@@ -1,29 +1,102 @@
+use crate::config::{Secret, ServerConfig, Security};
+use crate::oauth::TokenSource;
+use crate::smtp::error::SmtpError;
+
 use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::transport::smtp::client::{Tls, TlsParameters};
 use lettre::{Message, Transport};
-use lettre::transport::smtp::SmtpTransport;
-use std::error::Error;
+use lettre::transport::smtp::{SmtpTransport, SmtpTransportBuilder};
 
-pub struct SmtpClient 
+pub struct SmtpClient
 {
     mailer: SmtpTransport,
+    /// For OAuth2 accounts: where tokens come from and the one `mailer`
+    /// was built with, so `send` can rebuild it once that one expires.
+    oauth: Option<OAuthState>,
 }
 
-impl SmtpClient 
+struct OAuthState
 {
-   pub fn connect(user: &str, pass: &str) -> Result<Self, Box<dyn Error>> 
+    server: ServerConfig,
+    user: String,
+    tokens: TokenSource,
+    token: Secret,
+}
+
+impl SmtpClient
+{
+   pub fn connect(server: &ServerConfig, user: &str, pass: &str) -> Result<Self, SmtpError>
    {
         let creds = Credentials::new(user.into(), pass.into());
-        let mailer = SmtpTransport::starttls_relay("smtp.gmail.com")?
+        let mailer = Self::transport(server)?
             .credentials(creds)
             .authentication(vec![Mechanism::Plain])
             .build();
-        Ok(Self { mailer })
+        Ok(Self { mailer, oauth: None })
+    }
+
+   /// Like `connect`, but authenticates with `AUTH XOAUTH2` and an OAuth2 access token.
+   pub fn connect_xoauth2(server: &ServerConfig, user: &str, access_token: &str) -> Result<Self, SmtpError>
+   {
+        Ok(Self { mailer: Self::xoauth2_transport(server, user, access_token)?, oauth: None })
+    }
+
+   /// Like `connect_xoauth2`, but asks `tokens` before every send and
+   /// rebuilds the transport whenever the token has changed.
+   pub fn connect_oauth2(server: &ServerConfig, user: &str, tokens: TokenSource) -> Result<Self, SmtpError>
+   {
+        let token = tokens().map_err(|e| SmtpError::Token(e.to_string()))?;
+        let mailer = Self::xoauth2_transport(server, user, &token)?;
+        let oauth = OAuthState { server: server.clone(), user: user.to_string(), tokens, token: Secret::new(token) };
+        Ok(Self { mailer, oauth: Some(oauth) })
     }
 
-   pub fn send(&self, email: Message) -> Result<(), Box<dyn Error>> 
+   fn xoauth2_transport(server: &ServerConfig, user: &str, access_token: &str) -> Result<SmtpTransport, SmtpError>
    {
+        let creds = Credentials::new(user.into(), access_token.into());
+        Ok(Self::transport(server)?
+            .credentials(creds)
+            .authentication(vec![Mechanism::Xoauth2])
+            .build())
+    }
+
+   fn transport(server: &ServerConfig) -> Result<SmtpTransportBuilder, SmtpError>
+   {
+        let tls = match server.security
+        {
+            Security::Tls => Tls::Wrapper(TlsParameters::new(server.host.clone())?),
+            Security::StartTls => Tls::Required(TlsParameters::new(server.host.clone())?),
+            Security::Plain =>
+            {
+                if !server.is_localhost()
+                {
+                    return Err(SmtpError::Insecure(format!(
+                        "refusing unencrypted SMTP connection to non-local host {}",
+                        server.host
+                    )));
+                }
+                Tls::None
+            }
+        };
+
+        Ok(SmtpTransport::builder_dangerous(&server.host)
+            .port(server.port)
+            .tls(tls))
+    }
+
+   pub fn send(&mut self, email: Message) -> Result<(), SmtpError>
+   {
+        if let Some(oauth) = &mut self.oauth
+        {
+            let token = (oauth.tokens)().map_err(|e| SmtpError::Token(e.to_string()))?;
+            if token != oauth.token.expose()
+            {
+                // Pooled connections authenticated with the old token go with the old transport.
+                self.mailer = Self::xoauth2_transport(&oauth.server, &oauth.user, &token)?;
+                oauth.token = Secret::new(token);
+            }
+        }
         self.mailer.send(&email)?;
         Ok(())
     }
 }
-
@@ -0,0 +1,81 @@
+use lettre::address::AddressError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Everything that can go wrong building or sending a message.
+#[derive(Debug)]
+pub enum SmtpError
+{
+    /// Plaintext SMTP to a host other than localhost.
+    Insecure(String),
+    /// A From/To address that does not parse.
+    Address { address: String, source: AddressError },
+    /// A message without To, Cc or Bcc.
+    NoRecipients,
+    /// A file to attach could not be read.
+    Attachment { path: PathBuf, source: io::Error },
+    /// The message could not be assembled (e.g. no recipient).
+    Message(lettre::error::Error),
+    /// The server could not be reached or refused the message.
+    Transport(lettre::transport::smtp::Error),
+    /// No OAuth2 access token could be obtained for the account.
+    Token(String),
+}
+
+impl SmtpError
+{
+    pub(crate) fn address(address: &str, source: AddressError) -> Self
+    {
+        SmtpError::Address { address: address.to_string(), source }
+    }
+}
+
+impl fmt::Display for SmtpError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            SmtpError::Insecure(msg) => write!(f, "{}", msg),
+            SmtpError::Address { address, source } => write!(f, "invalid address '{}': {}", address, source),
+            SmtpError::NoRecipients => write!(f, "no recipients"),
+            SmtpError::Attachment { path, source } => write!(f, "cannot attach {}: {}", path.display(), source),
+            SmtpError::Message(e) => write!(f, "cannot build message: {}", e),
+            SmtpError::Transport(e) => write!(f, "SMTP: {}", e),
+            SmtpError::Token(msg) => write!(f, "no OAuth2 access token: {}", msg),
+        }
+    }
+}
+
+impl Error for SmtpError
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)>
+    {
+        match self
+        {
+            SmtpError::Insecure(_) | SmtpError::NoRecipients | SmtpError::Token(_) => None,
+            SmtpError::Address { source, .. } => Some(source),
+            SmtpError::Attachment { source, .. } => Some(source),
+            SmtpError::Message(e) => Some(e),
+            SmtpError::Transport(e) => Some(e),
+        }
+    }
+}
+
+impl From<lettre::error::Error> for SmtpError
+{
+    fn from(e: lettre::error::Error) -> Self
+    {
+        SmtpError::Message(e)
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for SmtpError
+{
+    fn from(e: lettre::transport::smtp::Error) -> Self
+    {
+        SmtpError::Transport(e)
+    }
+}
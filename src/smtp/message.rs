@@ -0,0 +1,70 @@
+use crate::compose::mime_type;
+use crate::imap::Headers;
+use crate::smtp::error::SmtpError;
+
+use lettre::message::header::{ContentTransferEncoding, ContentType};
+use lettre::message::{Attachment, Body, MessageBuilder, MultiPart, SinglePart};
+use lettre::Message;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Finishes `builder` with a plain text `body`, as `multipart/mixed` with
+/// the files at `attachments` after it if there are any. The files are
+/// read now, so a missing one fails the message rather than sending it
+/// without.
+pub fn with_attachments(builder: MessageBuilder, body: String, attachments: &[PathBuf]) -> Result<Message, SmtpError>
+{
+    let parts = attachments.iter().map(|path| attachment(path)).collect::<Result<_, _>>()?;
+    with_parts(builder, body, parts)
+}
+
+/// `with_attachments` with the raw message `forwarded` attached after the
+/// files, as `message/rfc822` named after its subject.
+pub fn with_forwarded(builder: MessageBuilder, body: String, attachments: &[PathBuf], forwarded: &[u8]) -> Result<Message, SmtpError>
+{
+    let mut parts: Vec<SinglePart> = attachments.iter().map(|path| attachment(path)).collect::<Result<_, _>>()?;
+    parts.push(message_part(forwarded));
+    with_parts(builder, body, parts)
+}
+
+fn with_parts(builder: MessageBuilder, body: String, parts: Vec<SinglePart>) -> Result<Message, SmtpError>
+{
+    if parts.is_empty()
+    {
+        return Ok(builder.body(body)?);
+    }
+    let mixed = parts.into_iter().fold(MultiPart::mixed().singlepart(SinglePart::plain(body)), MultiPart::singlepart);
+    Ok(builder.multipart(mixed)?)
+}
+
+fn attachment(path: &Path) -> Result<SinglePart, SmtpError>
+{
+    let data = fs::read(path).map_err(|source| SmtpError::Attachment { path: path.to_path_buf(), source })?;
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "attachment".into());
+    // Every type `mime_type` returns is valid
+    let content_type = ContentType::parse(mime_type(path, &data)).unwrap_or(ContentType::TEXT_PLAIN);
+    Ok(Attachment::new(name).body(data, content_type))
+}
+
+/// A whole message as an attachment. RFC 2046 allows only 7bit and 8bit
+/// for `message/rfc822`, which is what most messages fit; one with overlong
+/// lines is sent base64 encoded rather than not at all.
+fn message_part(raw: &[u8]) -> SinglePart
+{
+    let subject = Headers::parse(raw).ok().and_then(|headers| headers.get("Subject")).unwrap_or_default();
+    let subject: String = subject.chars().map(|c| if "/\\".contains(c) || c.is_control() { '_' } else { c }).collect();
+    let name = match subject.trim()
+    {
+        "" => "forwarded.eml".to_string(),
+        subject => format!("{}.eml", subject),
+    };
+    let body = Body::new_with_encoding(raw.to_vec(), ContentTransferEncoding::SevenBit)
+        .or_else(|raw| Body::new_with_encoding(raw, ContentTransferEncoding::EightBit))
+        .unwrap_or_else(Body::new);
+    // A constant, valid type
+    let content_type = ContentType::parse("message/rfc822").unwrap_or(ContentType::TEXT_PLAIN);
+    Attachment::new(name).body(body, content_type)
+}
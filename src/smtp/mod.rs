@@ -1,2 +1,6 @@
 pub mod client;
+pub mod error;
+pub mod message;
 pub use client::SmtpClient;
+pub use error::SmtpError;
+pub use message::{with_attachments, with_forwarded};
@@ -14,166 +14,1095 @@ use ratatui::{
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout, Rect},
     style::{Modifier, Style},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    text::{Line, Span, Text},
+    widgets::{Block, Borders, Cell as TableCell, Clear, List, ListItem, ListState, Paragraph, Row, Table, TableState, Wrap},
     Terminal,
 };
-use std::{error::Error, io};
+use chrono::{DateTime, Datelike, FixedOffset, Local};
+use std::{
+    cell::Cell,
+    error::Error,
+    io, panic,
+    sync::Once,
+    time::{Duration, Instant},
+};
+
+use crate::cache::{snippet_parts, Hit};
+use crate::compose::{complete_path, edit_draft, editor_command, mailboxes, size_warning, Attachment, Draft, Response};
+use crate::imap::{threads, Address, Flag, MailDetail, MailSummary};
+use crate::worker::{Done, Event as WorkerEvent, Request, Worker};
 
 // Import TextArea<'static> from tui-textarea v0.7.0.
 // It must be <'static> so that &TextArea<'static> implements Widget for ratatui 0.29.
-use tui_textarea::TextArea;
+use tui_textarea::{CursorMove, TextArea};
 
 /// ——————— APPLICATION MODES —————————————————————————————————————————————
 ///
 /// We track which “screen” the user is on:
 ///   • Mode::Inbox        → show the list of message summaries.
-///   • Mode::View         → display the full content (headers + body) of one message.
-///   • Mode::Compose      → show “To / Subject / Body” input fields for sending mail.
+///   • Mode::Folders      → the folder sidebar has focus; Enter opens a folder.
+///   • Mode::View         → display the full content (headers + body) of one message,
+///                          or of a whole conversation with each message expandable.
+///   • Mode::Compose      → show “To / Cc / Bcc / Subject / Body” input fields for sending mail.
+///   • Mode::Attach       → typing the path of a file to attach after Ctrl+O in Compose.
 ///   • Mode::ConfirmDelete→ a temporary “Are you sure?” state after pressing ‘d’ once.
+///   • Mode::Search       → typing a search after ‘/’ in the status line.
 ///
 pub enum Mode {
     Inbox,
+    Folders,
     View,
     Compose,
+    Attach,
     ConfirmDelete,
+    Search,
 }
 
 /// ——————— COMPOSE SUB-FIELDS ——————————————————————————————————————————
 ///
-/// In Compose mode, we have three sub-fields:
-///   • ComposeField::To      → editing the “To:” line (single line).
+/// In Compose mode, we have five sub-fields:
+///   • ComposeField::To      → editing the “To:” line (single line, comma separated).
+///   • ComposeField::Cc      → editing the “Cc:” line (likewise).
+///   • ComposeField::Bcc     → editing the “Bcc:” line (likewise; not shown to anyone).
 ///   • ComposeField::Subject → editing the “Subject:” line (single line).
 ///   • ComposeField::Body    → editing the multiline body (TextArea).
 ///
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum ComposeField {
     To,
+    Cc,
+    Bcc,
     Subject,
     Body,
 }
 
-/// ——————— APP STATE & CALLBACKS —————————————————————————————————————————
+impl ComposeField {
+    /// The field Tab (and Enter on a one-line field) moves to.
+    fn next(self) -> Self {
+        match self {
+            ComposeField::To => ComposeField::Cc,
+            ComposeField::Cc => ComposeField::Bcc,
+            ComposeField::Bcc => ComposeField::Subject,
+            ComposeField::Subject => ComposeField::Body,
+            ComposeField::Body => ComposeField::To,
+        }
+    }
+
+    /// The field Shift+Tab moves to.
+    fn previous(self) -> Self {
+        match self {
+            ComposeField::To => ComposeField::Body,
+            ComposeField::Cc => ComposeField::To,
+            ComposeField::Bcc => ComposeField::Cc,
+            ComposeField::Subject => ComposeField::Bcc,
+            ComposeField::Body => ComposeField::Subject,
+        }
+    }
+}
+
+/// ——————— PENDING REQUESTS ————————————————————————————————————————————
+///
+/// What to do once the worker finishes the request we are waiting on:
+///   • Action::Open   → nothing; the message list is updated by `Messages` events.
+///   • Action::View   → show the message in View mode.
+///   • Action::Respond→ open Compose with the reply or forward the worker drafted.
+///   • Action::Flag   → nothing; the list is updated by `Messages` events.
+///   • Action::Delete → report where the message went (Trash, or gone for good).
+///   • Action::Archive→ report “Archived”.
+///   • Action::Undo   → report what was undone.
+///   • Action::Search → list the hits in place of a folder.
+///   • Action::Save   → report where the attachments were saved.
+///   • Action::Send   → leave Compose; on failure the draft stays open.
+///
+enum Action {
+    Open,
+    View,
+    Respond,
+    Flag,
+    Delete,
+    Archive,
+    Undo,
+    Search,
+    Save,
+    Send,
+}
+
+/// The one request the UI is waiting on, drawn as a spinner in the status line.
+struct Pending {
+    id: u64,
+    action: Action,
+    request: Request,  // kept to work out how to undo it
+    label: String,     // “Loading message”, “Opening Sent”, …
+    started: Instant,  // drives the spinner and the elapsed time
+}
+
+/// ——————— UNDO ——————————————————————————————————————————————————————————
+///
+/// A finished move, delete or flag change as the request that reverts it:
+/// moving the message back from where it went (under the UID the server
+/// reported), or flipping the flag again.
+///
+struct Undo {
+    label: String, // “move to Trash”, “flag change”
+    request: Request,
+}
+
+/// ——————— SEARCH RESULTS ————————————————————————————————————————————
+///
+/// While the message list shows search hits instead of a folder, each hit
+/// may live in a different folder; requests about a message go to the
+/// folder it was found in. Hits from the local index come with a snippet
+/// of the matching text, shown under the subject.
+///
+struct SearchResults {
+    query: String,
+    folders: Vec<String>,          // folder of each hit, in the order of `items`
+    snippets: Vec<Option<String>>, // matched words marked, see `snippet_parts`
+    previous: String,              // the folder Esc goes back to
+}
+
+/// ——————— CONVERSATIONS ——————————————————————————————————————————————
+///
+/// One message of the conversation open in View mode. Collapsed, it is a
+/// single line (sender, date and the start of the body); the messages that
+/// were unread and the newest one start out expanded.
+///
+struct ThreadMessage {
+    summary: MailSummary,
+    detail: Option<MailDetail>, // filled in once the worker has loaded it
+    expanded: bool,
+}
+
+/// Syntax help shown while a search is typed.
+const SEARCH_HELP: &str = "from:alice  to:bob  subject:invoice  body:refund\n\
+                           after:2025-01-01  before:2025-02-01\n\
+                           is:unread  is:read  is:flagged  larger:500k\n\
+                           Other words are looked for anywhere in the message;\n\
+                           put values with spaces in \"double quotes\".\n\n\
+                           Enter searches the cached mail at once, then the\n\
+                           server in all folders. Esc cancels.";
+
+/// How many actions `u` can undo.
+const UNDO_LIMIT: usize = 20;
+
+/// The `Undo` for `request`, which finished with `done`. Sends, deleting
+/// for good and moves whose new UID the server did not report cannot be
+/// undone.
+fn inverse(request: &Request, done: &Done) -> Option<Undo> {
+    match (request, done) {
+        (Request::SetFlag { folder, uid, flag, on }, Done::FlagSet { .. }) => Some(Undo {
+            label: "flag change".into(),
+            request: Request::SetFlag { folder: folder.clone(), uid: *uid, flag: *flag, on: !on },
+        }),
+        (
+            Request::Delete { folder, .. } | Request::Archive { folder, .. } | Request::Move { folder, .. },
+            Done::Moved { to, new_uid: Some(new_uid), .. },
+        ) => Some(Undo {
+            label: format!("move to {}", to),
+            request: Request::Move { folder: to.clone(), uid: *new_uid, to: folder.clone() },
+        }),
+        _ => None,
+    }
+}
+
+const SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+/// Date column of the message list: the time for today's mail, day and
+/// month for this year's, the full date for anything older.
+fn short_date(date: &DateTime<FixedOffset>) -> String {
+    let local = date.with_timezone(&Local);
+    let now = Local::now();
+    if local.date_naive() == now.date_naive() {
+        local.format("%H:%M").to_string()
+    } else if local.year() == now.year() {
+        local.format("%e %b").to_string()
+    } else {
+        local.format("%Y-%m-%d").to_string()
+    }
+}
+
+/// Flags column of the message list: ★ flagged, ↩ answered, 📎 attachments,
+/// each shown for a conversation if any of its messages has it.
+fn flag_marks(messages: &[&MailSummary]) -> String {
+    let mut marks = String::new();
+    marks.push(if messages.iter().any(|m| m.has(Flag::Flagged)) { '★' } else { ' ' });
+    marks.push(if messages.iter().any(|m| m.has(Flag::Answered)) { '↩' } else { ' ' });
+    if messages.iter().any(|m| m.has_attachments) {
+        marks.push('📎');
+    }
+    marks
+}
+
+/// Sender column for a conversation: who wrote in it, oldest first, and
+/// how many messages it has, e.g. “Alice, Bob (3)”.
+fn senders(messages: &[&MailSummary]) -> String {
+    let mut names: Vec<&str> = Vec::new();
+    for m in messages.iter().rev() {
+        if !names.contains(&m.sender()) {
+            names.push(m.sender());
+        }
+    }
+    let mut column = names.join(", ");
+    if messages.len() > 1 {
+        column.push_str(&format!(" ({})", messages.len()));
+    }
+    column
+}
+
+/// Sizes of attachments: “812 B”, “14 KB”, “2.3 MB”.
+fn human_size(bytes: usize) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1_048_576 => format!("{} KB", bytes / 1024),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// What 'r', 'R', 'f' and 'e' draft from a message.
+fn response_for(key: char) -> Response {
+    match key {
+        'R' => Response::ReplyAll,
+        'f' => Response::Forward,
+        'e' => Response::ForwardAttached,
+        _ => Response::Reply,
+    }
+}
+
+/// Addresses as typed into a To: line, comma separated.
+fn address_list(addresses: &[Address]) -> String {
+    addresses.iter().map(Address::to_string).collect::<Vec<_>>().join(", ")
+}
+
+/// The View pane's text for one message: the headers that are set, the
+/// body, then a numbered line per attachment (the number saves it).
+fn detail_lines(detail: &MailDetail) -> Vec<Line<'_>> {
+    let mut headers = vec![("From", address_list(&detail.from))];
+    for (name, addresses) in [("To", &detail.to), ("Cc", &detail.cc), ("Reply-To", &detail.reply_to)] {
+        if !addresses.is_empty() {
+            headers.push((name, address_list(addresses)));
+        }
+    }
+    if let Some(date) = detail.date {
+        headers.push(("Date", date.with_timezone(&Local).format("%a, %e %b %Y %H:%M").to_string()));
+    }
+    headers.push(("Subject", detail.subject.clone()));
+    // Mailing list mail says which list it came through
+    if let Some((_, id)) = detail.list_headers.iter().find(|(name, _)| name.eq_ignore_ascii_case("List-Id")) {
+        headers.push(("List", id.clone()));
+    }
+
+    let mut lines: Vec<Line> = headers
+        .into_iter()
+        .map(|(name, value)| {
+            Line::from(vec![
+                Span::styled(format!("{}: ", name), Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(value),
+            ])
+        })
+        .collect();
+    lines.push(Line::default());
+    lines.extend(detail.body.lines().map(Line::from));
+    let attachments: Vec<Line> = detail
+        .attachments()
+        .enumerate()
+        .map(|(n, part)| {
+            let name = part.filename.as_deref().unwrap_or("(unnamed)");
+            Line::from(format!("📎 {}  {}  {}, {}", n + 1, name, part.content_type, human_size(part.size)))
+        })
+        .collect();
+    if !attachments.is_empty() {
+        lines.push(Line::default());
+        lines.extend(attachments);
+    }
+    lines
+}
+
+/// ——————— TERMINAL GUARD ——————————————————————————————————————————————
 ///
-/// This struct holds all state and four callbacks:
+/// Raw mode + alternate screen for as long as the guard lives. Dropping it
+/// restores the terminal whether `run` returns normally, with an error or
+/// by panicking; the panic hook restores it before the panic message is
+/// printed, so the message is readable. `suspend` gives the terminal back
+/// for a while, to run an editor.
 ///
-///   • on_send(&str, &str, &str) → send a message via SMTP
-///   • on_view(u32)             → fetch and return message content via IMAP
-///   • on_refresh(usize)        → re-fetch N message summaries via IMAP
-///   • on_delete(u32)           → delete a single message via IMAP
+struct TerminalGuard;
+
+thread_local! {
+    // Set on the UI thread while the guard lives. Only a panic there ends
+    // the app; a panicking worker thread must not pull the screen out from
+    // under it.
+    static TERMINAL_ACTIVE: Cell<bool> = const { Cell::new(false) };
+}
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        static HOOK: Once = Once::new();
+        HOOK.call_once(|| {
+            let previous = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                if TERMINAL_ACTIVE.with(|active| active.replace(false)) {
+                    restore_terminal();
+                }
+                previous(info);
+            }));
+        });
+
+        enable_raw_mode()?;
+        TERMINAL_ACTIVE.with(|active| active.set(true));
+        let guard = TerminalGuard;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        Ok(guard)
+    }
+
+    /// Hands the terminal to another program (an editor) for as long as
+    /// `f` runs: normal mode and screen, then ours again.
+    fn suspend<T>(&self, f: impl FnOnce() -> T) -> io::Result<T> {
+        restore_terminal();
+        let result = f();
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        Ok(result)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        TERMINAL_ACTIVE.with(|active| active.set(false));
+        restore_terminal();
+    }
+}
+
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen);
+}
+
+/// A rectangle of `width` x `height` cells centred in `area`.
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// ——————— APP STATE ———————————————————————————————————————————————————
+///
+/// All network and disk work happens on the `Worker`'s threads; the app
+/// submits `Request`s and applies the `Event`s that come back between key
+/// presses, so the terminal stays responsive during every round trip.
 ///
-/// It also holds:
-///   • items: Vec<(u32, String)> – the inbox list (UID, “From … Date”)
-///   • selected: usize           – which row is highlighted in the inbox list
+/// It holds:
+///   • items: Vec<MailSummary>   – the message list of the current folder
+///   • threaded: bool            – one row per conversation rather than per message
+///   • rows: Vec<Vec<usize>>     – the `items` of each row, a whole conversation when threaded
+///   • selected: usize           – which row is highlighted in the message list
+///   • folders: Vec<String>      – folder names for the sidebar
+///   • folder_selected: usize    – which folder is highlighted in the sidebar
+///   • current_folder: String    – the folder whose messages are listed
 ///   • mode: Mode                – which screen is currently active
-///   • view_buffer: String       – full text (headers + body) of the viewed message
+///   • view: Option<MailDetail>  – the message shown in View mode
 ///   • view_scroll: u16          – vertical scroll offset in View mode
+///   • thread: Vec<ThreadMessage> – the conversation shown in View mode, oldest first
+///   • thread_focus: usize       – the message of `thread` that Tab / Enter act on
 ///   • compose_to: String        – “To:” line text
+///   • compose_cc: String        – “Cc:” line text
+///   • compose_bcc: String       – “Bcc:” line text
 ///   • compose_subject: String   – “Subject:” line text
 ///   • compose_body: TextArea<'static> – multiline widget for the “Body:” text
 ///   • compose_field: ComposeField      – which compose sub-field is active
-///   • inbox_count: usize        – how many messages to request from IMAP (e.g. 20, then +10)
-///   • tooltip: String           – small status line at the bottom (“Sent!”, “Loading more…”)
+///   • compose_answering: Option<(String, u32)> – folder and UID of the message being replied to
+///   • compose_attachments: Vec<Attachment> – files sent along, read when sending
+///   • compose_draft: Draft      – threading headers and forwarded message of a reply / forward
+///   • attach_input: String      – the path being typed after Ctrl+O
+///   • attach_candidates: Vec<String> – what Tab found to complete `attach_input` with
+///   • inbox_count: usize        – how many messages to list (e.g. 20, then +10)
+///   • tooltip: String           – small status line at the bottom (“Sent!”, “Moved to Trash”)
+///   • worker: Worker            – the background threads doing IMAP / SMTP / cache work
+///   • pending: Option<Pending>  – the request being waited on, if any
+///   • error: Option<String>     – a failure shown in a modal until a key is pressed
+///   • undo: Vec<Undo>           – recent moves / deletes / flag changes, newest last
+///   • search_input: String      – the query being typed after ‘/’
+///   • search: Option<SearchResults> – set while `items` are search hits
 ///
-pub struct App<F, G, H, J>
-where
-    F: FnMut(&str, &str, &str) -> Result<(), Box<dyn Error>> + 'static,
-    G: FnMut(u32) -> Result<String, Box<dyn Error>> + 'static,
-    H: FnMut(usize) -> Result<Vec<(u32, String)>, Box<dyn Error>> + 'static,
-    J: FnMut(u32) -> Result<(), Box<dyn Error>> + 'static,
-{
+pub struct App {
     // ─── INBOX DATA ─────────────────────────────────────────────────────────────
-    items: Vec<(u32, String)>, // (UID, “From    Date”) pairs for the inbox list
+    items: Vec<MailSummary>, // newest first, rendered as flags / sender / subject / date rows
+    threaded: bool,            // group `items` into conversations ('t' toggles)
+    rows: Vec<Vec<usize>>,     // `items` indices per row, newest first (see `regroup`)
     selected: usize,           // which row is currently highlighted
     mode: Mode,                // which screen we’re on
 
+    // ─── FOLDER SIDEBAR ─────────────────────────────────────────────────────────
+    folders: Vec<String>,   // folder names as the server spells them
+    folder_selected: usize, // which folder is highlighted in the sidebar
+    current_folder: String, // the folder `items` belongs to
+
     // ─── VIEW MODE ──────────────────────────────────────────────────────────────
-    view_buffer: String, // “View” mode: full message text (headers + body)
+    view: Option<MailDetail>, // “View” mode: the message, see `detail_lines`
     view_scroll: u16,    // vertical scroll offset in View mode
+    thread: Vec<ThreadMessage>, // conversation in View mode; empty for a single message
+    thread_focus: usize,        // highlighted message of `thread`
 
     // ─── COMPOSE MODE ───────────────────────────────────────────────────────────
     compose_to: String,              // “To:” line
+    compose_cc: String,              // “Cc:” line
+    compose_bcc: String,             // “Bcc:” line
     compose_subject: String,         // “Subject:” line
     compose_body: TextArea<'static>, // multiline “Body:” editor widget
-    compose_field: ComposeField,     // which of To/Subject/Body is focused
+    compose_field: ComposeField,     // which of To/Cc/Bcc/Subject/Body is focused
+    compose_answering: Option<(String, u32)>, // gets \Answered once the reply is sent
+    compose_attachments: Vec<Attachment>,     // sent as multipart/mixed when not empty
+    compose_draft: Draft,                     // what the fields above leave out (see `open_compose`)
+    attach_input: String,                     // path typed in the file picker
+    attach_candidates: Vec<String>,           // Tab's matches for `attach_input`
 
-    // ─── CALLBACKS (SMTP / IMAP) ────────────────────────────────────────────────
-    on_send: F,      // called when sending mail (Ctrl+S)
-    on_view: G,      // called when viewing a message (v)
-    on_refresh: H,   // called when loading more messages (m)
-    on_delete: J,    // called when deleting a message (d)
+    // ─── BACKGROUND WORK ────────────────────────────────────────────────────────
+    worker: Worker,            // runs every IMAP / SMTP / cache operation
+    pending: Option<Pending>,  // what we are waiting on (spinner in the status line)
 
     // ─── OTHER STATE ─────────────────────────────────────────────────────────────
-    inbox_count: usize, // how many messages to fetch from IMAP
-    tooltip: String,    // status line at the bottom (“Sent!”, “Loading…”)
+    inbox_count: usize, // how many messages to list per folder
+    tooltip: String,    // status line at the bottom (“Sent!”, “Moved to Trash”)
+    error: Option<String>, // modal error message, dismissed by any key
+    undo: Vec<Undo>,       // what `u` reverts, newest last
+
+    // ─── SEARCH ──────────────────────────────────────────────────────────────────
+    search_input: String,           // what was typed after ‘/’, kept for the next search
+    search: Option<SearchResults>,  // the list shows search hits, not `current_folder`
 }
 
-impl<F, G, H, J> App<F, G, H, J>
-where
-    F: FnMut(&str, &str, &str) -> Result<(), Box<dyn Error>> + 'static,
-    G: FnMut(u32) -> Result<String, Box<dyn Error>> + 'static,
-    H: FnMut(usize) -> Result<Vec<(u32, String)>, Box<dyn Error>> + 'static,
-    J: FnMut(u32) -> Result<(), Box<dyn Error>> + 'static,
-{
-    /// Constructor: supply four callbacks plus initial inbox items, inbox_count, and tooltip.
+impl App {
+    /// Constructor: the worker that does the actual work, the folder to open
+    /// first and how many of its messages to list.
+    ///
+    ///  • `worker`: Worker – started background threads (see `crate::worker`)
+    ///  • `current_folder`: String – the folder opened on start (usually INBOX)
+    ///  • `inbox_count`: usize – how many messages to list initially
     ///
-    ///  • `items`: Vec<(UID, “From … Date”)> – initial inbox list
-    ///  • `on_view`: FnMut(u32) -> Result<String> – fetch full message by UID
-    ///  • `on_send`: FnMut(&str, &str, &str) -> Result<()> – send a new message
-    ///  • `on_refresh`: FnMut(usize) -> Result<Vec<(UID, String)>> – load more summaries
-    ///  • `on_delete`: FnMut(u32) -> Result<()> – delete a message by UID
-    ///  • `inbox_count`: usize – how many messages to fetch initially
-    ///  • `tooltip`: String – initial status line (usually empty)
-    pub fn new(
-        items: Vec<(u32, String)>,
-        on_view: G,
-        on_send: F,
-        on_refresh: H,
-        on_delete: J,
-        inbox_count: usize,
-        tooltip: String,
-    ) -> Self {
+    /// The message list and folder sidebar are filled in by the worker's
+    /// events, starting with whatever the cache already holds.
+    pub fn new(worker: Worker, current_folder: String, inbox_count: usize) -> Self {
         Self {
             // ─── INBOX ───────────────────────────────────────────────────────────
-            items,
+            items: Vec::new(),
+            threaded: true,
+            rows: Vec::new(),
             selected: 0,
             mode: Mode::Inbox,
 
+            // ─── FOLDERS ─────────────────────────────────────────────────────────
+            folders: vec![current_folder.clone()],
+            folder_selected: 0,
+            current_folder,
+
             // ─── VIEW ────────────────────────────────────────────────────────────
-            view_buffer: String::new(),
+            view: None,
             view_scroll: 0,
+            thread: Vec::new(),
+            thread_focus: 0,
 
             // ─── COMPOSE ─────────────────────────────────────────────────────────
             compose_to: String::new(),
+            compose_cc: String::new(),
+            compose_bcc: String::new(),
             compose_subject: String::new(),
             // MUST be TextArea<'static> so that &TextArea<'static> implements Widget
             compose_body: TextArea::default(),
             compose_field: ComposeField::To,
+            compose_answering: None,
+            compose_attachments: Vec::new(),
+            compose_draft: Draft::default(),
+            attach_input: String::new(),
+            attach_candidates: Vec::new(),
 
-            // ─── CALLBACKS ───────────────────────────────────────────────────────
-            on_send,
-            on_view,
-            on_refresh,
-            on_delete,
+            // ─── BACKGROUND WORK ─────────────────────────────────────────────────
+            worker,
+            pending: None,
 
             // ─── OTHER STATE ─────────────────────────────────────────────────────
             inbox_count,
-            tooltip,
+            tooltip: String::new(),
+            error: None,
+            undo: Vec::new(),
+
+            // ─── SEARCH ──────────────────────────────────────────────────────────
+            search_input: String::new(),
+            search: None,
         }
     }
 
+    /// Hands `request` to the worker and waits for it (without blocking)
+    /// with a spinner labelled `label`. A request still in flight is
+    /// cancelled first, except a send, which has to finish; returns false if
+    /// `request` was not submitted because of that.
+    fn start(&mut self, action: Action, label: String, request: Request) -> bool {
+        if let Some(pending) = &self.pending {
+            if matches!(pending.action, Action::Send) {
+                self.tooltip = "Still sending, try again in a moment".into();
+                return false;
+            }
+            self.worker.cancel(pending.id);
+        }
+        let id = self.worker.submit(request.clone());
+        self.pending = Some(Pending { id, action, request, label, started: Instant::now() });
+        true
+    }
+
+    /// `u`: reverts the most recent undoable action.
+    fn undo_last(&mut self) {
+        let Some(undo) = self.undo.pop() else {
+            self.tooltip = "Nothing to undo".into();
+            return;
+        };
+        let label = format!("Undoing {}", undo.label);
+        if !self.start(Action::Undo, label, undo.request.clone()) {
+            self.undo.push(undo);
+        }
+    }
+
+    /// Splits `items` into rows again after they changed: one per
+    /// conversation (see `imap::threads`) or, for search hits and with
+    /// threading off, one per message.
+    fn regroup(&mut self) {
+        self.rows = if self.threaded && self.search.is_none() {
+            threads(&self.items)
+        } else {
+            (0..self.items.len()).map(|i| vec![i]).collect()
+        };
+    }
+
+    /// The `items` index of the highlighted message: the newest one of a
+    /// conversation, which is what replies, flags and moves act on.
+    fn selected_index(&self) -> Option<usize> {
+        self.rows.get(self.selected)?.first().copied()
+    }
+
+    /// Folder and UID of the highlighted message; for a search hit, the
+    /// folder it was found in.
+    fn selected_message(&self) -> Option<(String, u32)> {
+        let index = self.selected_index()?;
+        let folder = match &self.search {
+            Some(search) => search.folders.get(index)?.clone(),
+            None => self.current_folder.clone(),
+        };
+        Some((folder, self.items[index].uid))
+    }
+
+    /// Puts the highlight on the row holding message `uid`, else keeps it
+    /// on the same row as far as there are rows.
+    fn select_uid(&mut self, uid: Option<u32>) {
+        let last = self.rows.len().saturating_sub(1);
+        self.selected = uid
+            .and_then(|uid| self.rows.iter().position(|row| row.iter().any(|&i| self.items[i].uid == uid)))
+            .unwrap_or(self.selected.min(last));
+    }
+
+    /// 'v': the highlighted message, or its whole conversation oldest
+    /// first, for View mode.
+    fn view_selected(&mut self) {
+        let Some((folder, uid)) = self.selected_message() else {
+            return;
+        };
+        let row = &self.rows[self.selected];
+        if row.len() == 1 {
+            self.start(Action::View, "Loading message".into(), Request::View { folder, uid });
+            return;
+        }
+        // Decide what to expand now: viewing marks everything as read
+        self.thread = row
+            .iter()
+            .rev()
+            .map(|&i| ThreadMessage {
+                summary: self.items[i].clone(),
+                detail: None,
+                expanded: !self.items[i].has(Flag::Seen) || i == row[0],
+            })
+            .collect();
+        self.thread_focus = self.thread.iter().position(|m| m.expanded).unwrap_or(0);
+        let uids = self.thread.iter().map(|m| m.summary.uid).collect();
+        let label = format!("Loading {} messages", row.len());
+        self.start(Action::View, label, Request::ViewThread { folder, uids });
+    }
+
+    /// The lines of the conversation in View mode, and at which of them
+    /// the focused message starts.
+    fn thread_lines(&self) -> (Vec<Line<'_>>, usize) {
+        let mut lines = Vec::new();
+        let mut focus_line = 0;
+        for (k, m) in self.thread.iter().enumerate() {
+            if k == self.thread_focus {
+                focus_line = lines.len();
+            }
+            let marker = if m.expanded { '▾' } else { '▸' };
+            let mut header = vec![Span::styled(
+                format!("{} {}  {}", marker, m.summary.sender(), short_date(&m.summary.date)),
+                Style::default().add_modifier(Modifier::BOLD),
+            )];
+            if !m.expanded {
+                let body = m.detail.as_ref().map_or("", |d| d.body.as_str());
+                let preview = body.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or("");
+                header.push(Span::styled(format!("  {}", preview), Style::default().add_modifier(Modifier::DIM)));
+            }
+            let header = Line::from(header);
+            lines.push(if k == self.thread_focus {
+                header.style(Style::default().add_modifier(Modifier::REVERSED))
+            } else {
+                header
+            });
+            if let (true, Some(detail)) = (m.expanded, &m.detail) {
+                lines.extend(detail_lines(detail));
+                lines.push(Line::default());
+            }
+        }
+        (lines, focus_line)
+    }
+
+    /// The message in View mode: the one shown, or the focused message of
+    /// the conversation.
+    fn shown_detail(&self) -> Option<&MailDetail> {
+        if self.thread.is_empty() {
+            self.view.as_ref()
+        } else {
+            self.thread.get(self.thread_focus)?.detail.as_ref()
+        }
+    }
+
+    /// '1'–'9' and 'S' in View mode: saves attachment `number` (counting
+    /// from 1) of the shown message, or all of them.
+    fn save_attachments(&mut self, number: Option<usize>) {
+        let Some(detail) = self.shown_detail() else {
+            return;
+        };
+        let uid = detail.uid;
+        let attachments: Vec<usize> = detail.attachments().map(|part| part.index).collect();
+        let parts = match number {
+            None => attachments,
+            Some(n) => attachments.get(n - 1).into_iter().copied().collect(),
+        };
+        if parts.is_empty() {
+            self.tooltip = match number {
+                Some(n) => format!("There is no attachment {}", n),
+                None => "There are no attachments".into(),
+            };
+            return;
+        }
+        let Some((folder, _)) = self.selected_message() else {
+            return;
+        };
+        let label = match parts.len() {
+            1 => "Saving attachment".to_string(),
+            n => format!("Saving {} attachments", n),
+        };
+        self.start(Action::Save, label, Request::SaveAttachments { folder, uid, parts });
+    }
+
+    /// Enter in the search prompt: asks the server for the newest
+    /// `inbox_count` matches in all folders.
+    fn submit_search(&mut self) {
+        let query = self.search_input.trim().to_string();
+        if query.is_empty() {
+            return;
+        }
+        let request = Request::Search { query: query.clone(), count: self.inbox_count };
+        self.start(Action::Search, format!("Searching for {}", query), request);
+    }
+
+    /// Updates or drops the search hit (`folder`, `uid`) after a request
+    /// about it finished, since no `Messages` event covers the hits.
+    fn update_hit(&mut self, folder: &str, uid: u32, done: &Done) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        let Some(index) = (0..self.items.len())
+            .find(|&i| self.items[i].uid == uid && search.folders.get(i).is_some_and(|f| f == folder))
+        else {
+            return;
+        };
+        match done {
+            Done::FlagSet { flags, .. } => self.items[index].flags = flags.clone(),
+            Done::Viewed { .. } | Done::Drafted { .. } if !self.items[index].has(Flag::Seen) => {
+                // The worker marks what is viewed or answered as read
+                self.items[index].flags.push(Flag::Seen.as_str().to_string());
+            }
+            Done::Moved { .. } | Done::Deleted { .. } => {
+                self.items.remove(index);
+                search.folders.remove(index);
+                search.snippets.remove(index);
+                self.regroup();
+                self.selected = self.selected.min(self.rows.len().saturating_sub(1));
+            }
+            _ => {}
+        }
+    }
+
+    /// Lists `hits` in place of the folder. The highlight stays on the same
+    /// message when the server's hits extend the cached ones.
+    fn show_hits(&mut self, query: String, hits: Vec<Hit>) {
+        let selected = self.selected_message();
+        let previous = match self.search.take() {
+            Some(search) => search.previous,
+            None => self.current_folder.clone(),
+        };
+        let mut search = SearchResults { query, folders: Vec::new(), snippets: Vec::new(), previous };
+        self.items.clear();
+        for hit in hits {
+            search.folders.push(hit.folder);
+            search.snippets.push(hit.snippet);
+            self.items.push(hit.summary);
+        }
+        self.selected = selected
+            .and_then(|(folder, uid)| {
+                (0..self.items.len()).find(|&i| self.items[i].uid == uid && search.folders[i] == folder)
+            })
+            .unwrap_or(0);
+        self.current_folder = format!("Search: {}", search.query);
+        self.search = Some(search);
+        self.regroup();
+    }
+
+    /// Esc: stop waiting for the current request. Returns false if there
+    /// was nothing to cancel.
+    fn cancel_pending(&mut self) -> bool {
+        match &self.pending {
+            Some(pending) if matches!(pending.action, Action::Send) => {
+                self.tooltip = "Sending cannot be cancelled".into();
+                true
+            }
+            Some(pending) => {
+                self.worker.cancel(pending.id);
+                self.tooltip = format!("{}: cancelled", pending.label);
+                self.pending = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The status line: a spinner while a request is pending, else the tooltip.
+    fn status_line(&self) -> String {
+        let Some(pending) = &self.pending else {
+            return self.tooltip.clone();
+        };
+        let elapsed = pending.started.elapsed();
+        let frame = SPINNER[(elapsed.as_millis() / 100) as usize % SPINNER.len()];
+        let mut line = format!("{} {}…", frame, pending.label);
+        if elapsed.as_secs() > 0 {
+            line.push_str(&format!(" {}s", elapsed.as_secs()));
+        }
+        if !matches!(pending.action, Action::Send) {
+            line.push_str(" (Esc to cancel)");
+        }
+        line
+    }
+
+    /// Applies every waiting worker event without blocking.
+    fn apply_events(&mut self) {
+        while let Some(event) = self.worker.try_recv() {
+            match event {
+                WorkerEvent::Messages { folder, summaries } if folder == self.current_folder => {
+                    // Keep the highlight on the same message if it is still
+                    // there, otherwise on the same row
+                    let uid = self.selected_index().map(|i| self.items[i].uid);
+                    self.items = summaries;
+                    self.regroup();
+                    self.select_uid(uid);
+                }
+                WorkerEvent::Messages { .. } => {}
+                WorkerEvent::Folders(folders) => {
+                    self.folders = folders;
+                    self.folder_selected = self
+                        .folders
+                        .iter()
+                        .position(|f| *f == self.current_folder)
+                        .unwrap_or(0);
+                }
+                // Cached hits, while the server is still being asked
+                WorkerEvent::Found { query, hits } => {
+                    let searching = self.pending.as_ref().is_some_and(|p| {
+                        matches!(&p.request, Request::Search { query: q, .. } if *q == query)
+                    });
+                    if searching {
+                        self.show_hits(query, hits);
+                    }
+                }
+                WorkerEvent::Status(text) => self.tooltip = text,
+                WorkerEvent::Finished { id, result } => self.finish(id, result),
+            }
+        }
+    }
+
+    /// Completes the pending request `id`; results of cancelled or
+    /// superseded requests are dropped.
+    fn finish(&mut self, id: u64, result: Result<Done, String>) {
+        let Some(pending) = self.pending.take_if(|p| p.id == id) else {
+            return;
+        };
+        let done = match result {
+            Ok(done) => done,
+            Err(e) => {
+                // Failed sends, deletes and moves get a modal so they are not missed;
+                // a failed send leaves Compose (and the draft) as it is
+                let message = format!("{} failed: {}", pending.label, e);
+                match pending.action {
+                    Action::Send | Action::Delete | Action::Archive | Action::Undo | Action::Save => {
+                        self.error = Some(message)
+                    }
+                    _ => self.tooltip = message,
+                }
+                return;
+            }
+        };
+        // Remember how to revert it; undoing is not undoable itself
+        if !matches!(pending.action, Action::Undo) {
+            if let Some(undo) = inverse(&pending.request, &done) {
+                if self.undo.len() == UNDO_LIMIT {
+                    self.undo.remove(0);
+                }
+                self.undo.push(undo);
+            }
+        }
+        match &pending.request {
+            Request::View { folder, uid }
+            | Request::Respond { folder, uid, .. }
+            | Request::SetFlag { folder, uid, .. }
+            | Request::Delete { folder, uid }
+            | Request::Archive { folder, uid }
+            | Request::Move { folder, uid, .. } => self.update_hit(folder, *uid, &done),
+            _ => {}
+        }
+        match (pending.action, done) {
+            // Only switch screens if the user is still where they asked from
+            (Action::View, Done::Viewed { detail }) if matches!(self.mode, Mode::Inbox) => {
+                self.tooltip = if detail.attachments().next().is_some() {
+                    "1–9: save that attachment, S: save all".into()
+                } else {
+                    String::new()
+                };
+                self.view = Some(*detail);
+                self.view_scroll = 0;
+                self.thread.clear();
+                self.mode = Mode::View;
+            }
+            (Action::View, Done::ViewedThread { messages }) if matches!(self.mode, Mode::Inbox) => {
+                for detail in messages {
+                    if let Some(m) = self.thread.iter_mut().find(|m| m.summary.uid == detail.uid) {
+                        m.detail = Some(detail);
+                    }
+                }
+                self.view_scroll = 0;
+                self.mode = Mode::View;
+                self.tooltip =
+                    "Tab / Shift+Tab: next / previous message, Enter: expand or collapse, 1–9 / S: save attachments"
+                        .into();
+            }
+            (Action::Respond, Done::Drafted { draft }) if matches!(self.mode, Mode::Inbox | Mode::View) => {
+                self.open_compose(*draft);
+                // Only a reply marks the original as answered
+                if let Request::Respond { folder, uid, response: Response::Reply | Response::ReplyAll } = pending.request {
+                    self.compose_answering = Some((folder, uid));
+                }
+            }
+            (Action::Search, Done::Found { query, results }) => {
+                // The hits replace the list until Esc or another folder is opened
+                self.tooltip = match results.len() {
+                    0 => format!("Nothing matches {}", query),
+                    1 => "1 message found".into(),
+                    n => format!("{} messages found ('m' for more, Esc to go back)", n),
+                };
+                self.show_hits(query, results);
+            }
+            (Action::Delete | Action::Archive, Done::Moved { to, new_uid, .. }) => {
+                self.tooltip = match new_uid {
+                    Some(_) => format!("Moved to {} ('u' to undo)", to),
+                    None => format!("Moved to {} (cannot be undone)", to),
+                };
+            }
+            (Action::Undo, _) => self.tooltip = pending.label.replacen("Undoing", "Undid", 1),
+            (Action::Delete, Done::Deleted { .. }) => self.tooltip = "Deleted for good".into(),
+            (Action::Save, Done::Saved { paths }) => {
+                self.tooltip = match paths.as_slice() {
+                    [path] => format!("Saved {}", path.display()),
+                    paths => match paths.first().and_then(|path| path.parent()) {
+                        Some(dir) => format!("Saved {} attachments to {}", paths.len(), dir.display()),
+                        None => format!("Saved {} attachments", paths.len()),
+                    },
+                };
+            }
+            (Action::Send, Done::Sent) => {
+                if matches!(self.mode, Mode::Compose) {
+                    self.mode = Mode::Inbox;
+                }
+                self.tooltip = "Sent!".into();
+            }
+            _ => {}
+        }
+    }
+
+    /// 'r', 'R', 'f' and 'e': has the worker draft a reply to or forward
+    /// of the highlighted message (in View mode, the one shown), which then
+    /// opens in Compose.
+    fn respond(&mut self, response: Response) {
+        let Some((folder, selected)) = self.selected_message() else {
+            return;
+        };
+        let uid = match self.mode {
+            Mode::View => match self.shown_detail() {
+                Some(detail) => detail.uid,
+                None => return,
+            },
+            _ => selected,
+        };
+        self.start(Action::Respond, "Loading message".into(), Request::Respond { folder, uid, response });
+    }
+
+    /// Opens Compose with `draft` in its fields: blank for a new message,
+    /// or a reply or forward from the worker. Threading headers and a
+    /// forwarded message have no field and stay in `compose_draft`.
+    fn open_compose(&mut self, draft: Draft) {
+        //  1) Prefill the one-line fields
+        self.compose_answering = None;
+        self.compose_to = draft.to.clone();
+        self.compose_cc = draft.cc.clone();
+        self.compose_bcc = draft.bcc.clone();
+        self.compose_subject = draft.subject.clone();
+
+        //  2) Fill the Body TextArea; a reply is written below the quote,
+        //     anything else above what is there
+        self.compose_body = TextArea::new(draft.body.lines().map(String::from).collect());
+        if draft.in_reply_to.is_some() {
+            self.compose_body.move_cursor(CursorMove::Bottom);
+        }
+        self.compose_attachments.clear();
+        self.compose_draft = draft;
+
+        //  3) Switch to Compose mode, focusing To if it is still empty
+        self.compose_field = if self.compose_to.is_empty() { ComposeField::To } else { ComposeField::Body };
+        self.mode = Mode::Compose;
+        self.tooltip.clear();
+    }
+
+    /// The draft as it stands in Compose.
+    fn draft(&self) -> Draft {
+        Draft {
+            to: self.compose_to.clone(),
+            cc: self.compose_cc.clone(),
+            bcc: self.compose_bcc.clone(),
+            subject: self.compose_subject.clone(),
+            body: self.compose_body.lines().join("\n"),
+            attachments: self.compose_attachments.iter().map(|a| a.path.clone()).collect(),
+            ..self.compose_draft.clone()
+        }
+    }
+
+    /// Ctrl+G in Compose: the TUI steps aside while the draft's headers
+    /// and body are edited in $VISUAL / $EDITOR, then the fields are filled
+    /// from what was saved. Attachments stay as they are.
+    fn edit_externally(&mut self, guard: &TerminalGuard, term: &mut Terminal<CrosstermBackend<io::Stdout>>) -> io::Result<()> {
+        let editor = editor_command();
+        let mut draft = self.draft();
+        let result = guard.suspend(|| edit_draft(&mut draft, &editor))?;
+        // The editor drew over everything
+        term.clear()?;
+        match result {
+            Ok(()) => {
+                self.compose_to = draft.to;
+                self.compose_cc = draft.cc;
+                self.compose_bcc = draft.bcc;
+                self.compose_subject = draft.subject;
+                self.compose_body = TextArea::new(draft.body.lines().map(String::from).collect());
+                self.compose_body.move_cursor(CursorMove::Bottom);
+                self.compose_field = ComposeField::Body;
+                self.tooltip = format!("Edited in {}", editor);
+            }
+            Err(e) => self.tooltip = e.to_string(),
+        }
+        Ok(())
+    }
+
+    /// The text of the focused one-line Compose field; `None` for Body.
+    fn compose_line(&mut self) -> Option<&mut String> {
+        match self.compose_field {
+            ComposeField::To => Some(&mut self.compose_to),
+            ComposeField::Cc => Some(&mut self.compose_cc),
+            ComposeField::Bcc => Some(&mut self.compose_bcc),
+            ComposeField::Subject => Some(&mut self.compose_subject),
+            ComposeField::Body => None,
+        }
+    }
+
+    /// What stops the draft from being sent to its recipients: the first
+    /// address field with an entry that is not an address, or no
+    /// recipients at all.
+    fn recipient_problem(&self) -> Option<(ComposeField, String)> {
+        let fields = [
+            (ComposeField::To, &self.compose_to),
+            (ComposeField::Cc, &self.compose_cc),
+            (ComposeField::Bcc, &self.compose_bcc),
+        ];
+        let mut count = 0;
+        for (field, text) in fields {
+            match mailboxes(text) {
+                Ok(list) => count += list.len(),
+                Err(e) => return Some((field, e.to_string())),
+            }
+        }
+        (count == 0).then(|| (ComposeField::To, "Who is it for? To, Cc and Bcc are all empty".to_string()))
+    }
+
+    /// Enter in the file picker: attaches the file typed, warning when the
+    /// attachments get large. The picker stays open on a bad path.
+    fn attach_file(&mut self) {
+        match Attachment::new(&self.attach_input) {
+            Ok(attachment) => {
+                self.tooltip = format!("Attached {} ({})", attachment.name(), human_size(attachment.size as usize));
+                self.compose_attachments.push(attachment);
+                let total = self.compose_attachments.iter().map(|a| a.size).sum();
+                if let Some(warning) = size_warning(total) {
+                    self.tooltip = warning;
+                }
+                // Start the next pick in the same directory
+                let keep = self.attach_input.rfind('/').map_or(0, |slash| slash + 1);
+                self.attach_input.truncate(keep);
+                self.attach_candidates.clear();
+                self.mode = Mode::Compose;
+            }
+            Err(e) => self.tooltip = format!("Cannot attach {}: {}", self.attach_input.trim(), e),
+        }
+    }
+
+    /// Switches the message list to `folder`: cached messages show up as soon
+    /// as the worker has read them, the rest once the folder is synced.
+    fn open_folder(&mut self, folder: String, label: String) {
+        let request = Request::OpenFolder { folder: folder.clone(), count: self.inbox_count };
+        self.search = None;
+        if folder != self.current_folder {
+            self.items.clear();
+            self.rows.clear();
+            self.selected = 0;
+            self.current_folder = folder;
+        }
+        self.start(Action::Open, label, request);
+    }
+
     /// The main event loop. Enable raw mode, switch to alternate screen,
-    /// then loop until the user presses ‘q’. On each iteration:
-    ///   1) draw the UI (Inbox / View / Compose / ConfirmDelete + tooltip)
-    ///   2) read a KeyEvent
+    /// open the first folder, then loop until the user presses ‘q’. On each
+    /// iteration:
+    ///   1) draw the UI (Inbox / View / Compose / ConfirmDelete + status)
+    ///   2) apply worker events, then wait up to 100ms for a KeyEvent
     ///   3) update state based on the current mode + key
     ///   4) repeat
     pub fn run(mut self) -> Result<(), Box<dyn Error>> {
-        // Enable raw mode (keypresses go straight to us)
-        enable_raw_mode()?;
+        let first = self.current_folder.clone();
+        self.open_folder(first.clone(), format!("Syncing {}", first));
 
-        // Switch to the alternate screen so our TUI doesn’t overwrite the shell
-        let mut stdout = io::stdout();
-        execute!(stdout, EnterAlternateScreen)?;
-        let mut term = Terminal::new(CrosstermBackend::new(stdout))?;
+        // Raw mode (keypresses go straight to us) + alternate screen so our
+        // TUI doesn’t overwrite the shell, undone when `guard` goes away
+        let guard = TerminalGuard::enter()?;
+        let mut term = Terminal::new(CrosstermBackend::new(io::stdout()))?;
 
         loop {
             // ─────────────────────────────────────────────────────────────────
@@ -183,48 +1112,145 @@ where
                 let chunks = Layout::default()
                     .direction(Direction::Vertical)
                     .constraints([Constraint::Percentage(90), Constraint::Percentage(10)])
-                    .split(f.size());
+                    .split(f.area());
 
                 // The top 90% (chunks[0]) is split horizontally:
-                //   • Left  30% → Inbox list
-                //   • Right 70% → View / Compose / ConfirmDelete
+                //   • Left   15% → Folder sidebar
+                //   • Middle 42% → Message list of the current folder
+                //   • Right  43% → View / Compose / ConfirmDelete
                 let main_area = chunks[0];
-                let columns = Layout::default()
+                let outer = Layout::default()
                     .direction(Direction::Horizontal)
-                    .constraints([Constraint::Percentage(30), Constraint::Percentage(70)])
+                    .constraints([Constraint::Percentage(15), Constraint::Percentage(85)])
                     .split(main_area);
+                let columns = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                    .split(outer[1]);
 
                 // ─────────────────────────────────────────────────────────────
-                // 2a) LEFT COLUMN: render the Inbox list
+                // 2) SIDEBAR: folder list, highlighted only while it has focus
                 // ─────────────────────────────────────────────────────────────
-                let list_items: Vec<ListItem> = self
-                    .items
+                let folder_items: Vec<ListItem> = self
+                    .folders
                     .iter()
-                    .map(|(_, txt)| ListItem::new(txt.clone()))
+                    .map(|name| {
+                        let item = ListItem::new(name.clone());
+                        if *name == self.current_folder {
+                            item.style(Style::default().add_modifier(Modifier::BOLD))
+                        } else {
+                            item
+                        }
+                    })
                     .collect();
-                let mut state = ListState::default();
+                let mut folder_state = ListState::default();
+                if matches!(self.mode, Mode::Folders) {
+                    folder_state.select(Some(self.folder_selected));
+                }
+                let folder_list = List::new(folder_items)
+                    .block(Block::default().borders(Borders::ALL).title("Folders"))
+                    .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+                f.render_stateful_widget(folder_list, outer[0], &mut folder_state);
+
+                // ─────────────────────────────────────────────────────────────
+                // 2a) MIDDLE COLUMN: the message list as a table
+                // ─────────────────────────────────────────────────────────────
+                // Flags | Sender | Subject | Date; the subject takes whatever
+                // width is left and every cell is cut off at its column's edge.
+                // A conversation is one row: its first subject, everyone who
+                // wrote in it and its newest date. Search hits show the
+                // matching text under the subject, the matched words underlined
+                let rows: Vec<Row> = self
+                    .rows
+                    .iter()
+                    .map(|row| {
+                        let messages: Vec<&MailSummary> = row.iter().map(|&i| &self.items[i]).collect();
+                        let (m, first) = (messages[0], messages[messages.len() - 1]);
+                        let snippet = self.search.as_ref().and_then(|s| s.snippets.get(row[0])?.as_deref());
+                        let mut subject = Text::from(first.subject.clone());
+                        if let Some(snippet) = snippet {
+                            let spans = snippet_parts(snippet).into_iter().map(|(text, matched)| {
+                                let style = if matched {
+                                    Style::default().add_modifier(Modifier::UNDERLINED)
+                                } else {
+                                    Style::default().add_modifier(Modifier::DIM)
+                                };
+                                Span::styled(text.to_string(), style)
+                            });
+                            subject.push_line(Line::from(spans.collect::<Vec<_>>()));
+                        }
+                        let height = subject.height() as u16;
+                        let row = Row::new(vec![
+                            TableCell::from(flag_marks(&messages)),
+                            TableCell::from(senders(&messages)),
+                            TableCell::from(subject),
+                            TableCell::from(short_date(&m.date)),
+                        ])
+                        .height(height);
+                        // Unread mail is bold
+                        if messages.iter().all(|m| m.has(Flag::Seen)) {
+                            row
+                        } else {
+                            row.style(Style::default().add_modifier(Modifier::BOLD))
+                        }
+                    })
+                    .collect();
+                let widths = [
+                    Constraint::Length(4),
+                    Constraint::Percentage(30),
+                    Constraint::Fill(1),
+                    Constraint::Length(10),
+                ];
+                let mut state = TableState::default();
                 state.select(Some(self.selected));
-                let list = List::new(list_items)
-                    .block(Block::default().borders(Borders::ALL).title("Inbox"))
-                    .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+                let table = Table::new(rows, widths)
+                    .block(Block::default().borders(Borders::ALL).title(self.current_folder.as_str()))
+                    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
                     .highlight_symbol(">> ");
-                f.render_stateful_widget(list, columns[0], &mut state);
+                f.render_stateful_widget(table, columns[0], &mut state);
 
                 // ─────────────────────────────────────────────────────────────
                 // 2b) RIGHT COLUMN: depends on `self.mode`
                 // ─────────────────────────────────────────────────────────────
                 match self.mode {
-                    Mode::Inbox => {
+                    Mode::Inbox | Mode::Folders => {
                         // Show a placeholder when no message is open
                         let placeholder = Paragraph::new(
-                            "Press 'v' to view, 'r' to reply, 'c' to compose,\n\
-                             'm' to load more, 'd' to delete, 'q' to quit",
+                            "Press 'v' to view, 'c' to compose, 'r' to reply, 'R' to reply to all,\n\
+                             'f' to forward, 'e' to forward as an attachment,\n\
+                             'N' to mark read/unread, 'F' to flag/unflag, 'u' to undo,\n\
+                             'm' to load more, 'd' to delete, 'a' to archive, '/' to search,\n\
+                             't' to list conversations or single messages,\n\
+                             Tab for folders, Esc to cancel a slow request, 'q' to quit",
                         )
                         .block(Block::default().borders(Borders::ALL).title("Instructions"))
                         .wrap(Wrap { trim: true });
                         f.render_widget(placeholder, columns[1]);
                     }
 
+                    Mode::Search => {
+                        // What can be typed into the prompt below
+                        let help = Paragraph::new(SEARCH_HELP)
+                            .block(Block::default().borders(Borders::ALL).title("Search"))
+                            .wrap(Wrap { trim: true });
+                        f.render_widget(help, columns[1]);
+                    }
+
+                    Mode::View if !self.thread.is_empty() => {
+                        // The conversation, scrolled to the focused message
+                        let (lines, focus_line) = self.thread_lines();
+                        let width = columns[1].width.saturating_sub(2);
+                        let above = Paragraph::new(lines[..focus_line].to_vec())
+                            .wrap(Wrap { trim: true })
+                            .line_count(width) as u16;
+                        let title = format!("Conversation ({} messages)", self.thread.len());
+                        let p = Paragraph::new(lines)
+                            .block(Block::default().borders(Borders::ALL).title(title))
+                            .wrap(Wrap { trim: true })
+                            .scroll((above.saturating_add(self.view_scroll), 0));
+                        f.render_widget(p, columns[1]);
+                    }
+
                     Mode::View | Mode::ConfirmDelete => {
                         // Draw the message content or the “Confirm Delete” box
                         let title = match self.mode {
@@ -236,57 +1262,108 @@ where
                         if let Mode::ConfirmDelete = self.mode {
                             block = block.style(Style::default().add_modifier(Modifier::REVERSED));
                         }
-                        // IMPORTANT: use `.as_str()` so Paragraph::new knows it’s &str
-                        let p = Paragraph::new(self.view_buffer.as_str())
+                        let lines = self.view.as_ref().map(detail_lines).unwrap_or_default();
+                        let p = Paragraph::new(lines)
                             .block(block)
                             .wrap(Wrap { trim: true })
                             .scroll((self.view_scroll, 0));
                         f.render_widget(p, columns[1]);
                     }
 
+                    Mode::Attach => {
+                        // The file picker: Tab's matches, else what is attached so far
+                        let mut lines: Vec<Line> = if self.attach_candidates.is_empty() {
+                            vec![
+                                Line::from("Type the path of a file to attach:"),
+                                Line::from("Tab completes it, Enter attaches the file,"),
+                                Line::from("Backspace on an empty line removes the last"),
+                                Line::from("attachment, Esc goes back to the message."),
+                            ]
+                        } else {
+                            self.attach_candidates.iter().map(|c| Line::from(c.as_str())).collect()
+                        };
+                        if self.attach_candidates.is_empty() && !self.compose_attachments.is_empty() {
+                            lines.push(Line::default());
+                            lines.extend(self.compose_attachments.iter().map(|a| {
+                                Line::from(format!("📎 {}  {}", a.path.display(), human_size(a.size as usize)))
+                            }));
+                        }
+                        let picker = Paragraph::new(lines)
+                            .block(Block::default().borders(Borders::ALL).title("Attach a file"))
+                            .wrap(Wrap { trim: false });
+                        f.render_widget(picker, columns[1]);
+                    }
+
                     Mode::Compose => {
-                        // COMPOSE MODE: split into three vertical areas: To, Subject, Body
+                        // COMPOSE MODE: split into vertical areas: To, Cc, Bcc,
+                        // Subject, the attachments if there are any, and Body
+                        let forwarded = self.compose_draft.forwarded.as_ref().map_or(0, Vec::len);
+                        let attached = !self.compose_attachments.is_empty() || forwarded > 0;
+                        let mut constraints = vec![Constraint::Length(3); 4];
+                        if attached {
+                            constraints.push(Constraint::Length(3));
+                        }
+                        constraints.push(Constraint::Min(0));
                         let compose_chunks = Layout::default()
                             .direction(Direction::Vertical)
-                            .constraints([
-                                Constraint::Length(3),
-                                Constraint::Length(3),
-                                Constraint::Min(0),
-                            ])
+                            .constraints(constraints)
                             .split(columns[1]);
+                        let body_area = compose_chunks[compose_chunks.len() - 1];
 
-                        // 2c) “To:” field (Paragraph)
-                        // Use .as_str() so the compiler infers &str
-                        let to_title = if matches!(self.compose_field, ComposeField::To) {
-                            "To*"
-                        } else {
-                            "To"
-                        };
-                        let p_to = Paragraph::new(self.compose_to.as_str())
-                            .block(Block::default().borders(Borders::ALL).title(to_title));
-                        f.render_widget(p_to, compose_chunks[0]);
+                        // 2c) One-line fields (Paragraphs), the focused one marked
+                        // with “*”. An address field left with something in it
+                        // that is not an address says so in its title
+                        let fields = [
+                            (ComposeField::To, "To", &self.compose_to),
+                            (ComposeField::Cc, "Cc", &self.compose_cc),
+                            (ComposeField::Bcc, "Bcc", &self.compose_bcc),
+                            (ComposeField::Subject, "Subject", &self.compose_subject),
+                        ];
+                        for (k, (field, name, text)) in fields.into_iter().enumerate() {
+                            let title = match mailboxes(text) {
+                                _ if self.compose_field == field => format!("{}*", name),
+                                Err(e) if field != ComposeField::Subject => format!("{} ⚠ {}", name, e),
+                                _ => name.to_string(),
+                            };
+                            let p = Paragraph::new(text.as_str())
+                                .block(Block::default().borders(Borders::ALL).title(title));
+                            f.render_widget(p, compose_chunks[k]);
+                        }
 
-                        // 2d) “Subject:” field (Paragraph)
-                        let sub_title = if matches!(self.compose_field, ComposeField::Subject) {
-                            "Subject*"
-                        } else {
-                            "Subject"
-                        };
-                        let p_sub = Paragraph::new(self.compose_subject.as_str())
-                            .block(Block::default().borders(Borders::ALL).title(sub_title));
-                        f.render_widget(p_sub, compose_chunks[1]);
+                        // 2d′) Attachments: names and sizes, the total in the title;
+                        // a message forwarded as an attachment comes last
+                        if attached {
+                            let mut names: Vec<String> = self
+                                .compose_attachments
+                                .iter()
+                                .map(|a| format!("{} ({})", a.name(), human_size(a.size as usize)))
+                                .collect();
+                            if forwarded > 0 {
+                                names.push(format!("forwarded message ({})", human_size(forwarded)));
+                            }
+                            let total = self.compose_attachments.iter().map(|a| a.size).sum::<u64>() + forwarded as u64;
+                            let mut title = format!("Attachments, {}", human_size(total as usize));
+                            if size_warning(total).is_some() {
+                                title.push_str(" ⚠ large");
+                            }
+                            let p_att = Paragraph::new(names.join(", "))
+                                .block(Block::default().borders(Borders::ALL).title(title));
+                            f.render_widget(p_att, compose_chunks[4]);
+                        }
 
                         // 2e) “Body:” field
                         // (a) Draw a Block with a border and title “Body”
-                        let body_block = Block::default().borders(Borders::ALL).title("Body");
-                        f.render_widget(body_block, compose_chunks[2]);
+                        let body_block = Block::default()
+                            .borders(Borders::ALL)
+                            .title("Body (Ctrl+S to send, Ctrl+O to attach a file, Ctrl+G for $EDITOR)");
+                        f.render_widget(body_block, body_area);
 
                         // (b) Compute the “inner” Rect inset by 1 cell so TextArea draws inside
                         let inner = Rect {
-                            x:      compose_chunks[2].x + 1,
-                            y:      compose_chunks[2].y + 1,
-                            width:  compose_chunks[2].width.saturating_sub(2),
-                            height: compose_chunks[2].height.saturating_sub(2),
+                            x:      body_area.x + 1,
+                            y:      body_area.y + 1,
+                            width:  body_area.width.saturating_sub(2),
+                            height: body_area.height.saturating_sub(2),
                         };
 
                         // (c) Render the TextArea<'static> inside that inner area.
@@ -296,24 +1373,67 @@ where
                         if matches!(self.compose_field, ComposeField::Body) {
                             let (row, col) = self.compose_body.cursor();
                             // Note: row/col are 0-based inside the TextArea.
-                            f.set_cursor(inner.x + col as u16, inner.y + row as u16);
+                            f.set_cursor_position((inner.x + col as u16, inner.y + row as u16));
                         }
                     }
                 }
 
                 // ─────────────────────────────────────────────────────────────
-                // 3) BOTTOM ROW: render the tooltip/status box
+                // 3) BOTTOM ROW: render the spinner or tooltip in the status box
+                // ─────────────────────────────────────────────────────────────
+                // While searching, the status box is the prompt
+                if let Mode::Search = self.mode {
+                    let prompt = Paragraph::new(format!("/{}", self.search_input))
+                        .block(Block::default().borders(Borders::ALL).title("Search all folders"));
+                    f.render_widget(prompt, chunks[1]);
+                    let col = self.search_input.chars().count() as u16 + 1;
+                    f.set_cursor_position((chunks[1].x + 1 + col, chunks[1].y + 1));
+                } else if let Mode::Attach = self.mode {
+                    // ...and while attaching, the path being typed, with
+                    // what went wrong with the last one as the title
+                    let title = if self.tooltip.is_empty() { "Attach" } else { self.tooltip.as_str() };
+                    let prompt = Paragraph::new(self.attach_input.as_str())
+                        .block(Block::default().borders(Borders::ALL).title(title));
+                    f.render_widget(prompt, chunks[1]);
+                    let col = self.attach_input.chars().count() as u16;
+                    f.set_cursor_position((chunks[1].x + 1 + col, chunks[1].y + 1));
+                } else {
+                    let tip = Paragraph::new(self.status_line())
+                        .block(Block::default().borders(Borders::ALL).title("Status"));
+                    f.render_widget(tip, chunks[1]);
+                }
+
+                // ─────────────────────────────────────────────────────────────
+                // 3b) ERROR MODAL on top of everything, until a key is pressed
                 // ─────────────────────────────────────────────────────────────
-                let tip = Paragraph::new(self.tooltip.as_str())
-                    .block(Block::default().borders(Borders::ALL).title("Status"));
-                f.render_widget(tip, chunks[1]);
+                if let Some(error) = &self.error {
+                    let area = centered(f.area(), 60, 8);
+                    let modal = Paragraph::new(format!("{}\n\n(press any key)", error))
+                        .block(Block::default().borders(Borders::ALL).title("Error"))
+                        .wrap(Wrap { trim: true });
+                    f.render_widget(Clear, area);
+                    f.render_widget(modal, area);
+                }
             })?;
             // End of term.draw
 
             // ─────────────────────────────────────────────────────────────────
-            // 4) HANDLE KEY EVENTS (Inbox / View / Compose / ConfirmDelete)
+            // 4) APPLY WORKER EVENTS, then wait briefly for a key so events
+            //    keep showing up (and the spinner turns) while the user is idle
+            // ─────────────────────────────────────────────────────────────────
+            self.apply_events();
+            if !event::poll(Duration::from_millis(100))? {
+                continue;
+            }
+
+            // ─────────────────────────────────────────────────────────────────
+            // 5) HANDLE KEY EVENTS (Inbox / View / Compose / ConfirmDelete)
             // ─────────────────────────────────────────────────────────────────
             if let Event::Key(key_event) = event::read()? {
+                // Any key dismisses the error modal and does nothing else
+                if self.error.take().is_some() {
+                    continue;
+                }
                 match self.mode {
                     // ─────────────────────────────────────────────────────────
                     // MODE: INBOX
@@ -321,84 +1441,99 @@ where
                     Mode::Inbox => match key_event.code {
                         KeyCode::Char('q') => break, // Quit the application
 
+                        // Stop waiting for a slow request
+                        KeyCode::Esc if self.cancel_pending() => {}
+
+                        KeyCode::Esc => {
+                            // Leave the search hits for the folder they replaced
+                            if let Some(search) = self.search.take() {
+                                self.open_folder(search.previous.clone(), format!("Opening {}", search.previous));
+                            }
+                        }
+
+                        KeyCode::Char('/') => {
+                            // Type a search in the status line
+                            self.mode = Mode::Search;
+                        }
+
                         KeyCode::Char('v') => {
-                            // Open the selected message in View mode
-                            let uid = self.items[self.selected].0;
-                            self.view_buffer = (self.on_view)(uid)?;
-                            self.view_scroll = 0;
-                            self.mode = Mode::View;
-                            self.tooltip.clear();
+                            // Open the selected message (or conversation) in
+                            // View mode once it has loaded
+                            self.view_selected();
                         }
 
-                        KeyCode::Char('r') => {
-                            // Reply to selected message:
-                            //  1) Fetch full text (headers + body)
-                            let uid = self.items[self.selected].0;
-                            let full_text = (self.on_view)(uid)?;
-                            let mut lines = full_text.lines();
-                            let from_line = lines
-                                .next()
-                                .and_then(|l| l.strip_prefix("From: "))
-                                .unwrap_or("")
-                                .to_string();
-                            let subject_line = lines
-                                .next()
-                                .and_then(|l| l.strip_prefix("Subject: "))
-                                .unwrap_or("")
-                                .to_string();
-
-                            //  2) Prefill To: and Subject:
-                            self.compose_to = from_line.clone();
-                            self.compose_subject = if subject_line
-                                .to_lowercase()
-                                .starts_with("re:")
-                            {
-                                subject_line.clone()
+                        KeyCode::Char('t') if self.search.is_none() => {
+                            // One row per conversation, or per message
+                            let uid = self.selected_index().map(|i| self.items[i].uid);
+                            self.threaded = !self.threaded;
+                            self.regroup();
+                            self.select_uid(uid);
+                            self.tooltip = if self.threaded {
+                                "Showing conversations".into()
                             } else {
-                                format!("Re: {}", subject_line)
+                                "Showing single messages".into()
                             };
+                        }
 
-                            //  3) Clear the Body TextArea
-                            self.compose_body = TextArea::default();
+                        KeyCode::Char(key @ ('r' | 'R' | 'f' | 'e')) => {
+                            // Reply, reply to all, forward inline or as an
+                            // attachment: the worker drafts it from the full
+                            // message, then Compose opens (see `respond`)
+                            self.respond(response_for(key));
+                        }
 
-                            //  4) Switch to Compose mode, focusing on Body
-                            self.compose_field = ComposeField::Body;
-                            self.mode = Mode::Compose;
-                            self.tooltip.clear();
+                        KeyCode::Char('u') => {
+                            // Revert the last move / delete / flag change
+                            self.undo_last();
+                        }
+
+                        KeyCode::Char(key @ ('N' | 'F')) => {
+                            // 'N' toggles read/unread (“new”), 'F' the flag (star)
+                            // of the selected message
+                            let flag = if key == 'N' { Flag::Seen } else { Flag::Flagged };
+                            if let (Some(index), Some((folder, uid))) = (self.selected_index(), self.selected_message()) {
+                                let on = !self.items[index].has(flag);
+                                let request = Request::SetFlag { folder, uid, flag, on };
+                                self.start(Action::Flag, "Updating flags".into(), request);
+                            }
                         }
 
                         KeyCode::Char('c') => {
                             // Compose a new blank message
-                            self.compose_to.clear();
-                            self.compose_subject.clear();
-                            self.compose_body = TextArea::default();
-                            self.compose_field = ComposeField::To;
-                            self.mode = Mode::Compose;
-                            self.tooltip.clear();
+                            self.open_compose(Draft::default());
                         }
 
                         KeyCode::Char('m') => {
-                            // Load more messages from IMAP
-                            self.tooltip = "Loading more…".into();
+                            // Load more messages of the current folder (or search hits)
                             self.inbox_count += 10;
-                            self.items = (self.on_refresh)(self.inbox_count)?;
-                            self.selected = 0;
-                            self.tooltip =
-                                format!("Successfully loaded {} messages", self.inbox_count);
+                            if let Some(search) = &self.search {
+                                self.search_input = search.query.clone();
+                                self.submit_search();
+                            } else {
+                                let folder = self.current_folder.clone();
+                                self.open_folder(folder, format!("Loading {} messages", self.inbox_count));
+                            }
                         }
 
-                        KeyCode::Char('d') => {
+                        KeyCode::Char('d') if !self.items.is_empty() => {
                             // Enter ConfirmDelete (first ‘d’)
                             self.mode = Mode::ConfirmDelete;
                             self.tooltip =
-                                "Are you sure you want to delete? Press 'd' again to confirm."
+                                "Move to Trash? Press 'd' again to confirm (in Trash this deletes for good)."
                                     .into();
                         }
 
+                        KeyCode::Char('a') => {
+                            // Move the selected message to the Archive folder
+                            if let Some((folder, uid)) = self.selected_message() {
+                                self.start(Action::Archive, "Archiving".into(), Request::Archive { folder, uid });
+                            }
+                        }
+
                         KeyCode::Down => {
                             // Move highlight down in Inbox
-                            if !self.items.is_empty() {
-                                self.selected = (self.selected + 1) % self.items.len();
+                            if !self.rows.is_empty() {
+                                self.selected = (self.selected + 1) % self.rows.len();
                             }
                             self.tooltip.clear();
                         }
@@ -411,6 +1546,43 @@ where
                             self.tooltip.clear();
                         }
 
+                        KeyCode::Tab | KeyCode::Left => {
+                            // Move focus to the folder sidebar
+                            self.mode = Mode::Folders;
+                            self.tooltip.clear();
+                        }
+
+                        _ => {}
+                    },
+
+                    // ─────────────────────────────────────────────────────────
+                    // MODE: FOLDERS (sidebar focused)
+                    // ─────────────────────────────────────────────────────────
+                    Mode::Folders => match key_event.code {
+                        KeyCode::Char('q') => break,
+
+                        KeyCode::Esc if self.cancel_pending() => {}
+
+                        KeyCode::Down if !self.folders.is_empty() => {
+                            self.folder_selected = (self.folder_selected + 1) % self.folders.len();
+                        }
+
+                        KeyCode::Up => {
+                            self.folder_selected = self.folder_selected.saturating_sub(1);
+                        }
+
+                        KeyCode::Enter => {
+                            // Open the highlighted folder and go back to its message list
+                            if let Some(name) = self.folders.get(self.folder_selected).cloned() {
+                                self.open_folder(name.clone(), format!("Opening {}", name));
+                            }
+                            self.mode = Mode::Inbox;
+                        }
+
+                        KeyCode::Tab | KeyCode::Right | KeyCode::Esc => {
+                            self.mode = Mode::Inbox;
+                        }
+
                         _ => {}
                     },
 
@@ -419,14 +1591,12 @@ where
                     // ─────────────────────────────────────────────────────────
                     Mode::ConfirmDelete => match key_event.code {
                         KeyCode::Char('d') => {
-                            // Second ‘d’ actually deletes
-                            let uid = self.items[self.selected].0;
-                            (self.on_delete)(uid)?;
-                            // Re-fetch inbox so the deleted message disappears
-                            self.items = (self.on_refresh)(self.inbox_count)?;
-                            self.selected = 0;
+                            // Second ‘d’ actually deletes; the worker sends the
+                            // updated list once the server has confirmed
+                            if let Some((folder, uid)) = self.selected_message() {
+                                self.start(Action::Delete, "Deleting".into(), Request::Delete { folder, uid });
+                            }
                             self.mode = Mode::Inbox;
-                            self.tooltip = "Deleted!".into();
                         }
                         KeyCode::Esc => {
                             // Cancel deletion
@@ -440,6 +1610,26 @@ where
                         }
                     },
 
+                    // ─────────────────────────────────────────────────────────
+                    // MODE: SEARCH (typing after ‘/’)
+                    // ─────────────────────────────────────────────────────────
+                    Mode::Search => match key_event.code {
+                        KeyCode::Enter => {
+                            self.mode = Mode::Inbox;
+                            self.submit_search();
+                        }
+                        KeyCode::Esc => {
+                            self.mode = Mode::Inbox;
+                        }
+                        KeyCode::Backspace => {
+                            self.search_input.pop();
+                        }
+                        KeyCode::Char(c) => {
+                            self.search_input.push(c);
+                        }
+                        _ => {}
+                    },
+
                     // ─────────────────────────────────────────────────────────
                     // MODE: VIEW
                     // ─────────────────────────────────────────────────────────
@@ -457,6 +1647,72 @@ where
                             // Scroll up in the message
                             self.view_scroll = self.view_scroll.saturating_sub(1);
                         }
+                        KeyCode::Tab if !self.thread.is_empty() => {
+                            // Next message of the conversation
+                            self.thread_focus = (self.thread_focus + 1).min(self.thread.len() - 1);
+                            self.view_scroll = 0;
+                        }
+                        KeyCode::BackTab => {
+                            // Previous message of the conversation
+                            self.thread_focus = self.thread_focus.saturating_sub(1);
+                            self.view_scroll = 0;
+                        }
+                        KeyCode::Char(c @ '1'..='9') => {
+                            // Save that attachment of the shown message
+                            self.save_attachments(c.to_digit(10).map(|n| n as usize));
+                        }
+                        KeyCode::Char('S') => {
+                            // Save all its attachments
+                            self.save_attachments(None);
+                        }
+                        KeyCode::Char(key @ ('r' | 'R' | 'f' | 'e')) => {
+                            // Reply to or forward the shown message
+                            self.respond(response_for(key));
+                        }
+                        KeyCode::Enter => {
+                            // Expand or collapse the focused message
+                            if let Some(m) = self.thread.get_mut(self.thread_focus) {
+                                m.expanded = !m.expanded;
+                                self.view_scroll = 0;
+                            }
+                        }
+                        _ => {}
+                    },
+
+                    // ─────────────────────────────────────────────────────────
+                    // MODE: ATTACH (file picker over Compose)
+                    // ─────────────────────────────────────────────────────────
+                    Mode::Attach => match key_event.code {
+                        KeyCode::Tab => {
+                            // Complete the path as far as the matches agree
+                            let completion = complete_path(&self.attach_input);
+                            self.tooltip = match completion.candidates.len() {
+                                0 => "No such file".into(),
+                                _ => String::new(),
+                            };
+                            self.attach_input = completion.line;
+                            self.attach_candidates = completion.candidates;
+                        }
+                        KeyCode::Enter => self.attach_file(),
+                        KeyCode::Esc => {
+                            self.mode = Mode::Compose;
+                            self.tooltip.clear();
+                        }
+                        KeyCode::Backspace if self.attach_input.is_empty() => {
+                            // Take the last attachment off again
+                            if let Some(removed) = self.compose_attachments.pop() {
+                                self.tooltip = format!("Removed {}", removed.name());
+                            }
+                        }
+                        KeyCode::Backspace => {
+                            self.attach_input.pop();
+                            self.attach_candidates.clear();
+                        }
+                        KeyCode::Char(c) => {
+                            self.attach_input.push(c);
+                            self.attach_candidates.clear();
+                            self.tooltip.clear();
+                        }
                         _ => {}
                     },
 
@@ -464,29 +1720,26 @@ where
                     // MODE: COMPOSE
                     // ─────────────────────────────────────────────────────────
                     Mode::Compose => {
-                        // 1) ESC = cancel compose → back to Inbox
+                        // 1) ESC = cancel compose → back to Inbox, but not while
+                        //    sending: a failed send must find the draft still here
                         if key_event.code == KeyCode::Esc {
+                            if self.pending.as_ref().is_some_and(|p| matches!(p.action, Action::Send)) {
+                                self.cancel_pending();
+                                continue;
+                            }
                             self.mode = Mode::Inbox;
                             self.tooltip.clear();
                             continue;
                         }
 
-                        // 2) Tab / Shift+Tab = cycle focus among To, Subject, Body
+                        // 2) Tab / Shift+Tab = cycle focus among To, Cc, Bcc, Subject, Body
                         if key_event.code == KeyCode::Tab {
-                            self.compose_field = match self.compose_field {
-                                ComposeField::To => ComposeField::Subject,
-                                ComposeField::Subject => ComposeField::Body,
-                                ComposeField::Body => ComposeField::To,
-                            };
+                            self.compose_field = self.compose_field.next();
                             self.tooltip.clear();
                             continue;
                         }
                         if key_event.code == KeyCode::BackTab {
-                            self.compose_field = match self.compose_field {
-                                ComposeField::To => ComposeField::Body,
-                                ComposeField::Subject => ComposeField::To,
-                                ComposeField::Body => ComposeField::Subject,
-                            };
+                            self.compose_field = self.compose_field.previous();
                             self.tooltip.clear();
                             continue;
                         }
@@ -495,72 +1748,65 @@ where
                         if key_event.code == KeyCode::Char('s')
                             && key_event.modifiers == KeyModifiers::CONTROL
                         {
-                            let body_text = self.compose_body.lines().join("\n");
-                            (self.on_send)(
-                                &self.compose_to,
-                                &self.compose_subject,
-                                &body_text,
-                            )?;
-                            self.mode = Mode::Inbox;
-                            self.tooltip = "Sent!".into();
+                            // Bad addresses are pointed out before anything is sent
+                            if let Some((field, problem)) = self.recipient_problem() {
+                                self.compose_field = field;
+                                self.tooltip = problem;
+                                continue;
+                            }
+                            // Compose stays open until the worker reports the
+                            // message as sent, so a failure keeps the draft
+                            let request = Request::Send { draft: self.draft(), answering: self.compose_answering.clone() };
+                            self.start(Action::Send, "Sending".into(), request);
+                            continue;
+                        }
+
+                        // 3a) Ctrl+G = edit headers and body in $EDITOR
+                        if key_event.code == KeyCode::Char('g')
+                            && key_event.modifiers == KeyModifiers::CONTROL
+                        {
+                            self.edit_externally(&guard, &mut term)?;
+                            continue;
+                        }
+
+                        // 3b) Ctrl+O = pick a file to attach
+                        if key_event.code == KeyCode::Char('o')
+                            && key_event.modifiers == KeyModifiers::CONTROL
+                        {
+                            self.attach_candidates.clear();
+                            self.mode = Mode::Attach;
+                            self.tooltip.clear();
                             continue;
                         }
 
-                        // 4) If focus is To or Subject, handle them manually:
-                        match self.compose_field {
-                            ComposeField::To => {
-                                match key_event.code {
-                                    KeyCode::Char(c) => {
-                                        self.compose_to.push(c);
-                                        self.tooltip.clear();
-                                    }
-                                    KeyCode::Backspace => {
-                                        self.compose_to.pop();
-                                    }
-                                    KeyCode::Enter => {
-                                        // Move focus from To → Subject
-                                        self.compose_field = ComposeField::Subject;
-                                    }
-                                    _ => {}
+                        // 4) If focus is To, Cc, Bcc or Subject, handle them manually:
+                        if let Some(line) = self.compose_line() {
+                            match key_event.code {
+                                KeyCode::Char(c) => line.push(c),
+                                KeyCode::Backspace => {
+                                    line.pop();
                                 }
-                                continue;
-                            }
-                            ComposeField::Subject => {
-                                match key_event.code {
-                                    KeyCode::Char(c) => {
-                                        self.compose_subject.push(c);
-                                        self.tooltip.clear();
-                                    }
-                                    KeyCode::Backspace => {
-                                        self.compose_subject.pop();
-                                    }
-                                    KeyCode::Enter => {
-                                        // Move focus from Subject → Body
-                                        self.compose_field = ComposeField::Body;
-                                    }
-                                    _ => {}
+                                KeyCode::Enter => {
+                                    // Move focus on to the next field
+                                    self.compose_field = self.compose_field.next();
                                 }
-                                continue;
-                            }
-
-                            // 5) If focus is Body, pass the raw KeyEvent to TextArea:
-                            ComposeField::Body => {
-                                // TextArea handles arrow keys, backspace, newline, wrapping, scrolling
-                                self.compose_body.input(key_event.clone());
-                                self.tooltip.clear();
-                                continue;
+                                _ => {}
                             }
+                            self.tooltip.clear();
+                            continue;
                         }
+
+                        // 5) If focus is Body, pass the raw KeyEvent to TextArea:
+                        // TextArea handles arrow keys, backspace, newline, wrapping, scrolling
+                        self.compose_body.input(key_event);
+                        self.tooltip.clear();
                     }
                 }
             }
             // ─────────────────────────────────────────────────────────────────
         }
 
-        // Before exiting, restore normal terminal mode and screen
-        disable_raw_mode()?;
-        let mut stdout = io::stdout();
-        execute!(stdout, LeaveAlternateScreen)?;
+        // `guard` restores normal terminal mode and screen on the way out
         Ok(())
     }
 }
@@ -0,0 +1,73 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Longest name written, in bytes; most file systems stop at 255.
+const MAX_NAME: usize = 200;
+
+/// A file name from a message made safe to create in the download
+/// directory: only its last path component, without control characters,
+/// characters Windows refuses (`<>:"|?*`), leading dots (`..`, hidden
+/// files) or trailing dots and spaces, and cut to a sane length with the
+/// extension kept. A name with nothing left becomes `attachment`.
+pub fn safe_filename(name: &str) -> String
+{
+    let last = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let cleaned: String = last
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if "<>:\"|?*".contains(c) { '_' } else { c })
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.').trim_end_matches(['.', ' ']);
+    if cleaned.is_empty()
+    {
+        return "attachment".into();
+    }
+    if cleaned.len() <= MAX_NAME
+    {
+        return cleaned.to_string();
+    }
+    let (stem, ext) = match cleaned.rsplit_once('.')
+    {
+        Some((stem, ext)) if ext.len() <= 16 => (stem, format!(".{}", ext)),
+        _ => (cleaned, String::new()),
+    };
+    let mut end = MAX_NAME - ext.len();
+    while !stem.is_char_boundary(end)
+    {
+        end -= 1;
+    }
+    format!("{}{}", &stem[..end], ext)
+}
+
+/// Writes `data` to `dir/name`, creating `dir` if needed. A file that is
+/// already there is never overwritten: the name gets a number instead,
+/// `report (1).pdf`, `report (2).pdf`, … Returns the path written.
+pub fn save_attachment(dir: &Path, name: &str, data: &[u8]) -> io::Result<PathBuf>
+{
+    fs::create_dir_all(dir)?;
+    let (stem, ext) = match name.rsplit_once('.')
+    {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+        _ => (name, String::new()),
+    };
+    for n in 0..
+    {
+        let path = match n
+        {
+            0 => dir.join(name),
+            n => dir.join(format!("{} ({}){}", stem, n, ext)),
+        };
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path)
+        {
+            Ok(mut file) =>
+            {
+                file.write_all(data)?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("ran out of file names")
+}
@@ -0,0 +1,740 @@
+use crate::cache::{sync_mailbox, Cache, Hit, SyncReport};
+use crate::config::Config;
+use crate::imap::{
+    apply_overrides, find_special_use, mail_detail, part_data, spawn_watcher, Flag, Folder, ImapClient, ImapError, MailDetail,
+    SearchQuery, SpecialUse, WatchConfig, WatchEvent,
+};
+use crate::compose::{forward, reply, Draft, Response};
+use crate::oauth::TokenSource;
+use crate::smtp::{SmtpClient, SmtpError};
+use crate::worker::attachments::{safe_filename, save_attachment};
+use crate::worker::models::{Done, Event, Request};
+
+use lettre::message::Mailbox;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How many messages of a folder are kept listed until the UI asks for more.
+const DEFAULT_COUNT: usize = 20;
+
+/// An idle connection gets a NOOP this often, well inside the 30 minutes
+/// after which servers may drop it.
+const KEEPALIVE: Duration = Duration::from_secs(5 * 60);
+
+/// The UI's handle on the background threads that do all network and disk
+/// work, so the terminal never waits on a server.
+///
+/// Three threads sit behind it: one runs the UI's requests (viewing,
+/// deleting, sending), one syncs folders into the cache and one watches the
+/// inbox for new mail. Each has its own IMAP connection, so a long sync never
+/// holds up opening a message.
+pub struct Worker
+{
+    requests: Sender<(u64, Request)>,
+    events: Receiver<Event>,
+    cancelled: Arc<Mutex<HashSet<u64>>>,
+    next_id: u64,
+}
+
+impl Worker
+{
+    /// Opens the cache at `cache_path` and starts the threads. Nothing is
+    /// sent to a server until the first request (or the watcher) needs it.
+    ///
+    /// OAuth2 accounts pass their `TokenSource`, which every connection
+    /// asks for a current token whenever it logs in or sends.
+    pub fn start(cfg: Config, tokens: Option<TokenSource>, cache_path: PathBuf) -> Result<Self, Box<dyn Error>>
+    {
+        let cache = Cache::open(&cache_path)?;
+        let smtp = match &tokens
+        {
+            Some(tokens) => SmtpClient::connect_oauth2(&cfg.smtp, &cfg.smtp_user, tokens.clone())?,
+            None => SmtpClient::connect(&cfg.smtp, &cfg.smtp_user, cfg.smtp_pass.expose())?,
+        };
+        let from = Mailbox::new(cfg.display_name.clone(), cfg.email.parse()?);
+
+        let (event_tx, events) = mpsc::channel();
+        let cancelled = Arc::new(Mutex::new(HashSet::new()));
+        let outbox = Outbox { events: event_tx, cancelled: Arc::clone(&cancelled) };
+
+        let folders = cache.folders()?;
+        if !folders.is_empty()
+        {
+            outbox.send(Event::Folders(folders));
+        }
+
+        let sync = spawn_sync(cfg.clone(), tokens.clone(), cache_path, outbox.clone());
+
+        // New mail in the inbox is pushed by IDLE (or noticed by NOOP polling)
+        // on a connection of its own and synced like any other change.
+        let (watch_cfg, watch_tokens, inbox) = (cfg.clone(), tokens.clone(), cfg.folders.inbox.clone());
+        let (watch_sync, watch_outbox) = (sync.clone(), outbox.clone());
+        spawn_watcher(
+            move || connect_imap(&watch_cfg, watch_tokens.as_ref()),
+            cfg.folders.inbox.clone(),
+            WatchConfig::default(),
+            move |event| match event
+            {
+                WatchEvent::Changed => watch_sync.send(SyncRequest::Changed(inbox.clone())).is_ok(),
+                WatchEvent::Error(e) => watch_outbox.send(Event::Status(format!("watch: {}", e))),
+            },
+        );
+
+        let (requests, rx) = mpsc::channel::<(u64, Request)>();
+        let mut handler = Handler
+        {
+            remote: Remote { cfg: cfg.clone(), tokens, client: None },
+            cache,
+            smtp,
+            from,
+            me: [cfg.email].into_iter().chain(cfg.aliases).collect(),
+            signature: cfg.signature,
+            downloads: cfg.download_dir,
+            counts: HashMap::new(),
+            folders: None,
+            sync,
+            outbox,
+        };
+        thread::spawn(move || loop
+        {
+            match rx.recv_timeout(KEEPALIVE)
+            {
+                Ok((id, request)) => handler.handle(id, request),
+                Err(RecvTimeoutError::Timeout) => handler.remote.keepalive(),
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        });
+
+        Ok(Self { requests, events, cancelled, next_id: 1 })
+    }
+
+    /// Queues a request and returns its id.
+    pub fn submit(&mut self, request: Request) -> u64
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        let _ = self.requests.send((id, request));
+        id
+    }
+
+    /// Gives up on a request: it is skipped if it has not started yet, and
+    /// no `Event::Finished` is sent for it either way. A request that is
+    /// already talking to the server still runs to completion, since IMAP
+    /// and SMTP commands cannot be taken back halfway.
+    pub fn cancel(&self, id: u64)
+    {
+        if let Ok(mut cancelled) = self.cancelled.lock()
+        {
+            cancelled.insert(id);
+        }
+    }
+
+    /// The next event, if one is waiting.
+    pub fn try_recv(&self) -> Option<Event>
+    {
+        self.events.try_recv().ok()
+    }
+
+    /// The next event, waiting at most `timeout` for it.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Event>
+    {
+        self.events.recv_timeout(timeout).ok()
+    }
+}
+
+/// The sending half of the event channel, shared by the worker threads.
+#[derive(Clone)]
+struct Outbox
+{
+    events: Sender<Event>,
+    cancelled: Arc<Mutex<HashSet<u64>>>,
+}
+
+impl Outbox
+{
+    /// False once the UI has gone away.
+    fn send(&self, event: Event) -> bool
+    {
+        self.events.send(event).is_ok()
+    }
+
+    fn is_cancelled(&self, id: u64) -> bool
+    {
+        self.cancelled.lock().map(|c| c.contains(&id)).unwrap_or(false)
+    }
+
+    /// Reports the outcome of request `id`, unless it was cancelled.
+    fn finish(&self, id: u64, result: Result<Done, Box<dyn Error>>)
+    {
+        let cancelled = self.cancelled.lock().map(|mut c| c.remove(&id)).unwrap_or(false);
+        if !cancelled
+        {
+            self.send(Event::Finished { id, result: result.map_err(|e| e.to_string()) });
+        }
+    }
+
+    /// Sends the cached summaries of `folder`.
+    fn messages(&self, cache: &Cache, folder: &str, count: usize) -> Result<(), Box<dyn Error>>
+    {
+        let summaries = cache.summaries(folder, count)?;
+        self.send(Event::Messages { folder: folder.to_string(), summaries });
+        Ok(())
+    }
+}
+
+/// Opens an IMAP connection with the account's password or OAuth2 tokens.
+fn connect_imap(cfg: &Config, tokens: Option<&TokenSource>) -> Result<ImapClient, ImapError>
+{
+    match tokens
+    {
+        Some(tokens) => ImapClient::connect_oauth2(&cfg.imap, &cfg.imap_user, tokens.clone()),
+        None => ImapClient::connect(&cfg.imap, &cfg.imap_user, cfg.imap_pass.expose()),
+    }
+}
+
+/// The request thread's IMAP connection, opened on first use so the cached
+/// inbox shows up without waiting for the server.
+struct Remote
+{
+    cfg: Config,
+    tokens: Option<TokenSource>,
+    client: Option<ImapClient>,
+}
+
+impl Remote
+{
+    /// The connection, with `folder` selected.
+    fn client(&mut self, folder: &str) -> Result<&mut ImapClient, ImapError>
+    {
+        if self.client.is_none()
+        {
+            self.client = Some(connect_imap(&self.cfg, self.tokens.as_ref())?);
+        }
+        let client = self.client.as_mut().ok_or(ImapError::ConnectionLost)?;
+        if client.mailbox() != folder
+        {
+            client.select(folder)?;
+        }
+        Ok(client)
+    }
+
+    /// Keeps an open connection from timing out; one that cannot be
+    /// revived is dropped and reopened by the next request.
+    fn keepalive(&mut self)
+    {
+        if let Some(client) = &mut self.client
+        {
+            if client.keepalive(KEEPALIVE).is_err()
+            {
+                self.client = None;
+            }
+        }
+    }
+
+    /// Runs `op` on the connection. If it is still broken after the client's
+    /// own reconnect attempts, it is dropped so the next request starts from
+    /// scratch; a command the server merely refused leaves it open.
+    fn run<T>(
+        &mut self,
+        folder: &str,
+        op: impl FnOnce(&mut ImapClient) -> Result<T, ImapError>,
+    ) -> Result<T, ImapError>
+    {
+        let result = self.client(folder).and_then(op);
+        if result.as_ref().is_err_and(ImapError::is_disconnect)
+        {
+            self.client = None;
+        }
+        result
+    }
+}
+
+/// Runs the UI's requests one at a time on the request thread.
+struct Handler
+{
+    remote: Remote,
+    cache: Cache,
+    smtp: SmtpClient,
+    from: Mailbox,
+    /// Our address and aliases, left out of replies to all.
+    me: Vec<String>,
+    signature: Option<String>,
+    /// Where attachments are saved.
+    downloads: PathBuf,
+    /// How many messages the UI lists per folder, from the last `OpenFolder`.
+    counts: HashMap<String, usize>,
+    /// The server's folders with their roles, listed when first needed.
+    folders: Option<Vec<Folder>>,
+    sync: Sender<SyncRequest>,
+    outbox: Outbox,
+}
+
+impl Handler
+{
+    fn handle(&mut self, id: u64, request: Request)
+    {
+        if self.outbox.is_cancelled(id)
+        {
+            self.outbox.finish(id, Err("cancelled".into()));
+            return;
+        }
+        let result = match request
+        {
+            Request::OpenFolder { folder, count } =>
+            {
+                // The cached list goes out right away; the sync thread
+                // finishes the request once the server has been asked.
+                self.counts.insert(folder.clone(), count);
+                match self.outbox.messages(&self.cache, &folder, count)
+                {
+                    Ok(()) => match self.sync.send(SyncRequest::Folder { id, folder, count })
+                    {
+                        Ok(()) => return,
+                        Err(_) => Err("the sync thread has stopped".into()),
+                    },
+                    Err(e) => Err(e),
+                }
+            }
+            Request::View { folder, uid } => self.view(&folder, uid).map(|detail| Done::Viewed { detail: Box::new(detail) }),
+            Request::ViewThread { folder, uids } =>
+            {
+                self.view_thread(id, &folder, &uids).map(|messages| Done::ViewedThread { messages })
+            }
+            Request::Respond { folder, uid, response } =>
+            {
+                self.respond(&folder, uid, response).map(|draft| Done::Drafted { draft: Box::new(draft) })
+            }
+            Request::SaveAttachments { folder, uid, parts } =>
+            {
+                self.save_attachments(&folder, uid, &parts).map(|paths| Done::Saved { paths })
+            }
+            Request::SetFlag { folder, uid, flag, on } =>
+            {
+                self.set_flag(&folder, uid, flag, on).map(|flags| Done::FlagSet { uid, flags })
+            }
+            Request::Delete { folder, uid } => self.delete(&folder, uid),
+            Request::Archive { folder, uid } => self.archive(&folder, uid),
+            Request::Move { folder, uid, to } => self.move_to(&folder, uid, to),
+            Request::Search { query, count } =>
+            {
+                self.search(id, &query, count).map(|results| Done::Found { query, results })
+            }
+            Request::Send { draft, answering } => match self.send(draft)
+            {
+                Ok(()) =>
+                {
+                    if let Some((folder, uid)) = answering
+                    {
+                        self.mark(&folder, uid, Flag::Answered);
+                    }
+                    Ok(Done::Sent)
+                }
+                Err(e) => Err(e.into()),
+            },
+        };
+        self.outbox.finish(id, result);
+    }
+
+    /// The full RFC 822 message, from the cache if it has been downloaded
+    /// before, else from the server (and then kept in the cache). A message
+    /// the server no longer has is an error and nothing is cached.
+    fn raw(&mut self, folder: &str, uid: u32) -> Result<Vec<u8>, Box<dyn Error>>
+    {
+        if let Some(raw) = self.cache.raw(folder, uid)?
+        {
+            return Ok(raw);
+        }
+        let raw = self.remote.run(folder, |imap| imap.fetch_raw(uid))?.ok_or("message no longer on the server")?;
+        self.cache.store_raw(folder, uid, &raw)?;
+        Ok(raw)
+    }
+
+    /// A message parsed for viewing and replying. The download does not
+    /// touch `\\Seen`; an unread message is marked as read explicitly, so it
+    /// also happens when the body comes from the cache.
+    fn view(&mut self, folder: &str, uid: u32) -> Result<MailDetail, Box<dyn Error>>
+    {
+        let raw = self.raw(folder, uid)?;
+        let detail = mail_detail(uid, &raw)?;
+        if !self.cache.flags(folder, uid)?.is_some_and(|flags| Flag::Seen.is_in(&flags))
+        {
+            self.mark(folder, uid, Flag::Seen);
+        }
+        Ok(detail)
+    }
+
+    /// A reply to or forward of a message, which is marked as read like
+    /// one that is viewed.
+    fn respond(&mut self, folder: &str, uid: u32, response: Response) -> Result<Draft, Box<dyn Error>>
+    {
+        let original = self.view(folder, uid)?;
+        Ok(match response
+        {
+            Response::Reply => reply(&original, false, &self.me),
+            Response::ReplyAll => reply(&original, true, &self.me),
+            Response::Forward => forward(&original, None),
+            // Cached by `view`
+            Response::ForwardAttached => forward(&original, Some(self.raw(folder, uid)?)),
+        })
+    }
+
+    /// Writes the `parts` of a message to the download directory, each
+    /// under its own name made safe by `safe_filename`.
+    fn save_attachments(&mut self, folder: &str, uid: u32, parts: &[usize]) -> Result<Vec<PathBuf>, Box<dyn Error>>
+    {
+        let raw = self.raw(folder, uid)?;
+        let detail = mail_detail(uid, &raw)?;
+        let mut paths = Vec::with_capacity(parts.len());
+        for &index in parts
+        {
+            let part = detail.parts.get(index).ok_or_else(|| format!("the message has no part {}", index))?;
+            let data = part_data(&raw, index)?.unwrap_or_default();
+            let name = safe_filename(part.filename.as_deref().unwrap_or_default());
+            let path = save_attachment(&self.downloads, &name, &data)
+                .map_err(|e| format!("{}: {}", self.downloads.join(&name).display(), e))?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// `view` for each message, stopping early if the request is cancelled.
+    fn view_thread(&mut self, id: u64, folder: &str, uids: &[u32]) -> Result<Vec<MailDetail>, Box<dyn Error>>
+    {
+        let mut messages = Vec::with_capacity(uids.len());
+        for &uid in uids
+        {
+            if self.outbox.is_cancelled(id)
+            {
+                break;
+            }
+            messages.push(self.view(folder, uid)?);
+        }
+        Ok(messages)
+    }
+
+    /// Sets or clears `flag` on the server, then in the cache and the list.
+    fn set_flag(&mut self, folder: &str, uid: u32, flag: Flag, on: bool) -> Result<Vec<String>, Box<dyn Error>>
+    {
+        let flags = self.remote.run(folder, |imap| imap.set_flag(uid, flag, on))?;
+        self.cache.update_flags(folder, &[(uid, flags.clone())])?;
+        self.refresh(folder)?;
+        Ok(flags)
+    }
+
+    /// `set_flag` as a side effect of another request, which should not
+    /// fail because of it: a failure only reaches the status line.
+    fn mark(&mut self, folder: &str, uid: u32, flag: Flag)
+    {
+        if let Err(e) = self.set_flag(folder, uid, flag, true)
+        {
+            self.outbox.send(Event::Status(format!("{}: cannot set {} on message {}: {}", folder, flag.as_str(), uid, e)));
+        }
+    }
+
+    /// Moves a message to Trash. Only what is in Trash already is deleted
+    /// for good, and without a Trash folder nothing is.
+    fn delete(&mut self, folder: &str, uid: u32) -> Result<Done, Box<dyn Error>>
+    {
+        let trash = self
+            .role_folder(folder, &[SpecialUse::Trash])?
+            .ok_or("no Trash folder found; set `trash` in the account's [folders] table")?;
+        if trash != folder
+        {
+            return self.move_to(folder, uid, trash);
+        }
+        self.remote.run(folder, |imap| imap.delete_message(uid))?;
+        self.cache.remove(folder, &[uid])?;
+        self.refresh(folder)?;
+        Ok(Done::Deleted { uid })
+    }
+
+    fn archive(&mut self, folder: &str, uid: u32) -> Result<Done, Box<dyn Error>>
+    {
+        let archive = self
+            .role_folder(folder, &[SpecialUse::Archive, SpecialUse::All])?
+            .ok_or("no Archive folder found; set `archive` in the account's [folders] table")?;
+        if archive == folder
+        {
+            return Err(format!("the message is in {} already", archive).into());
+        }
+        self.move_to(folder, uid, archive)
+    }
+
+    /// Moves a message out of `folder`. A target the UI has listed before
+    /// is synced, so the message shows up there (e.g. after an undo).
+    fn move_to(&mut self, folder: &str, uid: u32, target: String) -> Result<Done, Box<dyn Error>>
+    {
+        let new_uid = self.remote.run(folder, |imap| imap.move_message(uid, &target))?;
+        self.cache.remove(folder, &[uid])?;
+        self.refresh(folder)?;
+        if self.counts.contains_key(&target)
+        {
+            let _ = self.sync.send(SyncRequest::Changed(target.clone()));
+        }
+        Ok(Done::Moved { uid, to: target, new_uid })
+    }
+
+    /// The folder with the first of `roles` the account has, by SPECIAL-USE
+    /// or name with the `[folders]` overrides applied.
+    fn role_folder(&mut self, folder: &str, roles: &[SpecialUse]) -> Result<Option<String>, Box<dyn Error>>
+    {
+        let folders = self.folder_list(folder)?;
+        Ok(roles.iter().find_map(|&role| find_special_use(folders, role)).map(|f| f.name.clone()))
+    }
+
+    /// The server's folders with the `[folders]` overrides applied, listed
+    /// on the connection to `folder` the first time they are needed.
+    fn folder_list(&mut self, folder: &str) -> Result<&[Folder], Box<dyn Error>>
+    {
+        if self.folders.is_none()
+        {
+            let mut folders = self.remote.run(folder, |imap| imap.list_mailboxes())?;
+            apply_overrides(&mut folders, &self.remote.cfg.folders);
+            self.folders = Some(folders);
+        }
+        Ok(self.folders.as_deref().unwrap_or_default())
+    }
+
+    /// Searches the local index, whose ranked matches go to the UI right
+    /// away, then the server, whose other matches are added after them.
+    /// Offline, the cached matches are all there is.
+    fn search(&mut self, id: u64, query: &str, count: usize) -> Result<Vec<Hit>, Box<dyn Error>>
+    {
+        let parsed = SearchQuery::parse(query)?;
+        let mut hits = self.cache.search(&parsed, count)?;
+        self.outbox.send(Event::Found { query: query.to_string(), hits: hits.clone() });
+        let remote = match self.search_server(id, &parsed, count)
+        {
+            Ok(remote) => remote,
+            Err(e) if e.downcast_ref::<ImapError>().is_some_and(ImapError::is_disconnect) =>
+            {
+                self.outbox.send(Event::Status(format!("offline ({}), showing cached matches only", e)));
+                return Ok(hits);
+            }
+            Err(e) => return Err(e),
+        };
+        // A message the cache knows from one folder may turn up in another
+        // on the server (INBOX and All Mail); it is listed once.
+        let mut known: HashSet<HitKey> = hits.iter().map(HitKey::of).collect();
+        hits.extend(remote.into_iter().filter(|h| known.insert(HitKey::of(h))));
+        Ok(hits)
+    }
+
+    /// The newest `count` messages matching `query` on the server, each
+    /// with the folder it is in. Where there is an All Mail folder (Gmail)
+    /// only that is searched, since it holds everything but Trash and Junk;
+    /// elsewhere every folder except those two is, one after the other.
+    fn search_server(&mut self, id: u64, query: &SearchQuery, count: usize) -> Result<Vec<Hit>, Box<dyn Error>>
+    {
+        let inbox = self.remote.cfg.folders.inbox.clone();
+        let folders = self.folder_list(&inbox)?;
+        let targets: Vec<String> = match find_special_use(folders, SpecialUse::All)
+        {
+            Some(all) => vec![all.name.clone()],
+            None => folders
+                .iter()
+                .filter(|f| f.selectable && !matches!(f.special_use, Some(SpecialUse::Trash | SpecialUse::Junk)))
+                .map(|f| f.name.clone())
+                .collect(),
+        };
+        let mut found = Vec::new();
+        for folder in targets
+        {
+            if self.outbox.is_cancelled(id)
+            {
+                break;
+            }
+            let summaries = self.remote.run(&folder, |imap|
+            {
+                let uids = imap.search(query)?;
+                let newest = &uids[uids.len().saturating_sub(count)..];
+                if newest.is_empty()
+                {
+                    return Ok(Vec::new());
+                }
+                imap.fetch_summaries(&newest.iter().map(u32::to_string).collect::<Vec<_>>().join(","))
+            })?;
+            found.extend(summaries.into_iter().map(|summary| Hit { folder: folder.clone(), summary, snippet: None }));
+        }
+        found.sort_by_key(|h| Reverse(h.summary.date));
+        found.truncate(count);
+        Ok(found)
+    }
+
+    /// Sends the cached list of `folder` again after a change.
+    fn refresh(&self, folder: &str) -> Result<(), Box<dyn Error>>
+    {
+        let count = self.counts.get(folder).copied().unwrap_or(DEFAULT_COUNT);
+        self.outbox.messages(&self.cache, folder, count)
+    }
+
+    fn send(&mut self, mut draft: Draft) -> Result<(), SmtpError>
+    {
+        draft.body = match &self.signature
+        {
+            Some(sig) if sig.starts_with("-- ") => format!("{}\n\n{}", draft.body, sig),
+            Some(sig) => format!("{}\n\n-- \n{}", draft.body, sig),
+            None => draft.body,
+        };
+        let email = draft.message(self.from.clone())?;
+        self.smtp.send(email)
+    }
+}
+
+/// Tells search hits for the same message apart from different messages:
+/// by Message-ID, which stays the same in every folder, or for messages
+/// without one by where they are.
+#[derive(PartialEq, Eq, Hash)]
+enum HitKey
+{
+    MessageId(String),
+    Place(String, u32),
+}
+
+impl HitKey
+{
+    fn of(hit: &Hit) -> Self
+    {
+        match &hit.summary.message_id
+        {
+            Some(id) => HitKey::MessageId(id.clone()),
+            None => HitKey::Place(hit.folder.clone(), hit.summary.uid),
+        }
+    }
+}
+
+/// Work for the sync thread.
+enum SyncRequest
+{
+    /// Sync a folder for request `id` and make sure its newest `count`
+    /// messages are cached.
+    Folder { id: u64, folder: String, count: usize },
+    /// The watcher saw the folder change; sync it with the last used count.
+    Changed(String),
+}
+
+/// Starts the thread that syncs folders into the cache. Each sync ends
+/// with an `Event::Messages` read back from the cache.
+fn spawn_sync(cfg: Config, tokens: Option<TokenSource>, cache_path: PathBuf, outbox: Outbox) -> Sender<SyncRequest>
+{
+    let (tx, rx) = mpsc::channel::<SyncRequest>();
+    thread::spawn(move ||
+    {
+        let cache = match open_cache(&cache_path, &outbox)
+        {
+            Some(cache) => cache,
+            None => return,
+        };
+        let mut imap: Option<ImapClient> = None;
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        loop
+        {
+            let request = match rx.recv_timeout(KEEPALIVE)
+            {
+                Ok(request) => request,
+                Err(RecvTimeoutError::Timeout) =>
+                {
+                    if imap.as_mut().is_some_and(|client| client.keepalive(KEEPALIVE).is_err())
+                    {
+                        imap = None;
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+            let (id, folder, count) = match request
+            {
+                SyncRequest::Folder { id, folder, count } =>
+                {
+                    if outbox.is_cancelled(id)
+                    {
+                        outbox.finish(id, Err("cancelled".into()));
+                        continue;
+                    }
+                    (Some(id), folder, count)
+                }
+                SyncRequest::Changed(folder) =>
+                {
+                    let count = counts.get(&folder).copied().unwrap_or(DEFAULT_COUNT);
+                    (None, folder, count)
+                }
+            };
+            counts.insert(folder.clone(), count);
+            let result = sync_folder(&cfg, tokens.as_ref(), &cache, &mut imap, &outbox, &folder, count);
+            let status = match &result
+            {
+                Ok(report) if *report == SyncReport::default() => format!("{} is up to date", folder),
+                Ok(report) => format!(
+                    "{}: {} fetched, {} flag changes, {} removed",
+                    folder, report.fetched, report.flags_changed, report.expunged
+                ),
+                // The server refused something but the connection is fine.
+                Err(e) if e.downcast_ref::<ImapError>().is_some_and(|e| !e.is_disconnect()) =>
+                {
+                    format!("{}: {}", folder, e)
+                }
+                Err(e) =>
+                {
+                    // Reconnect on the next request; until then the cache is all we have.
+                    imap = None;
+                    format!("offline ({}), showing cached mail", e)
+                }
+            };
+            outbox.send(Event::Status(status));
+            if let Some(id) = id
+            {
+                outbox.finish(id, result.map(|report| Done::Synced { folder, report }));
+            }
+        }
+    });
+    tx
+}
+
+fn open_cache(path: &Path, outbox: &Outbox) -> Option<Cache>
+{
+    match Cache::open(path)
+    {
+        Ok(cache) => Some(cache),
+        Err(e) =>
+        {
+            outbox.send(Event::Status(format!("cache unavailable: {}", e)));
+            None
+        }
+    }
+}
+
+fn sync_folder(
+    cfg: &Config,
+    tokens: Option<&TokenSource>,
+    cache: &Cache,
+    imap: &mut Option<ImapClient>,
+    outbox: &Outbox,
+    folder: &str,
+    count: usize,
+) -> Result<SyncReport, Box<dyn Error>>
+{
+    let client = match imap
+    {
+        Some(client) => client,
+        None =>
+        {
+            // First contact: refresh the folder list as well.
+            let mut client = connect_imap(cfg, tokens)?;
+            let mut folders = client.list_mailboxes()?;
+            apply_overrides(&mut folders, &cfg.folders);
+            let names: Vec<String> = folders.into_iter().filter(|f| f.selectable).map(|f| f.name).collect();
+            cache.set_folders(&names)?;
+            outbox.send(Event::Folders(names));
+            imap.insert(client)
+        }
+    };
+    let report = sync_mailbox(client, cache, folder, count)?;
+    outbox.messages(cache, folder, count)?;
+    Ok(report)
+}
@@ -0,0 +1,7 @@
+pub mod attachments;
+pub mod handler;
+pub mod models;
+
+pub use attachments::{safe_filename, save_attachment};
+pub use handler::Worker;
+pub use models::{Done, Event, Request};
@@ -0,0 +1,81 @@
+use crate::cache::{Hit, SyncReport};
+use crate::compose::{Draft, Response};
+use crate::imap::{Flag, MailDetail, MailSummary};
+
+use std::path::PathBuf;
+
+/// Work the UI hands to the worker. Every request gets an id from
+/// `Worker::submit` and ends with exactly one `Event::Finished` carrying that
+/// id, unless it was cancelled.
+#[derive(Debug, Clone)]
+pub enum Request
+{
+    /// Show the cached messages of `folder` right away, then sync it so that
+    /// at least its newest `count` messages are known.
+    OpenFolder { folder: String, count: usize },
+    /// Fetch and parse a message for the View pane, marking it as read.
+    View { folder: String, uid: u32 },
+    /// `View` for every message of a conversation, in the order given.
+    ViewThread { folder: String, uids: Vec<u32> },
+    /// A reply to a message or a forward of it, for Compose.
+    Respond { folder: String, uid: u32, response: Response },
+    /// Save the attachments `parts` (see `MimePart::index`) of a message to
+    /// the download directory under their own names, never overwriting a
+    /// file that is already there.
+    SaveAttachments { folder: String, uid: u32, parts: Vec<usize> },
+    /// Set (`on`) or clear a flag, e.g. to mark a message unread again.
+    SetFlag { folder: String, uid: u32, flag: Flag, on: bool },
+    /// Move a message to Trash; in Trash itself, delete it for good.
+    Delete { folder: String, uid: u32 },
+    /// Move a message to the Archive (or Gmail's All Mail) folder.
+    Archive { folder: String, uid: u32 },
+    /// Move a message to the folder `to`, e.g. back where it came from.
+    Move { folder: String, uid: u32, to: String },
+    /// Search the cached mail and then the server (see `SearchQuery` for
+    /// the syntax) for up to `count` matching messages each, in any folder.
+    Search { query: String, count: usize },
+    /// `answering` is the folder and UID of the message being replied to,
+    /// which gets `\\Answered` once the reply is sent.
+    Send { draft: Draft, answering: Option<(String, u32)> },
+}
+
+/// What a finished request produced.
+#[derive(Debug, Clone)]
+pub enum Done
+{
+    Synced { folder: String, report: SyncReport },
+    Viewed { detail: Box<MailDetail> },
+    /// Each message of a `ViewThread`, in the order asked for.
+    ViewedThread { messages: Vec<MailDetail> },
+    /// The draft of a `Respond`.
+    Drafted { draft: Box<Draft> },
+    /// Where each attachment of a `SaveAttachments` was written.
+    Saved { paths: Vec<PathBuf> },
+    FlagSet { uid: u32, flags: Vec<String> },
+    /// Moved to the folder `to`, e.g. Trash or Archive, where it is
+    /// `new_uid` if the server said so.
+    Moved { uid: u32, to: String, new_uid: Option<u32> },
+    /// Deleted for good.
+    Deleted { uid: u32 },
+    /// Search hits: the cached ones best first, then those only the server
+    /// found, newest first.
+    Found { query: String, results: Vec<Hit> },
+    Sent,
+}
+
+/// Everything the worker tells the UI.
+#[derive(Debug, Clone)]
+pub enum Event
+{
+    /// The newest summaries of a folder, from the cache, newest first. Sent
+    /// whenever they may have changed, not only in reply to a request.
+    Messages { folder: String, summaries: Vec<MailSummary> },
+    /// A new folder list for the sidebar.
+    Folders(Vec<String>),
+    /// Matches of a `Search` from the local index, best first, sent before
+    /// the server is asked.
+    Found { query: String, hits: Vec<Hit> },
+    /// Background news for the status line (new mail, offline, …).
+    Status(String),
+    Finished { id: u64, result: Result<Done, String> },
+}
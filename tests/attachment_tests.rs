@@ -0,0 +1,90 @@
+use bps_mail::imap::{mail_detail, part_data, Disposition};
+use bps_mail::worker::{safe_filename, save_attachment};
+
+const MIXED: &str = "From: Alice <alice@example.com>\r\n\
+                     Subject: files\r\n\
+                     MIME-Version: 1.0\r\n\
+                     Content-Type: multipart/mixed; boundary=\"outer\"\r\n\r\n\
+                     --outer\r\n\
+                     Content-Type: multipart/related; boundary=\"inner\"\r\n\r\n\
+                     --inner\r\n\
+                     Content-Type: text/html; charset=utf-8\r\n\r\n\
+                     <p>our logo: <img src=\"cid:logo@example.com\"></p>\r\n\
+                     --inner\r\n\
+                     Content-Type: image/png\r\n\
+                     Content-ID: <logo@example.com>\r\n\
+                     Content-Disposition: inline\r\n\
+                     Content-Transfer-Encoding: base64\r\n\r\n\
+                     iVBORw0KGgo=\r\n\
+                     --inner--\r\n\
+                     --outer\r\n\
+                     Content-Type: application/PDF; name=\"report.pdf\"\r\n\
+                     Content-Disposition: attachment; filename=\"../../report.pdf\"\r\n\
+                     Content-Transfer-Encoding: base64\r\n\r\n\
+                     JVBERi0xLjQK\r\n\
+                     --outer\r\n\
+                     Content-Type: image/jpeg; name=\"photo.jpg\"\r\n\
+                     Content-Transfer-Encoding: base64\r\n\r\n\
+                     /9j/4A==\r\n\
+                     --outer--\r\n";
+
+#[test]
+fn attachments_come_from_the_mime_tree()
+{
+    let detail = mail_detail(1, MIXED.as_bytes()).unwrap();
+    let summary: Vec<(usize, &str, Option<&str>)> =
+        detail.parts.iter().map(|p| (p.index, p.content_type.as_str(), p.filename.as_deref())).collect();
+    assert_eq!(
+        summary,
+        [
+            (0, "text/html", None),
+            (1, "image/png", None),
+            (2, "application/pdf", Some("../../report.pdf")),
+            (3, "image/jpeg", Some("photo.jpg")),
+        ]
+    );
+    assert_eq!(detail.parts[1].content_id.as_deref(), Some("logo@example.com"));
+    assert_eq!(detail.parts[1].disposition, Some(Disposition::Inline));
+    assert_eq!(detail.parts[2].disposition, Some(Disposition::Attachment));
+    assert_eq!(detail.parts[3].disposition, None);
+    assert_eq!(detail.parts[2].size, 9, "decoded size");
+
+    let attachments: Vec<usize> = detail.attachments().map(|p| p.index).collect();
+    assert_eq!(attachments, [2, 3], "the inline logo is part of the text, the named photo is not");
+    assert_eq!(part_data(MIXED.as_bytes(), 2).unwrap().as_deref(), Some(&b"%PDF-1.4\n"[..]));
+    assert_eq!(part_data(MIXED.as_bytes(), 4).unwrap(), None);
+}
+
+#[test]
+fn file_names_are_made_safe()
+{
+    assert_eq!(safe_filename("report.pdf"), "report.pdf");
+    assert_eq!(safe_filename("../../etc/passwd"), "passwd");
+    assert_eq!(safe_filename("C:\\Users\\me\\notes.txt"), "notes.txt");
+    assert_eq!(safe_filename(".bashrc"), "bashrc", "no hidden files");
+    assert_eq!(safe_filename("what? <really>.txt"), "what_ _really_.txt");
+    assert_eq!(safe_filename("bell\x07\r\n.txt. . "), "bell.txt");
+    assert_eq!(safe_filename(".."), "attachment");
+    assert_eq!(safe_filename(""), "attachment");
+
+    let long = format!("{}.tar.gz", "ü".repeat(300));
+    let safe = safe_filename(&long);
+    assert!(safe.len() <= 200 && safe.ends_with("ü.gz"), "cut at a character, extension kept: {}", safe);
+}
+
+#[test]
+fn saving_never_overwrites()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let downloads = dir.path().join("new").join("downloads");
+    let first = save_attachment(&downloads, "a.txt", b"one").unwrap();
+    let second = save_attachment(&downloads, "a.txt", b"two").unwrap();
+    let bare = save_attachment(&downloads, "README", b"three").unwrap();
+    let again = save_attachment(&downloads, "README", b"four").unwrap();
+
+    assert_eq!(first, downloads.join("a.txt"));
+    assert_eq!(second, downloads.join("a (1).txt"));
+    assert_eq!(again, downloads.join("README (1)"));
+    assert_eq!(std::fs::read_to_string(first).unwrap(), "one");
+    assert_eq!(std::fs::read_to_string(bare).unwrap(), "three");
+}
@@ -0,0 +1,196 @@
+mod common;
+
+use bps_mail::cache::{sync_mailbox, Cache, SyncReport};
+use common::{connect, MockMessage, MockServer, MockState};
+
+fn condstore_server(count: u32) -> MockServer
+{
+    let mut state = MockState::new((1..=count).map(MockMessage::numbered).collect());
+    state.capabilities.push("CONDSTORE".into());
+    MockServer::with_state(state)
+}
+
+fn cached_uids(cache: &Cache, count: usize) -> Vec<u32>
+{
+    cache.summaries("INBOX", count).unwrap().iter().map(|m| m.uid).collect()
+}
+
+/// Commands sent since the last call, without CAPABILITY/LOGIN noise.
+fn take_log(server: &MockServer) -> Vec<String>
+{
+    let mut st = server.state.lock().unwrap();
+    std::mem::take(&mut st.log)
+        .into_iter()
+        .filter(|l| !l.starts_with("CAPABILITY") && !l.starts_with("LOGIN"))
+        .collect()
+}
+
+#[test]
+fn first_sync_caches_the_newest_messages()
+{
+    let server = condstore_server(100);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    let mut imap = connect(&server);
+
+    let report = sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+    assert_eq!(report.fetched, 10);
+    assert_eq!(cached_uids(&cache, 100), (91..=100).rev().collect::<Vec<_>>());
+
+    // Reopening the file gives the same rows without touching the server.
+    drop(cache);
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    assert_eq!(cache.summaries("INBOX", 1).unwrap()[0].from, "sender100@example.com");
+}
+
+#[test]
+fn growing_the_window_fills_gaps_by_uid()
+{
+    let server = condstore_server(10);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    let mut imap = connect(&server);
+    sync_mailbox(&mut imap, &cache, "INBOX", 3).unwrap();
+    cache.remove("INBOX", &[9]).unwrap();
+    take_log(&server);
+
+    let report = sync_mailbox(&mut imap, &cache, "INBOX", 5).unwrap();
+    assert_eq!(report.fetched, 3);
+    assert_eq!(cached_uids(&cache, 100), [10, 9, 8, 7, 6]);
+    let log = take_log(&server);
+    assert!(log.contains(&"UID SEARCH 6:*".to_string()), "{:?}", log);
+    assert!(log.iter().any(|l| l.starts_with("UID FETCH 6,7,9 ")), "{:?}", log);
+}
+
+#[test]
+fn unchanged_mailbox_costs_one_status_with_condstore()
+{
+    let server = condstore_server(30);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    let mut imap = connect(&server);
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+    take_log(&server);
+
+    let report = sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+    assert_eq!(report, SyncReport::default());
+    assert_eq!(take_log(&server), ["STATUS \"INBOX\" (MESSAGES UIDNEXT UIDVALIDITY HIGHESTMODSEQ)"]);
+}
+
+#[test]
+fn cache_from_an_older_version_is_rebuilt()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("c.sqlite");
+    {
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE messages (mailbox TEXT, uid_validity INTEGER, uid INTEGER, sender TEXT,
+                                    date TEXT, timestamp INTEGER, flags TEXT, raw BLOB);
+             INSERT INTO messages VALUES ('INBOX', 1, 1, 'old@example.com', '', 0, '', NULL);",
+        )
+        .unwrap();
+    }
+
+    let server = condstore_server(3);
+    let cache = Cache::open(&path).unwrap();
+    assert!(cache.summaries("INBOX", 10).unwrap().is_empty());
+    sync_mailbox(&mut connect(&server), &cache, "INBOX", 10).unwrap();
+    let newest = &cache.summaries("INBOX", 10).unwrap()[0];
+    assert_eq!((newest.sender(), newest.subject.as_str()), ("Sender 3", "message 3"));
+}
+
+#[test]
+fn incremental_sync_fetches_only_what_changed()
+{
+    let server = condstore_server(30);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    let mut imap = connect(&server);
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+    take_log(&server);
+
+    {
+        let mut st = server.state.lock().unwrap();
+        let inbox = st.mailboxes.get_mut("INBOX").unwrap();
+        inbox.push(MockMessage::numbered(31));
+        inbox.push(MockMessage::numbered(32));
+        inbox.retain(|m| m.uid != 25);
+        st.set_flags("INBOX", 28, &["\\Seen", "\\Flagged"]);
+    }
+
+    let report = sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+    assert_eq!(report, SyncReport { fetched: 2, flags_changed: 1, expunged: 1 });
+
+    let log = take_log(&server);
+    assert!(log.contains(&"UID FETCH 31:* (UID FLAGS INTERNALDATE RFC822.SIZE BODY.PEEK[HEADER.FIELDS (FROM TO CC SUBJECT DATE CONTENT-TYPE MESSAGE-ID IN-REPLY-TO REFERENCES)])".to_string()), "{:?}", log);
+    assert!(log.iter().any(|l| l.starts_with("UID FETCH 21:30 (UID FLAGS) (CHANGEDSINCE 1)")), "{:?}", log);
+    assert!(log.contains(&"UID SEARCH UID 21:*".to_string()), "{:?}", log);
+
+    let uids = cached_uids(&cache, 100);
+    assert_eq!(uids[..3], [32, 31, 30]);
+    assert!(!uids.contains(&25));
+    assert_eq!(uids.len(), 11);
+    let flagged = cache.summaries("INBOX", 100).unwrap().into_iter().find(|m| m.uid == 28).unwrap();
+    assert_eq!(flagged.flags, ["\\Seen", "\\Flagged"]);
+}
+
+#[test]
+fn flags_are_refetched_without_condstore()
+{
+    let server = MockServer::start((1..=5).map(MockMessage::numbered).collect());
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    let mut imap = connect(&server);
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+
+    server.state.lock().unwrap().set_flags("INBOX", 2, &["\\Seen"]);
+    take_log(&server);
+    let report = sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+    assert_eq!(report, SyncReport { fetched: 0, flags_changed: 1, expunged: 0 });
+    let log = take_log(&server);
+    assert!(log.contains(&"UID FETCH 1:5 (UID FLAGS)".to_string()), "{:?}", log);
+    assert_eq!(log.iter().filter(|l| l.starts_with("STATUS")).count(), 0);
+}
+
+#[test]
+fn uidvalidity_change_resets_the_mailbox()
+{
+    let server = condstore_server(5);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    let mut imap = connect(&server);
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+    cache.store_raw("INBOX", 5, b"old copy").unwrap();
+
+    {
+        let mut st = server.state.lock().unwrap();
+        st.uid_validity = 2;
+        *st.mailboxes.get_mut("INBOX").unwrap() = vec![MockMessage::new(1, "new@example.com", "renumbered", 3, "x")];
+    }
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+
+    let summaries = cache.summaries("INBOX", 10).unwrap();
+    assert_eq!(summaries.len(), 1);
+    assert_eq!(summaries[0].from, "new@example.com");
+    assert_eq!(cache.raw("INBOX", 5).unwrap(), None);
+    assert_eq!(cache.mailbox_status("INBOX").unwrap().unwrap().uid_validity, 2);
+}
+
+#[test]
+fn downloaded_messages_survive_resyncs()
+{
+    let server = condstore_server(3);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    let mut imap = connect(&server);
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+
+    let raw = imap.fetch_raw(2).unwrap().unwrap();
+    cache.store_raw("INBOX", 2, &raw).unwrap();
+    server.state.lock().unwrap().set_flags("INBOX", 2, &["\\Seen"]);
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+
+    assert_eq!(cache.raw("INBOX", 2).unwrap().as_deref(), Some(raw.as_slice()));
+    assert_eq!(cache.raw("INBOX", 1).unwrap(), None);
+}
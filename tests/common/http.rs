@@ -0,0 +1,94 @@
+// A minimal HTTP/1.1 server standing in for an OAuth2 token endpoint.
+//
+// Each path maps to a canned JSON body, in which `{n}` stands for how many
+// requests that path has had so far; every request (path + form body) is
+// recorded so tests can check what the client sent.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub struct MockHttp
+{
+    pub base: String,
+    pub requests: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl MockHttp
+{
+    /// `routes` maps a path such as `/token` to `(status, json body)`.
+    pub fn start(routes: Vec<(&str, u16, &str)>) -> Self
+    {
+        let routes: HashMap<String, (u16, String)> = routes
+            .into_iter()
+            .map(|(p, status, body)| (p.to_string(), (status, body.to_string())))
+            .collect();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let log = Arc::clone(&requests);
+        thread::spawn(move ||
+        {
+            for stream in listener.incoming()
+            {
+                let Ok(stream) = stream else { break };
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                if reader.read_line(&mut line).is_err()
+                {
+                    continue;
+                }
+                let path = line.split_whitespace().nth(1).unwrap_or("/").to_string();
+
+                let mut length = 0;
+                loop
+                {
+                    let mut header = String::new();
+                    if reader.read_line(&mut header).unwrap_or(0) == 0 || header == "\r\n"
+                    {
+                        break;
+                    }
+                    let lower = header.to_ascii_lowercase();
+                    if let Some(v) = lower.strip_prefix("content-length:")
+                    {
+                        length = v.trim().parse().unwrap_or(0);
+                    }
+                }
+                let mut body = vec![0; length];
+                let _ = reader.read_exact(&mut body);
+                let n =
+                {
+                    let mut log = log.lock().unwrap();
+                    log.push((path.clone(), String::from_utf8_lossy(&body).into_owned()));
+                    log.iter().filter(|(p, _)| *p == path).count()
+                };
+
+                let (status, reply) = routes.get(&path).cloned().unwrap_or((404, "{}".into()));
+                let reply = reply.replace("{n}", &n.to_string());
+                let mut writer = &stream;
+                let _ = write!(
+                    writer,
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    reply.len(),
+                    reply
+                );
+            }
+        });
+
+        Self { base, requests }
+    }
+
+    pub fn url(&self, path: &str) -> String
+    {
+        format!("{}{}", self.base, path)
+    }
+
+    pub fn requests(&self) -> Vec<(String, String)>
+    {
+        self.requests.lock().unwrap().clone()
+    }
+}
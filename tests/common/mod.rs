@@ -0,0 +1,1040 @@
+// A tiny in-process IMAP server for integration tests.
+//
+// It understands just enough of RFC 3501 for `ImapClient`: LOGIN,
+// AUTHENTICATE XOAUTH2, LIST, SELECT, STATUS,
+// FETCH / UID FETCH with the attributes the client asks for, UID STORE,
+// UID SEARCH (by sequence number, UID, header, text, date, flag, size or
+// X-GM-RAW), UID COPY, EXPUNGE, NOOP, IDLE and LOGOUT, UID MOVE, UID EXPUNGE and
+// UID THREAD when MOVE / UIDPLUS / THREAD=REFERENCES are in `capabilities`,
+// Gmail's X-GM-THRID, plus CONDSTORE's MODSEQ
+// and CHANGEDSINCE. `RFC822` and `BODY[]` set \Seen, `BODY.PEEK[]` does
+// not. NOOP and IDLE report EXISTS / FETCH for changes other
+// clients (i.e. the test) made to the selected mailbox. Literal arguments,
+// `{n}` and LITERAL+'s `{n+}`, are read wherever a command has one. Every
+// command line is logged so tests can assert on round trips.
+
+#![allow(dead_code)]
+
+pub mod http;
+
+use base64::Engine;
+use bps_mail::config::{Security, ServerConfig, Timeouts};
+use bps_mail::imap::ImapClient;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct MockMessage
+{
+    pub uid: u32,
+    pub internal_date: String,
+    pub flags: Vec<String>,
+    pub raw: String,
+    /// CONDSTORE mod-sequence of the last flag change.
+    pub modseq: u64,
+    /// The conversation the server puts the message in, reported as
+    /// `X-GM-THRID` and used to group `UID THREAD` results; its own UID
+    /// unless a test says otherwise.
+    pub thread: u64,
+}
+
+impl MockMessage
+{
+    /// Whether the message, number `seq` of `count` in its mailbox, meets
+    /// every one of the SEARCH `criteria`.
+    fn matches(&self, criteria: &[String], seq: u32, count: u32, max: u32) -> bool
+    {
+        let raw = self.raw.to_lowercase();
+        let (headers, body) = raw.split_once("\r\n\r\n").unwrap_or((&raw, ""));
+        let header = |name: &str| headers.lines().find_map(|l| l.strip_prefix(&format!("{}: ", name))).unwrap_or("");
+        let day = chrono::NaiveDate::parse_from_str(self.internal_date.split(' ').next().unwrap(), "%d-%b-%Y").unwrap();
+        let date = |s: &str| chrono::NaiveDate::parse_from_str(s, "%d-%b-%Y").unwrap();
+        let has = |flag: &str| self.flags.iter().any(|f| f.eq_ignore_ascii_case(flag));
+        let mut words = criteria.iter();
+        while let Some(key) = words.next()
+        {
+            let mut value = || words.next().map(|v| v.to_lowercase()).unwrap_or_default();
+            let ok = match key.to_ascii_uppercase().as_str()
+            {
+                "CHARSET" => !value().is_empty(),
+                "UID" => in_set(&value(), self.uid, max),
+                "FROM" => header("from").contains(&value()),
+                "TO" => header("to").contains(&value()),
+                "SUBJECT" => header("subject").contains(&value()),
+                "BODY" => body.contains(&value()),
+                "TEXT" => raw.contains(&value()),
+                "SINCE" => day >= date(&value()),
+                "BEFORE" => day < date(&value()),
+                "SEEN" => has("\\Seen"),
+                "UNSEEN" => !has("\\Seen"),
+                "FLAGGED" => has("\\Flagged"),
+                "LARGER" => self.raw.len() > value().parse().unwrap_or(usize::MAX),
+                "X-GM-RAW" => value().split_whitespace().all(|w| match w
+                {
+                    "is:unread" => !has("\\Seen"),
+                    "is:starred" => has("\\Flagged"),
+                    w => raw.contains(w.rsplit(':').next().unwrap()),
+                }),
+                set if set.starts_with(|c: char| c.is_ascii_digit() || c == '*') => in_set(set, seq, count),
+                _ => false,
+            };
+            if !ok
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Builds a simple text/plain message. `day` is used for both the Date
+    /// header and INTERNALDATE so ordering by either gives the same result.
+    pub fn new(uid: u32, from: &str, subject: &str, day: u32, body: &str) -> Self
+    {
+        let date = format!("Mon, {:02} Jan 2024 10:00:00 +0000", day);
+        let raw = format!(
+            "From: {}\r\nTo: me@example.com\r\nSubject: {}\r\nDate: {}\r\n\
+             Message-ID: <{}@example.com>\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            from, subject, date, uid, body
+        );
+        Self
+        {
+            uid,
+            internal_date: format!("{:02}-Jan-2024 10:00:00 +0000", day),
+            flags: Vec::new(),
+            raw,
+            modseq: 1,
+            thread: u64::from(uid),
+        }
+    }
+
+    /// Message number `n` of a large mailbox: one minute apart, starting
+    /// 2024-01-01, so higher UIDs are always newer.
+    pub fn numbered(n: u32) -> Self
+    {
+        let at = chrono::DateTime::from_timestamp(1_704_067_200 + i64::from(n) * 60, 0).unwrap();
+        let raw = format!(
+            "From: Sender {n} <sender{n}@example.com>\r\nTo: me@example.com\r\nSubject: message {n}\r\n\
+             Date: {}\r\nMessage-ID: <{n}@example.com>\r\n\r\nbody {n}\r\n",
+            at.to_rfc2822()
+        );
+        Self
+        {
+            uid: n,
+            internal_date: at.format("%d-%b-%Y %H:%M:%S +0000").to_string(),
+            flags: Vec::new(),
+            raw,
+            modseq: 1,
+            thread: u64::from(n),
+        }
+    }
+
+    pub fn with_raw(uid: u32, day: u32, raw: &str) -> Self
+    {
+        Self
+        {
+            uid,
+            internal_date: format!("{:02}-Jan-2024 10:00:00 +0000", day),
+            flags: Vec::new(),
+            raw: raw.replace("\r\n", "\n").replace('\n', "\r\n"),
+            modseq: 1,
+            thread: u64::from(uid),
+        }
+    }
+
+    fn header_block(&self) -> &str
+    {
+        match self.raw.find("\r\n\r\n")
+        {
+            Some(i) => &self.raw[..i + 4],
+            None => &self.raw,
+        }
+    }
+
+    /// Header lines (with folded continuations) whose name is in `fields`.
+    fn header_fields(&self, fields: &[String]) -> String
+    {
+        let mut out = String::new();
+        let mut keep = false;
+        for line in self.header_block().split("\r\n")
+        {
+            if line.is_empty()
+            {
+                break;
+            }
+            if line.starts_with(' ') || line.starts_with('\t')
+            {
+                if keep
+                {
+                    out.push_str(line);
+                    out.push_str("\r\n");
+                }
+                continue;
+            }
+            let name = line.split(':').next().unwrap_or("").to_ascii_uppercase();
+            keep = fields.iter().any(|f| f.eq_ignore_ascii_case(&name));
+            if keep
+            {
+                out.push_str(line);
+                out.push_str("\r\n");
+            }
+        }
+        out.push_str("\r\n");
+        out
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MockState
+{
+    pub mailboxes: BTreeMap<String, Vec<MockMessage>>,
+    /// Extra `LIST` attributes per mailbox, e.g. `\Sent` or `\Noselect`.
+    pub attributes: BTreeMap<String, Vec<String>>,
+    pub uid_validity: u32,
+    pub capabilities: Vec<String>,
+    pub user: String,
+    pub pass: String,
+    /// Access token accepted by `AUTHENTICATE XOAUTH2`.
+    pub token: String,
+    /// HIGHESTMODSEQ, shared by all mailboxes.
+    pub modseq: u64,
+    /// Every command line received, without its tag.
+    pub log: Vec<String>,
+    /// While set, new connections are closed before the greeting.
+    pub down: bool,
+    /// The next command with this verb gets no answer at all, like a server
+    /// that hung.
+    pub stall: Option<String>,
+    /// The connection is closed right after answering the next command that
+    /// starts with this, e.g. `UID COPY`, as if it broke just then.
+    pub drop_after: Option<String>,
+}
+
+impl MockState
+{
+    pub fn new(inbox: Vec<MockMessage>) -> Self
+    {
+        let mut state = MockState
+        {
+            uid_validity: 1,
+            capabilities: vec!["IMAP4rev1".into()],
+            user: "user@example.com".into(),
+            pass: "secret".into(),
+            token: "access-token".into(),
+            modseq: 1,
+            ..Default::default()
+        };
+        state.mailboxes.insert("INBOX".into(), inbox);
+        state
+    }
+
+    /// Changes the flags of `uid` in `mailbox` the way another client would.
+    pub fn set_flags(&mut self, mailbox: &str, uid: u32, flags: &[&str])
+    {
+        self.modseq += 1;
+        let modseq = self.modseq;
+        let m = self.mailboxes.get_mut(mailbox).unwrap().iter_mut().find(|m| m.uid == uid).unwrap();
+        m.flags = flags.iter().map(|f| f.to_string()).collect();
+        m.modseq = modseq;
+    }
+
+    pub fn commands(&self, verb: &str) -> usize
+    {
+        let verb = verb.to_ascii_uppercase();
+        self.log.iter().filter(|l| l.to_ascii_uppercase().starts_with(&verb)).count()
+    }
+}
+
+pub struct MockServer
+{
+    pub addr: SocketAddr,
+    pub state: Arc<Mutex<MockState>>,
+    /// Open client connections, for `kill_connections`.
+    connections: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl MockServer
+{
+    pub fn start(messages: Vec<MockMessage>) -> Self
+    {
+        Self::with_state(MockState::new(messages))
+    }
+
+    pub fn with_state(state: MockState) -> Self
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(state));
+
+        let connections = Arc::new(Mutex::new(Vec::new()));
+
+        let (shared, open) = (Arc::clone(&state), Arc::clone(&connections));
+        thread::spawn(move ||
+        {
+            for stream in listener.incoming()
+            {
+                let Ok(stream) = stream else { break };
+                if shared.lock().unwrap().down
+                {
+                    continue;
+                }
+                open.lock().unwrap().push(stream.try_clone().unwrap());
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || Connection::new(stream, shared).serve());
+            }
+        });
+
+        Self { addr, state, connections }
+    }
+
+    /// Drops every open connection, as a server or NAT box timing out idle
+    /// clients would. The server keeps accepting new ones.
+    pub fn kill_connections(&self)
+    {
+        for stream in self.connections.lock().unwrap().drain(..)
+        {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+
+    pub fn port(&self) -> u16
+    {
+        self.addr.port()
+    }
+
+    pub fn commands(&self, verb: &str) -> usize
+    {
+        self.state.lock().unwrap().commands(verb)
+    }
+}
+
+/// Plaintext settings for `server` with the default timeouts.
+pub fn local(server: &MockServer) -> ServerConfig
+{
+    ServerConfig
+    {
+        host: "127.0.0.1".into(),
+        port: server.port(),
+        security: Security::Plain,
+        timeouts: Timeouts::default(),
+    }
+}
+
+/// A client logged in to `server` with the credentials every mock accepts.
+pub fn connect(server: &MockServer) -> ImapClient
+{
+    ImapClient::connect(&local(server), "user@example.com", "secret").unwrap()
+}
+
+struct Connection
+{
+    stream: TcpStream,
+    state: Arc<Mutex<MockState>>,
+    selected: Option<String>,
+    /// Tag of an `AUTHENTICATE` waiting for the client's SASL response line.
+    pending_auth: Option<String>,
+    /// Tag of a running `IDLE`, ended by the client's `DONE`.
+    idling: Option<String>,
+    /// Message count and highest modseq of the selected mailbox as last
+    /// reported to the client.
+    seen: (usize, u64),
+}
+
+impl Connection
+{
+    fn new(stream: TcpStream, state: Arc<Mutex<MockState>>) -> Self
+    {
+        Self { stream, state, selected: None, pending_auth: None, idling: None, seen: (0, 0) }
+    }
+
+    fn send(&mut self, data: &str) -> std::io::Result<()>
+    {
+        self.stream.write_all(data.as_bytes())
+    }
+
+    fn serve(mut self)
+    {
+        if self.send("* OK mock IMAP ready\r\n").is_err()
+        {
+            return;
+        }
+        let mut reader = BufReader::new(self.stream.try_clone().unwrap());
+        let mut line = String::new();
+        loop
+        {
+            match reader.read_line(&mut line)
+            {
+                Ok(0) => return,
+                Ok(_) => {}
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    // Idling: push whatever changed, keep any partial line.
+                    let changes = self.changes();
+                    if !changes.is_empty() && self.send(&changes).is_err()
+                    {
+                        return;
+                    }
+                    continue;
+                }
+                Err(_) => return,
+            }
+            let mut raw = line.trim_end().to_string();
+            let mut trimmed = raw.clone();
+            line.clear();
+            // Literals, `{n}` or LITERAL+'s `{n+}`: the data is read and the
+            // command handled with it as a quoted string, but logged as sent.
+            while let Some((head, n, synchronizing)) = literal_at_end(&trimmed)
+            {
+                if synchronizing && self.send("+ Ready for literal data\r\n").is_err()
+                {
+                    return;
+                }
+                let mut data = vec![0; n];
+                let mut after = String::new();
+                if reader.read_exact(&mut data).is_err() || reader.read_line(&mut after).is_err()
+                {
+                    return;
+                }
+                let data = String::from_utf8_lossy(&data).into_owned();
+                raw = format!("{}\r\n{}{}", raw, data, after.trim_end());
+                trimmed = format!("{}\"{}\"{}", head, data.replace('\\', "\\\\").replace('"', "\\\""), after.trim_end());
+            }
+            if let Some(tag) = self.idling.take()
+            {
+                let _ = self.stream.set_read_timeout(None);
+                let reply = if trimmed.eq_ignore_ascii_case("DONE")
+                {
+                    format!("{} OK IDLE terminated\r\n", tag)
+                }
+                else
+                {
+                    format!("{} BAD expected DONE\r\n", tag)
+                };
+                if self.send(&reply).is_err()
+                {
+                    return;
+                }
+                continue;
+            }
+            if let Some(tag) = self.pending_auth.take()
+            {
+                let reply = self.finish_xoauth2(&tag, &trimmed);
+                if self.send(&reply).is_err()
+                {
+                    return;
+                }
+                continue;
+            }
+            let (tag, rest) = match trimmed.split_once(' ')
+            {
+                Some(parts) => parts,
+                None => continue,
+            };
+            let drop_after =
+            {
+                let mut state = self.state.lock().unwrap();
+                state.log.push(raw.split_once(' ').map_or(rest, |(_, sent)| sent).to_string());
+                let verb = rest.split(' ').next().unwrap_or("");
+                if state.stall.as_deref().is_some_and(|s| s.eq_ignore_ascii_case(verb))
+                {
+                    state.stall = None;
+                    continue;
+                }
+                let upper = rest.to_ascii_uppercase();
+                state.drop_after.take_if(|prefix| upper.starts_with(&prefix.to_ascii_uppercase())).is_some()
+            };
+            let reply = self.handle(tag, rest);
+            if self.send(&reply).is_err()
+            {
+                return;
+            }
+            if drop_after
+            {
+                let _ = self.stream.shutdown(std::net::Shutdown::Both);
+                return;
+            }
+            if self.idling.is_some()
+            {
+                let _ = self.stream.set_read_timeout(Some(Duration::from_millis(20)));
+            }
+            if rest.eq_ignore_ascii_case("LOGOUT")
+            {
+                return;
+            }
+        }
+    }
+
+    fn handle(&mut self, tag: &str, rest: &str) -> String
+    {
+        let (verb, args) = rest.split_once(' ').unwrap_or((rest, ""));
+        let verb = verb.to_ascii_uppercase();
+        match verb.as_str()
+        {
+            "CAPABILITY" =>
+            {
+                let caps = self.state.lock().unwrap().capabilities.join(" ");
+                format!("* CAPABILITY {}\r\n{} OK CAPABILITY completed\r\n", caps, tag)
+            }
+            "LOGIN" =>
+            {
+                let parts: Vec<String> = args.split(' ').map(unquote).collect();
+                let st = self.state.lock().unwrap();
+                if parts.len() == 2 && parts[0] == st.user && parts[1] == st.pass
+                {
+                    format!("{} OK LOGIN completed\r\n", tag)
+                }
+                else
+                {
+                    format!("{} NO [AUTHENTICATIONFAILED] invalid credentials\r\n", tag)
+                }
+            }
+            "AUTHENTICATE" if args.eq_ignore_ascii_case("XOAUTH2") =>
+            {
+                self.pending_auth = Some(tag.to_string());
+                "+ \r\n".to_string()
+            }
+            "LIST" =>
+            {
+                let st = self.state.lock().unwrap();
+                let mut out = String::new();
+                for name in st.mailboxes.keys()
+                {
+                    let attrs = st.attributes.get(name).map(|a| a.join(" ")).unwrap_or_default();
+                    out.push_str(&format!("* LIST ({}) \"/\" \"{}\"\r\n", attrs, name));
+                }
+                format!("{}{} OK LIST completed\r\n", out, tag)
+            }
+            "SELECT" | "EXAMINE" =>
+            {
+                let name = unquote(args);
+                let st = self.state.lock().unwrap();
+                match st.mailboxes.get(&name)
+                {
+                    Some(msgs) =>
+                    {
+                        let uid_next = msgs.iter().map(|m| m.uid).max().unwrap_or(0) + 1;
+                        let reply = format!(
+                            "* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n\
+                             * {} EXISTS\r\n* 0 RECENT\r\n\
+                             * OK [UIDVALIDITY {}] UIDs valid\r\n\
+                             * OK [UIDNEXT {}] Predicted next UID\r\n\
+                             {} OK [READ-WRITE] {} completed\r\n",
+                            msgs.len(), st.uid_validity, uid_next, tag, verb
+                        );
+                        self.seen = (msgs.len(), msgs.iter().map(|m| m.modseq).max().unwrap_or(0));
+                        drop(st);
+                        self.selected = Some(name);
+                        reply
+                    }
+                    None => format!("{} NO no such mailbox\r\n", tag),
+                }
+            }
+            "STATUS" =>
+            {
+                let (name, items) = args.split_once(' ').unwrap_or((args, ""));
+                let name = unquote(name);
+                let st = self.state.lock().unwrap();
+                let Some(msgs) = st.mailboxes.get(&name)
+                else
+                {
+                    return format!("{} NO no such mailbox\r\n", tag);
+                };
+                let mut out = Vec::new();
+                for item in items.trim_matches(|c| c == '(' || c == ')').split_whitespace()
+                {
+                    match item.to_ascii_uppercase().as_str()
+                    {
+                        "MESSAGES" => out.push(format!("MESSAGES {}", msgs.len())),
+                        "UIDNEXT" => out.push(format!("UIDNEXT {}", msgs.iter().map(|m| m.uid).max().unwrap_or(0) + 1)),
+                        "UIDVALIDITY" => out.push(format!("UIDVALIDITY {}", st.uid_validity)),
+                        "HIGHESTMODSEQ" => out.push(format!("HIGHESTMODSEQ {}", st.modseq)),
+                        _ => {}
+                    }
+                }
+                format!("* STATUS \"{}\" ({})\r\n{} OK STATUS completed\r\n", name, out.join(" "), tag)
+            }
+            "FETCH" => self.fetch(tag, args, false),
+            "UID" =>
+            {
+                let (sub, sub_args) = args.split_once(' ').unwrap_or((args, ""));
+                match sub.to_ascii_uppercase().as_str()
+                {
+                    "FETCH" => self.fetch(tag, sub_args, true),
+                    "STORE" => self.store(tag, sub_args),
+                    "SEARCH" => self.search(tag, sub_args),
+                    "THREAD" if self.has_capability("THREAD=REFERENCES") => self.thread(tag, sub_args),
+                    "COPY" => self.copy(tag, sub_args, false),
+                    "MOVE" if self.has_capability("MOVE") => self.copy(tag, sub_args, true),
+                    "EXPUNGE" if self.has_capability("UIDPLUS") => self.expunge(tag, Some(sub_args)),
+                    _ => format!("{} BAD unsupported UID command\r\n", tag),
+                }
+            }
+            "EXPUNGE" => self.expunge(tag, None),
+            "NOOP" => format!("{}{} OK NOOP completed\r\n", self.changes(), tag),
+            "IDLE" =>
+            {
+                self.idling = Some(tag.to_string());
+                "+ idling\r\n".to_string()
+            }
+            "LOGOUT" => format!("* BYE logging out\r\n{} OK LOGOUT completed\r\n", tag),
+            _ => format!("{} BAD unknown command\r\n", tag),
+        }
+    }
+
+    fn finish_xoauth2(&mut self, tag: &str, line: &str) -> String
+    {
+        let decoded = base64::engine::general_purpose::STANDARD.decode(line).unwrap_or_default();
+        let st = self.state.lock().unwrap();
+        let expected = format!("user={}\x01auth=Bearer {}\x01\x01", st.user, st.token);
+        if decoded == expected.as_bytes()
+        {
+            format!("{} OK AUTHENTICATE completed\r\n", tag)
+        }
+        else
+        {
+            format!("{} NO [AUTHENTICATIONFAILED] invalid token\r\n", tag)
+        }
+    }
+
+    /// Untagged EXISTS / FETCH lines for what changed in the selected
+    /// mailbox since the client last heard about it.
+    fn changes(&mut self) -> String
+    {
+        let seen = self.seen;
+        let result = self.with_selected(|msgs|
+        {
+            let mut out = String::new();
+            for (i, m) in msgs.iter().enumerate()
+            {
+                if m.modseq > seen.1
+                {
+                    out.push_str(&format!("* {} FETCH (UID {} FLAGS ({}))\r\n", i + 1, m.uid, m.flags.join(" ")));
+                }
+            }
+            if msgs.len() != seen.0
+            {
+                out.push_str(&format!("* {} EXISTS\r\n", msgs.len()));
+            }
+            (out, (msgs.len(), msgs.iter().map(|m| m.modseq).max().unwrap_or(0)))
+        });
+        match result
+        {
+            Some((out, now)) =>
+            {
+                self.seen = now;
+                out
+            }
+            None => String::new(),
+        }
+    }
+
+    fn with_selected<R>(&self, f: impl FnOnce(&mut Vec<MockMessage>) -> R) -> Option<R>
+    {
+        let name = self.selected.as_ref()?;
+        let mut st = self.state.lock().unwrap();
+        st.mailboxes.get_mut(name).map(f)
+    }
+
+    fn fetch(&mut self, tag: &str, args: &str, by_uid: bool) -> String
+    {
+        let (set, query) = args.split_once(' ').unwrap_or((args, ""));
+        // CONDSTORE: `(attrs) (CHANGEDSINCE n)`
+        let (query, changed_since) = match query.rsplit_once(" (CHANGEDSINCE ")
+        {
+            Some((q, n)) => (q, n.trim_end_matches(')').parse::<u64>().ok()),
+            None => (query, None),
+        };
+        let attrs = split_attrs(query);
+        let marks_seen = attrs.iter().any(|a|
+        {
+            let a = a.to_ascii_uppercase();
+            a == "RFC822" || a.starts_with("BODY[")
+        });
+        let set = set.to_string();
+        let body = self.with_selected(|msgs|
+        {
+            let max = if by_uid { msgs.iter().map(|m| m.uid).max().unwrap_or(0) } else { msgs.len() as u32 };
+            let mut out = String::new();
+            for (i, m) in msgs.iter_mut().enumerate()
+            {
+                let seq = i as u32 + 1;
+                let key = if by_uid { m.uid } else { seq };
+                if !in_set(&set, key, max) || changed_since.is_some_and(|n| m.modseq <= n)
+                {
+                    continue;
+                }
+                if marks_seen && !m.flags.iter().any(|f| f == "\\Seen")
+                {
+                    m.flags.push("\\Seen".into());
+                }
+                let mut line = fetch_line(seq, m, &attrs, by_uid);
+                if changed_since.is_some()
+                {
+                    line = line.replacen(" FETCH (", &format!(" FETCH (MODSEQ ({}) ", m.modseq), 1);
+                }
+                out.push_str(&line);
+            }
+            out
+        });
+        match body
+        {
+            Some(b) => format!("{}{} OK FETCH completed\r\n", b, tag),
+            None => format!("{} NO no mailbox selected\r\n", tag),
+        }
+    }
+
+    fn store(&mut self, tag: &str, args: &str) -> String
+    {
+        let mut parts = args.splitn(3, ' ');
+        let set = parts.next().unwrap_or("").to_string();
+        let op = parts.next().unwrap_or("").to_ascii_uppercase();
+        let flags: Vec<String> = parts
+            .next()
+            .unwrap_or("")
+            .trim_matches(|c| c == '(' || c == ')')
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+        let body = self.with_selected(|msgs|
+        {
+            let max = msgs.iter().map(|m| m.uid).max().unwrap_or(0);
+            let mut out = String::new();
+            for (i, m) in msgs.iter_mut().enumerate()
+            {
+                if !in_set(&set, m.uid, max)
+                {
+                    continue;
+                }
+                if op.starts_with("+FLAGS")
+                {
+                    for f in &flags
+                    {
+                        if !m.flags.iter().any(|x| x.eq_ignore_ascii_case(f))
+                        {
+                            m.flags.push(f.clone());
+                        }
+                    }
+                }
+                else if op.starts_with("-FLAGS")
+                {
+                    m.flags.retain(|x| !flags.iter().any(|f| f.eq_ignore_ascii_case(x)));
+                }
+                else
+                {
+                    m.flags = flags.clone();
+                }
+                if !op.ends_with(".SILENT")
+                {
+                    out.push_str(&format!("* {} FETCH (UID {} FLAGS ({}))\r\n", i + 1, m.uid, m.flags.join(" ")));
+                }
+            }
+            out
+        });
+        match body
+        {
+            Some(b) => format!("{}{} OK STORE completed\r\n", b, tag),
+            None => format!("{} NO no mailbox selected\r\n", tag),
+        }
+    }
+
+    /// `UID SEARCH` with the criteria the client sends, all of which must
+    /// match: a sequence set, UID <set>, FROM / TO / SUBJECT / BODY / TEXT substrings,
+    /// SINCE / BEFORE by INTERNALDATE, SEEN, UNSEEN, FLAGGED and LARGER.
+    /// `X-GM-RAW` is honoured roughly: apart from `is:unread` and
+    /// `is:starred`, every word (or the value of a `key:value` word) has to
+    /// occur somewhere in the message.
+    fn search(&mut self, tag: &str, args: &str) -> String
+    {
+        let criteria = search_words(args);
+        let uids = self.with_selected(|msgs|
+        {
+            let max = msgs.iter().map(|m| m.uid).max().unwrap_or(0);
+            let count = msgs.len() as u32;
+            msgs.iter()
+                .zip(1..)
+                .filter(|(m, seq)| m.matches(&criteria, *seq, count, max))
+                .map(|(m, _)| m.uid.to_string())
+                .collect::<Vec<_>>()
+        });
+        match uids
+        {
+            Some(uids) if uids.is_empty() => format!("* SEARCH\r\n{} OK SEARCH completed\r\n", tag),
+            Some(uids) => format!("* SEARCH {}\r\n{} OK SEARCH completed\r\n", uids.join(" "), tag),
+            None => format!("{} NO no mailbox selected\r\n", tag),
+        }
+    }
+
+    /// `UID THREAD <algorithm> <charset> <criteria>`: the matching messages
+    /// grouped by their `thread`, the first of each group as the parent of
+    /// the others, e.g. `(1 (4)(7))`.
+    fn thread(&mut self, tag: &str, args: &str) -> String
+    {
+        let criteria: Vec<String> = search_words(args).into_iter().skip(2).collect();
+        let groups = self.with_selected(|msgs|
+        {
+            let max = msgs.iter().map(|m| m.uid).max().unwrap_or(0);
+            let count = msgs.len() as u32;
+            let mut groups: BTreeMap<u64, Vec<u32>> = BTreeMap::new();
+            for (m, _) in msgs.iter().zip(1..).filter(|(m, seq)| m.matches(&criteria, *seq, count, max))
+            {
+                groups.entry(m.thread).or_default().push(m.uid);
+            }
+            groups
+                .into_values()
+                .map(|uids| match uids.split_first()
+                {
+                    Some((first, [])) => format!("({})", first),
+                    Some((first, rest)) =>
+                    {
+                        let children: String = rest.iter().map(|uid| format!("({})", uid)).collect();
+                        format!("({} {})", first, children)
+                    }
+                    None => String::new(),
+                })
+                .collect::<String>()
+        });
+        match groups
+        {
+            Some(groups) => format!("* THREAD {}\r\n{} OK THREAD completed\r\n", groups, tag),
+            None => format!("{} NO no mailbox selected\r\n", tag),
+        }
+    }
+
+    fn has_capability(&self, name: &str) -> bool
+    {
+        self.state.lock().unwrap().capabilities.iter().any(|c| c.eq_ignore_ascii_case(name))
+    }
+
+    /// UID COPY, or UID MOVE with `remove`; both answer with COPYUID.
+    fn copy(&mut self, tag: &str, args: &str, remove: bool) -> String
+    {
+        let (set, target) = args.split_once(' ').unwrap_or((args, ""));
+        let target = unquote(target);
+        let Some(source) = self.selected.clone()
+        else
+        {
+            return format!("{} NO no mailbox selected\r\n", tag);
+        };
+        let mut st = self.state.lock().unwrap();
+        if !st.mailboxes.contains_key(&target)
+        {
+            return format!("{} NO [TRYCREATE] no such mailbox\r\n", tag);
+        }
+        let msgs = &st.mailboxes[&source];
+        let max = msgs.iter().map(|m| m.uid).max().unwrap_or(0);
+        let picked: Vec<MockMessage> = msgs.iter().filter(|m| in_set(set, m.uid, max)).cloned().collect();
+
+        let dest = st.mailboxes.get_mut(&target).unwrap();
+        let first = dest.iter().map(|m| m.uid).max().unwrap_or(0) + 1;
+        let (mut old, mut new) = (Vec::new(), Vec::new());
+        for (uid, m) in (first..).zip(&picked)
+        {
+            old.push(m.uid.to_string());
+            new.push(uid.to_string());
+            dest.push(MockMessage { uid, ..m.clone() });
+        }
+        let copyuid = format!("[COPYUID {} {} {}]", st.uid_validity, old.join(","), new.join(","));
+        if !remove
+        {
+            return format!("{} OK {} COPY completed\r\n", tag, copyuid);
+        }
+
+        let mut out = format!("* OK {} moved\r\n", copyuid);
+        let mut seq = 1;
+        st.mailboxes.get_mut(&source).unwrap().retain(|m|
+        {
+            if picked.iter().any(|p| p.uid == m.uid)
+            {
+                out.push_str(&format!("* {} EXPUNGE\r\n", seq));
+                false
+            }
+            else
+            {
+                seq += 1;
+                true
+            }
+        });
+        format!("{}{} OK MOVE completed\r\n", out, tag)
+    }
+
+    /// EXPUNGE, or UID EXPUNGE limited to `uid_set`.
+    fn expunge(&mut self, tag: &str, uid_set: Option<&str>) -> String
+    {
+        let body = self.with_selected(|msgs|
+        {
+            let max = msgs.iter().map(|m| m.uid).max().unwrap_or(0);
+            let mut out = String::new();
+            let mut seq = 1;
+            msgs.retain(|m|
+            {
+                if m.flags.iter().any(|f| f.eq_ignore_ascii_case("\\Deleted"))
+                    && uid_set.is_none_or(|set| in_set(set, m.uid, max))
+                {
+                    out.push_str(&format!("* {} EXPUNGE\r\n", seq));
+                    false
+                }
+                else
+                {
+                    seq += 1;
+                    true
+                }
+            });
+            out
+        });
+        match body
+        {
+            Some(b) => format!("{}{} OK EXPUNGE completed\r\n", b, tag),
+            None => format!("{} NO no mailbox selected\r\n", tag),
+        }
+    }
+}
+
+fn unquote(s: &str) -> String
+{
+    s.trim().trim_matches('"').to_string()
+}
+
+/// Search criteria split into words, with quoted strings unquoted.
+fn search_words(args: &str) -> Vec<String>
+{
+    let mut words = Vec::new();
+    let mut chars = args.chars();
+    let mut word = String::new();
+    let mut quoted = false;
+    while let Some(c) = chars.next()
+    {
+        match c
+        {
+            '\\' if quoted => word.extend(chars.next()),
+            '"' => quoted = !quoted,
+            ' ' if !quoted =>
+            {
+                if !word.is_empty()
+                {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty()
+    {
+        words.push(word);
+    }
+    words
+}
+
+/// For a command line ending in a literal announcement, `{n}` or `{n+}`:
+/// the line before it, `n`, and whether the client waits for a `+`.
+fn literal_at_end(line: &str) -> Option<(String, usize, bool)>
+{
+    let open = line.strip_suffix('}')?.rfind('{')?;
+    let count = &line[open + 1..line.len() - 1];
+    let (count, synchronizing) = match count.strip_suffix('+')
+    {
+        Some(count) => (count, false),
+        None => (count, true),
+    };
+    Some((line[..open].to_string(), count.parse().ok()?, synchronizing))
+}
+
+/// True if `n` is part of an IMAP sequence set like `1:5,9,12:*`.
+pub fn in_set(set: &str, n: u32, max: u32) -> bool
+{
+    let value = |s: &str| if s == "*" { max } else { s.parse().unwrap_or(0) };
+    set.split(',').any(|part| match part.split_once(':')
+    {
+        Some((a, b)) =>
+        {
+            let (a, b) = (value(a), value(b));
+            let (lo, hi) = if a <= b { (a, b) } else { (b, a) };
+            n >= lo && n <= hi
+        }
+        None => value(part) == n,
+    })
+}
+
+/// Splits a FETCH query like `(UID BODY.PEEK[HEADER.FIELDS (FROM DATE)])`
+/// into its top-level attributes.
+fn split_attrs(query: &str) -> Vec<String>
+{
+    let query = query.trim();
+    let inner = if query.starts_with('(') && query.ends_with(')') { &query[1..query.len() - 1] } else { query };
+    let mut out = Vec::new();
+    let mut depth = 0;
+    let mut cur = String::new();
+    for c in inner.chars()
+    {
+        match c
+        {
+            '[' | '(' => depth += 1,
+            ']' | ')' => depth -= 1,
+            _ => {}
+        }
+        if c == ' ' && depth == 0
+        {
+            if !cur.is_empty()
+            {
+                out.push(std::mem::take(&mut cur));
+            }
+        }
+        else
+        {
+            cur.push(c);
+        }
+    }
+    if !cur.is_empty()
+    {
+        out.push(cur);
+    }
+    out
+}
+
+fn literal(name: &str, data: &str) -> String
+{
+    format!("{} {{{}}}\r\n{}", name, data.len(), data)
+}
+
+fn fetch_line(seq: u32, m: &MockMessage, attrs: &[String], by_uid: bool) -> String
+{
+    let mut items = Vec::new();
+    if by_uid && !attrs.iter().any(|a| a.eq_ignore_ascii_case("UID"))
+    {
+        items.push(format!("UID {}", m.uid));
+    }
+    for attr in attrs
+    {
+        let upper = attr.to_ascii_uppercase();
+        let section = upper.replace("BODY.PEEK[", "BODY[");
+        match upper.as_str()
+        {
+            "UID" => items.push(format!("UID {}", m.uid)),
+            "INTERNALDATE" => items.push(format!("INTERNALDATE \"{}\"", m.internal_date)),
+            "FLAGS" => items.push(format!("FLAGS ({})", m.flags.join(" "))),
+            "X-GM-THRID" => items.push(format!("X-GM-THRID {}", m.thread)),
+            "RFC822.SIZE" => items.push(format!("RFC822.SIZE {}", m.raw.len())),
+            "RFC822" => items.push(literal("RFC822", &m.raw)),
+            "RFC822.HEADER" => items.push(literal("RFC822.HEADER", m.header_block())),
+            _ if section == "BODY[]" => items.push(literal("BODY[]", &m.raw)),
+            _ if section.starts_with("BODY[]<0.") =>
+            {
+                let limit = section["BODY[]<0.".len()..].trim_end_matches('>').parse().unwrap_or(0);
+                let end = (0..=limit.min(m.raw.len())).rev().find(|&i| m.raw.is_char_boundary(i)).unwrap_or(0);
+                items.push(literal("BODY[]<0>", &m.raw[..end]));
+            }
+            _ if section == "BODY[HEADER]" => items.push(literal("BODY[HEADER]", m.header_block())),
+            _ if section.starts_with("BODY[HEADER.FIELDS") =>
+            {
+                let open = section.find('(').unwrap_or(0);
+                let close = section.rfind(')').unwrap_or(section.len());
+                let fields: Vec<String> = section[open + 1..close].split_whitespace().map(String::from).collect();
+                items.push(literal(&section, &m.header_fields(&fields)));
+            }
+            _ => {}
+        }
+    }
+    format!("* {} FETCH ({})\r\n", seq, items.join(" "))
+}
@@ -0,0 +1,149 @@
+use bps_mail::compose::{
+    complete_path, mailboxes, mime_type, size_warning, split_addresses, Attachment, Draft, LARGE_ATTACHMENTS,
+};
+use bps_mail::imap::Headers;
+use bps_mail::smtp::{with_attachments, SmtpError};
+use lettre::Message;
+use std::fs;
+use std::path::Path;
+
+fn builder() -> lettre::message::MessageBuilder
+{
+    Message::builder()
+        .from("me@example.com".parse().unwrap())
+        .to("you@example.com".parse().unwrap())
+        .subject("files")
+}
+
+#[test]
+fn paths_complete_as_far_as_the_matches_agree()
+{
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["report-2024.pdf", "report-2025.pdf", ".hidden"]
+    {
+        fs::write(dir.path().join(name), "x").unwrap();
+    }
+    fs::create_dir(dir.path().join("photos")).unwrap();
+    let base = format!("{}/", dir.path().display());
+
+    let both = complete_path(&format!("{}re", base));
+    assert_eq!(both.line, format!("{}report-202", base));
+    assert_eq!(both.candidates, ["report-2024.pdf", "report-2025.pdf"]);
+
+    let one = complete_path(&format!("{}ph", base));
+    assert_eq!(one.line, format!("{}photos/", base), "a directory gets its slash");
+
+    assert_eq!(complete_path(&base).candidates, ["photos/", "report-2024.pdf", "report-2025.pdf"], "no hidden files");
+    assert_eq!(complete_path(&format!("{}.h", base)).line, format!("{}.hidden", base));
+
+    let none = complete_path(&format!("{}zzz", base));
+    assert_eq!(none.line, format!("{}zzz", base));
+    assert!(none.candidates.is_empty());
+}
+
+#[test]
+fn mime_types_come_from_the_extension_then_the_content()
+{
+    assert_eq!(mime_type(Path::new("Scan.PDF"), b""), "application/pdf");
+    assert_eq!(mime_type(Path::new("notes.md"), b"# hi"), "text/markdown");
+    assert_eq!(mime_type(Path::new("scan"), b"%PDF-1.7\n"), "application/pdf");
+    assert_eq!(mime_type(Path::new("image.unknown"), b"\x89PNG\r\n\x1a\n\0\0"), "image/png");
+    assert_eq!(mime_type(Path::new("README"), "grüße".as_bytes()), "text/plain");
+    assert_eq!(mime_type(Path::new("blob"), &[0, 159, 146, 150]), "application/octet-stream");
+}
+
+#[test]
+fn attachments_are_sized_and_large_ones_warned_about()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("a.txt");
+    fs::write(&path, "twelve bytes").unwrap();
+
+    let attachment = Attachment::new(&format!(" {} ", path.display())).unwrap();
+    assert_eq!((attachment.name().as_str(), attachment.size), ("a.txt", 12));
+    assert!(Attachment::new(&dir.path().display().to_string()).is_err(), "not a directory");
+    assert!(Attachment::new(&format!("{}/missing", dir.path().display())).is_err());
+
+    assert_eq!(size_warning(LARGE_ATTACHMENTS), None);
+    let warning = size_warning(LARGE_ATTACHMENTS + 1).unwrap();
+    assert!(warning.starts_with("10.0 MB of attachments"), "{}", warning);
+}
+
+#[test]
+fn attachments_go_out_as_multipart_mixed()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let notes = dir.path().join("notes.txt");
+    let scan = dir.path().join("scan");
+    fs::write(&notes, "remember the milk").unwrap();
+    fs::write(&scan, b"%PDF-1.4\n").unwrap();
+
+    let plain = with_attachments(builder(), "hello".into(), &[]).unwrap();
+    let plain = String::from_utf8(plain.formatted()).unwrap();
+    assert!(!plain.contains("multipart"), "{}", plain);
+
+    let mixed = with_attachments(builder(), "hello".into(), &[notes, scan]).unwrap();
+    let mixed = String::from_utf8(mixed.formatted()).unwrap();
+    assert!(mixed.contains("Content-Type: multipart/mixed"), "{}", mixed);
+    assert!(mixed.contains("hello"));
+    assert!(mixed.contains("Content-Disposition: attachment; filename=\"notes.txt\""), "{}", mixed);
+    assert!(mixed.contains("Content-Type: application/pdf"), "sniffed from the content: {}", mixed);
+
+    let missing = dir.path().join("gone.pdf");
+    match with_attachments(builder(), "hello".into(), std::slice::from_ref(&missing))
+    {
+        Err(SmtpError::Attachment { path, .. }) => assert_eq!(path, missing),
+        other => panic!("unexpected result {:?}", other.map(|m| m.formatted().len())),
+    }
+}
+
+#[test]
+fn recipient_fields_split_at_commas_outside_names()
+{
+    let field = "\"Doe, John\" <john@example.com>, bob@example.com; (team, all) carol@example.com ,, ";
+    assert_eq!(
+        split_addresses(field),
+        ["\"Doe, John\" <john@example.com>", "bob@example.com", "(team, all) carol@example.com"]
+    );
+    assert!(split_addresses(" , ").is_empty());
+
+    let parsed: Vec<String> = mailboxes("Müller Hans <hans@example.com>, \"Doe, John\" <john@example.com>")
+        .unwrap()
+        .iter()
+        .map(|m| m.to_string())
+        .collect();
+    assert_eq!(parsed, ["Müller Hans <hans@example.com>", "\"Doe, John\" <john@example.com>"]);
+    match mailboxes("bob@example.com, bob at example dot com")
+    {
+        Err(SmtpError::Address { address, .. }) => assert_eq!(address, "bob at example dot com"),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn drafts_go_to_every_recipient_but_bcc_stays_hidden()
+{
+    let draft = Draft
+    {
+        to: "Alice <alice@example.com>, bob@example.com".into(),
+        cc: "\"Doe, John\" <john@example.com>".into(),
+        bcc: "boss@example.com".into(),
+        subject: "plans".into(),
+        body: "hello".into(),
+        ..Draft::default()
+    };
+    let email = draft.message("me@example.com".parse().unwrap()).unwrap();
+    let envelope: Vec<String> = email.envelope().to().iter().map(|a| a.to_string()).collect();
+    assert_eq!(envelope, ["alice@example.com", "bob@example.com", "john@example.com", "boss@example.com"]);
+    let formatted = email.formatted();
+    let headers = Headers::parse(&formatted).unwrap();
+    let written = |name: &str| headers.addresses(name).iter().map(|a| a.to_string()).collect::<Vec<_>>();
+    assert_eq!(written("To"), ["Alice <alice@example.com>", "bob@example.com"]);
+    assert_eq!(written("Cc"), ["\"Doe, John\" <john@example.com>"]);
+    assert!(!String::from_utf8_lossy(&formatted).contains("boss"), "no Bcc header");
+
+    let only_bcc = Draft { bcc: "boss@example.com".into(), ..Draft::default() };
+    assert!(only_bcc.message("me@example.com".parse().unwrap()).is_ok());
+    let nobody = Draft { subject: "to whom?".into(), ..Draft::default() };
+    assert!(matches!(nobody.message("me@example.com".parse().unwrap()), Err(SmtpError::NoRecipients)));
+}
@@ -0,0 +1,252 @@
+use bps_mail::config::{download_dir, ConfigError, ConfigFile, CredentialStore, Secret, Security};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const TWO_ACCOUNTS: &str = r#"
+default_account = "work"
+
+[accounts.work]
+email = "me@work.example"
+display_name = "Me Myself"
+signature = "Cheers"
+password = "work-pass"
+
+[accounts.work.imap]
+host = "imap.work.example"
+security = "starttls"
+
+[accounts.work.smtp]
+host = "smtp.work.example"
+port = 2525
+user = "relay-user"
+password = "relay-pass"
+
+[accounts.work.folders]
+trash = "Deleted Items"
+
+[accounts.home]
+email = "me@home.example"
+password = "home-pass"
+
+[accounts.home.imap]
+host = "localhost"
+port = 1143
+security = "plain"
+
+[accounts.home.smtp]
+host = "localhost"
+security = "plain"
+"#;
+
+fn parse(text: &str) -> Result<ConfigFile, ConfigError>
+{
+    ConfigFile::parse(text, Path::new("config.toml"))
+}
+
+#[test]
+fn picks_default_account()
+{
+    let cfg = parse(TWO_ACCOUNTS).unwrap().account(None).unwrap();
+
+    assert_eq!(cfg.name, "work");
+    assert_eq!(cfg.display_name.as_deref(), Some("Me Myself"));
+    assert_eq!(cfg.imap.host, "imap.work.example");
+    assert_eq!(cfg.imap.port, 143);
+    assert_eq!(cfg.imap.security, Security::StartTls);
+    assert_eq!(cfg.imap_user, "me@work.example");
+    assert_eq!(cfg.imap_pass.expose(), "work-pass");
+    assert_eq!(cfg.smtp.port, 2525);
+    assert_eq!(cfg.smtp_user, "relay-user");
+    assert_eq!(cfg.smtp_pass.expose(), "relay-pass");
+    assert_eq!(cfg.folders.inbox, "INBOX");
+    assert_eq!(cfg.folders.trash.as_deref(), Some("Deleted Items"));
+}
+
+#[test]
+fn picks_named_account()
+{
+    let cfg = parse(TWO_ACCOUNTS).unwrap().account(Some("home")).unwrap();
+
+    assert_eq!(cfg.email, "me@home.example");
+    assert_eq!(cfg.imap.port, 1143);
+    assert_eq!(cfg.smtp.port, 25);
+    assert_eq!(cfg.smtp.security, Security::Plain);
+}
+
+#[test]
+fn unknown_account_is_an_error()
+{
+    let err = parse(TWO_ACCOUNTS).unwrap().account(Some("nope")).unwrap_err();
+    assert!(matches!(err, ConfigError::UnknownAccount(ref n) if n == "nope"));
+}
+
+#[test]
+fn missing_password_is_reported_not_panicked()
+{
+    let err = parse(NO_PASSWORD).unwrap().account(None).unwrap_err();
+    assert_eq!(err.to_string(), "account 'a': imap: no password configured");
+}
+
+#[test]
+fn bad_security_mode_is_a_parse_error()
+{
+    let text = TWO_ACCOUNTS.replace("\"starttls\"", "\"ssh\"");
+    let err = parse(&text).unwrap_err();
+    assert!(matches!(err, ConfigError::Parse { .. }));
+    assert!(err.to_string().contains("unknown security mode 'ssh'"));
+}
+
+#[test]
+fn timeouts_default_and_can_be_overridden()
+{
+    let text = TWO_ACCOUNTS.replace("security = \"starttls\"", "security = \"starttls\"\nread_timeout = 300");
+    let cfg = parse(&text).unwrap().account(None).unwrap();
+    assert_eq!(cfg.imap.timeouts.read, Duration::from_secs(300));
+    assert_eq!(cfg.imap.timeouts.connect, Duration::from_secs(15));
+    assert_eq!(cfg.smtp.timeouts.read, Duration::from_secs(60));
+
+    let text = TWO_ACCOUNTS.replace("security = \"starttls\"", "security = \"starttls\"\nconnect_timeout = 0");
+    let err = parse(&text).unwrap().account(None).unwrap_err();
+    assert_eq!(err.to_string(), "account 'work': imap.connect_timeout: must be at least 1 second");
+}
+
+#[test]
+fn download_dir_can_be_set_per_account()
+{
+    let text = TWO_ACCOUNTS.replace("password = \"work-pass\"", "password = \"work-pass\"\ndownload_dir = \"/srv/mail\"");
+    let file = parse(&text).unwrap();
+    assert_eq!(file.account(None).unwrap().download_dir, PathBuf::from("/srv/mail"));
+    assert_eq!(file.account(Some("home")).unwrap().download_dir, download_dir());
+
+    let text = TWO_ACCOUNTS.replace("password = \"work-pass\"", "password = \"work-pass\"\ndownload_dir = \" \"");
+    let err = parse(&text).unwrap().account(None).unwrap_err();
+    assert_eq!(err.to_string(), "account 'work': download_dir: must not be empty");
+}
+
+#[test]
+fn aliases_must_be_addresses()
+{
+    let text = TWO_ACCOUNTS.replace("password = \"work-pass\"", "password = \"work-pass\"\naliases = [\"me@example.org\"]");
+    let file = parse(&text).unwrap();
+    assert_eq!(file.account(None).unwrap().aliases, ["me@example.org"]);
+    assert!(file.account(Some("home")).unwrap().aliases.is_empty());
+
+    let text = TWO_ACCOUNTS.replace("password = \"work-pass\"", "password = \"work-pass\"\naliases = [\"me\"]");
+    let err = parse(&text).unwrap().account(None).unwrap_err();
+    assert_eq!(err.to_string(), "account 'work': aliases: 'me' is not an email address");
+}
+
+#[test]
+fn empty_file_has_no_accounts()
+{
+    assert!(matches!(parse(""), Err(ConfigError::NoAccounts(_))));
+}
+
+const NO_PASSWORD: &str = r#"
+[accounts.a]
+email = "a@example.com"
+imap = { host = "imap.example.com" }
+smtp = { host = "smtp.example.com" }
+"#;
+
+#[cfg(unix)]
+#[test]
+fn pass_cmd_reads_first_line_of_output()
+{
+    let text = r#"
+[accounts.a]
+email = "a@example.com"
+pass_cmd = "printf 'from-cmd\\nsecond line\\n'"
+imap = { host = "imap.example.com" }
+smtp = { host = "smtp.example.com", pass_cmd = "echo smtp-only" }
+"#;
+    let cfg = parse(text).unwrap().account(None).unwrap();
+    assert_eq!(cfg.imap_pass.expose(), "from-cmd");
+    assert_eq!(cfg.smtp_pass.expose(), "smtp-only");
+}
+
+#[cfg(unix)]
+#[test]
+fn account_pass_cmd_runs_once_for_both_servers()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let runs = dir.path().join("runs");
+    let text = format!(
+        r#"
+[accounts.a]
+email = "a@example.com"
+pass_cmd = "echo run >> '{}'; echo shared"
+imap = {{ host = "imap.example.com" }}
+smtp = {{ host = "smtp.example.com" }}
+"#,
+        runs.display()
+    );
+    let cfg = parse(&text).unwrap().account(None).unwrap();
+    assert_eq!(cfg.imap_pass.expose(), "shared");
+    assert_eq!(cfg.smtp_pass.expose(), "shared");
+    assert_eq!(std::fs::read_to_string(&runs).unwrap(), "run\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn failing_pass_cmd_is_an_error()
+{
+    let text = NO_PASSWORD.replace("email = \"a@example.com\"", "email = \"a@example.com\"\npass_cmd = \"exit 3\"");
+    let err = parse(&text).unwrap().account(None).unwrap_err();
+    assert!(err.to_string().starts_with("account 'a': pass_cmd: `exit 3` failed"), "{}", err);
+}
+
+#[test]
+fn passwords_fall_back_to_the_encrypted_store()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("credentials.enc");
+    let passphrase = Secret::from("correct horse");
+
+    let mut store = CredentialStore::open(&path, &passphrase).unwrap();
+    store.set("a", Secret::from("stored-pass"));
+    store.set("a.smtp", Secret::from("stored-smtp-pass"));
+    store.save().unwrap();
+
+    let on_disk = std::fs::read_to_string(&path).unwrap();
+    assert!(!on_disk.contains("stored-pass"));
+
+    let file = parse(NO_PASSWORD).unwrap();
+    let cfg = file
+        .account_with_store(None, || CredentialStore::open(&path, &passphrase).map(Some))
+        .unwrap();
+    assert_eq!(cfg.imap_pass.expose(), "stored-pass");
+    assert_eq!(cfg.smtp_pass.expose(), "stored-smtp-pass");
+}
+
+#[test]
+fn wrong_master_passphrase_is_rejected()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("credentials.enc");
+    let mut store = CredentialStore::open(&path, &Secret::from("right")).unwrap();
+    store.set("a", Secret::from("pw"));
+    store.save().unwrap();
+
+    let err = CredentialStore::open(&path, &Secret::from("wrong")).err().unwrap();
+    assert!(matches!(err, ConfigError::Secret(_)));
+}
+
+#[test]
+fn store_is_not_unlocked_when_a_password_is_configured()
+{
+    let cfg = parse(TWO_ACCOUNTS)
+        .unwrap()
+        .account_with_store(Some("home"), || panic!("should not prompt for the master passphrase"))
+        .unwrap();
+    assert_eq!(cfg.imap_pass.expose(), "home-pass");
+}
+
+#[test]
+fn secrets_are_redacted_in_debug_output()
+{
+    let cfg = parse(TWO_ACCOUNTS).unwrap().account(None).unwrap();
+    let dump = format!("{:?}", cfg);
+    assert!(!dump.contains("work-pass"));
+    assert!(dump.contains("Secret(***)"));
+}
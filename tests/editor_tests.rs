@@ -0,0 +1,66 @@
+use bps_mail::compose::{draft_text, edit_draft, read_draft, Draft, EditorError};
+use std::fs;
+
+fn draft() -> Draft
+{
+    Draft
+    {
+        to: "Alice <alice@example.com>".into(),
+        cc: "bob@example.com".into(),
+        subject: "plans".into(),
+        body: "Hi Alice,\n\nsee you at noon.".into(),
+        in_reply_to: Some("m1@example.com".into()),
+        ..Draft::default()
+    }
+}
+
+#[test]
+fn drafts_are_read_back_from_the_editor_file()
+{
+    let original = draft();
+    let text = draft_text(&original);
+    assert_eq!(text, "To: Alice <alice@example.com>\nCc: bob@example.com\nBcc: \nSubject: plans\n\nHi Alice,\n\nsee you at noon.\n");
+    let mut edited = original.clone();
+    read_draft(&text, &mut edited).unwrap();
+    assert_eq!(edited, original, "unchanged text, unchanged draft");
+
+    let text = "to: alice@example.com,\r\n  carol@example.com\r\nSUBJECT: new plans\r\n\r\nNoon is off.\r\n\r\n\r\n";
+    read_draft(text, &mut edited).unwrap();
+    assert_eq!(edited.to, "alice@example.com, carol@example.com", "continued on the next line");
+    assert_eq!((edited.cc.as_str(), edited.subject.as_str()), ("", "new plans"), "a missing header is empty");
+    assert_eq!(edited.body, "Noon is off.");
+    assert_eq!(edited.in_reply_to.as_deref(), Some("m1@example.com"), "what has no header is kept");
+
+    read_draft("\nonly a body\n", &mut edited).unwrap();
+    assert_eq!((edited.to.as_str(), edited.body.as_str()), ("", "only a body"));
+
+    let mut kept = draft();
+    let err = read_draft("To: bob@example.com\nFrom: me@example.com\n\nbody\n", &mut kept).unwrap_err();
+    assert!(matches!(&err, EditorError::Header(line) if line == "From: me@example.com"), "{}", err);
+    assert_eq!(kept, draft(), "left alone on error");
+}
+
+#[cfg(unix)]
+#[test]
+fn the_editor_runs_on_a_temporary_file()
+{
+    let dir = tempfile::tempdir().unwrap();
+    let seen = dir.path().join("seen");
+    // An "editor" that notes which file it got, keeps a copy and rewrites it
+    let editor = format!(
+        "echo \"$1\" > {seen} && cp \"$1\" {seen}.eml && printf 'To: carol@example.com\\nSubject: plans\\n\\nNew text\\n' >",
+        seen = seen.display()
+    );
+    let mut edited = draft();
+    edit_draft(&mut edited, &editor).unwrap();
+    assert_eq!((edited.to.as_str(), edited.body.as_str()), ("carol@example.com", "New text"));
+    assert_eq!(fs::read_to_string(seen.with_extension("eml")).unwrap(), draft_text(&draft()));
+    let path = fs::read_to_string(&seen).unwrap();
+    assert!(path.trim_end().ends_with(".eml"), "{}", path);
+    assert!(!std::path::Path::new(path.trim_end()).exists(), "removed afterwards");
+
+    // Quitting with an error (`:cq`) changes nothing
+    let err = edit_draft(&mut edited, "false").unwrap_err();
+    assert!(matches!(err, EditorError::Failed(_)), "{}", err);
+    assert_eq!(edited.to, "carol@example.com");
+}
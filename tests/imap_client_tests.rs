@@ -0,0 +1,313 @@
+mod common;
+
+use bps_mail::config::{Folders, Security, ServerConfig, Timeouts};
+use bps_mail::imap::{apply_overrides, find_special_use, Flag, ImapClient, ImapError, SpecialUse};
+use common::{connect, local, MockMessage, MockServer, MockState};
+use std::time::{Duration, Instant};
+
+#[test]
+fn connects_to_plaintext_localhost_server()
+{
+    let server = MockServer::start(vec![
+        MockMessage::new(1, "Alice <alice@example.com>", "first", 1, "one"),
+        MockMessage::new(2, "bob@example.com", "second", 2, "two"),
+    ]);
+
+    let mut imap = connect(&server);
+    let inbox = imap.fetch_inbox(10).unwrap();
+
+    let froms: Vec<&str> = inbox.iter().map(|m| m.from.as_str()).collect();
+    assert_eq!(froms, ["bob@example.com", "alice@example.com"]);
+}
+
+#[test]
+fn rejects_bad_credentials()
+{
+    let server = MockServer::start(Vec::new());
+    let err = ImapClient::connect(&local(&server), "user@example.com", "wrong").err().unwrap();
+    assert!(matches!(err, ImapError::Auth(_)), "{:?}", err);
+    assert!(!err.is_disconnect());
+}
+
+#[test]
+fn refuses_plaintext_to_remote_host()
+{
+    let remote = ServerConfig
+    {
+        host: "imap.example.com".into(),
+        port: 143,
+        security: Security::Plain,
+        timeouts: Timeouts::default(),
+    };
+    let err = ImapClient::connect(&remote, "user", "pass").err().unwrap();
+    assert!(matches!(err, ImapError::Connect(_)));
+    assert!(err.to_string().contains("refusing unencrypted"));
+}
+
+fn with_folders() -> MockServer
+{
+    let mut state = MockState::new(vec![MockMessage::new(1, "alice@example.com", "hi", 1, "inbox")]);
+    state.mailboxes.insert("Sent Items".into(), vec![MockMessage::new(7, "me@example.com", "re: hi", 2, "sent")]);
+    state.mailboxes.insert("Trash".into(), Vec::new());
+    state.mailboxes.insert("Rubbish".into(), Vec::new());
+    state.mailboxes.insert("Projects".into(), Vec::new());
+    state.mailboxes.insert("Projects/2024".into(), Vec::new());
+    state.mailboxes.insert("[Gmail]".into(), Vec::new());
+    state.attributes.insert("Sent Items".into(), vec!["\\HasNoChildren".into(), "\\Sent".into()]);
+    state.attributes.insert("Rubbish".into(), vec!["\\Trash".into()]);
+    state.attributes.insert("[Gmail]".into(), vec!["\\Noselect".into()]);
+    MockServer::with_state(state)
+}
+
+#[test]
+fn lists_mailboxes_with_special_use()
+{
+    let server = with_folders();
+    let mut imap = connect(&server);
+    let folders = imap.list_mailboxes().unwrap();
+
+    let names: Vec<&str> = folders.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["INBOX", "Sent Items", "Rubbish", "[Gmail]", "Projects", "Projects/2024", "Trash"]);
+    assert_eq!(find_special_use(&folders, SpecialUse::Sent).unwrap().name, "Sent Items");
+    // The announced \Trash wins over a folder merely called "Trash".
+    assert_eq!(find_special_use(&folders, SpecialUse::Trash).unwrap().name, "Rubbish");
+    assert!(!folders.iter().find(|f| f.name == "[Gmail]").unwrap().selectable);
+    assert_eq!(folders[0].delimiter.as_deref(), Some("/"));
+}
+
+#[test]
+fn config_overrides_special_use()
+{
+    let server = with_folders();
+    let mut imap = connect(&server);
+    let mut folders = imap.list_mailboxes().unwrap();
+    let config = Folders { trash: Some("Trash".into()), ..Folders::default() };
+    apply_overrides(&mut folders, &config);
+
+    assert_eq!(find_special_use(&folders, SpecialUse::Trash).unwrap().name, "Trash");
+    assert_eq!(folders.iter().filter(|f| f.special_use == Some(SpecialUse::Trash)).count(), 1);
+}
+
+#[test]
+fn fetch_and_delete_use_the_selected_mailbox()
+{
+    let server = with_folders();
+    let mut imap = connect(&server);
+    assert_eq!(imap.mailbox(), "INBOX");
+
+    imap.select("Sent Items").unwrap();
+    let sent = imap.fetch_inbox(10).unwrap();
+    assert_eq!(sent.iter().map(|m| m.uid).collect::<Vec<_>>(), [7]);
+    assert!(imap.fetch_body(7).unwrap().contains("sent"));
+
+    imap.delete_message(7).unwrap();
+    let st = server.state.lock().unwrap();
+    assert!(st.mailboxes["Sent Items"].is_empty());
+    assert_eq!(st.mailboxes["INBOX"].len(), 1);
+}
+
+#[test]
+fn reading_a_message_leaves_it_unread()
+{
+    let server = MockServer::start((1..=2).map(MockMessage::numbered).collect());
+    let mut imap = connect(&server);
+
+    assert!(imap.fetch_body(2).unwrap().contains("body 2"));
+    assert!(imap.fetch_raw(1).unwrap().is_some());
+
+    let inbox = imap.fetch_inbox(10).unwrap();
+    assert!(inbox.iter().all(|m| !m.has(Flag::Seen)), "{:?}", inbox);
+}
+
+#[test]
+fn flags_are_set_and_cleared()
+{
+    let server = MockServer::start((1..=2).map(MockMessage::numbered).collect());
+    let mut imap = connect(&server);
+
+    assert_eq!(imap.set_flag(2, Flag::Flagged, true).unwrap(), ["\\Flagged"]);
+    assert_eq!(imap.set_flag(2, Flag::Seen, true).unwrap(), ["\\Flagged", "\\Seen"]);
+    assert_eq!(imap.set_flag(2, Flag::Flagged, false).unwrap(), ["\\Seen"]);
+
+    let inbox = imap.fetch_inbox(10).unwrap();
+    let second = inbox.iter().find(|m| m.uid == 2).unwrap();
+    assert!(second.has(Flag::Seen) && !second.has(Flag::Flagged));
+    assert!(!inbox.iter().find(|m| m.uid == 1).unwrap().has(Flag::Seen));
+}
+
+fn trash_server(capabilities: &[&str]) -> MockServer
+{
+    let mut state = MockState::new((1..=3).map(MockMessage::numbered).collect());
+    state.mailboxes.insert("Trash".into(), vec![MockMessage::numbered(9)]);
+    state.capabilities.extend(capabilities.iter().map(|c| c.to_string()));
+    MockServer::with_state(state)
+}
+
+fn uids(server: &MockServer, mailbox: &str) -> Vec<u32>
+{
+    server.state.lock().unwrap().mailboxes[mailbox].iter().map(|m| m.uid).collect()
+}
+
+#[test]
+fn move_uses_uid_move_when_the_server_has_it()
+{
+    let server = trash_server(&["MOVE", "UIDPLUS"]);
+    let mut imap = connect(&server);
+
+    assert_eq!(imap.move_message(2, "Trash").unwrap(), Some(10), "from COPYUID");
+
+    assert_eq!(uids(&server, "INBOX"), [1, 3]);
+    assert_eq!(uids(&server, "Trash"), [9, 10]);
+    assert_eq!(server.commands("UID MOVE"), 1);
+    assert_eq!(server.commands("UID COPY"), 0);
+}
+
+#[test]
+fn move_without_move_copies_and_expunges_only_that_message()
+{
+    let server = trash_server(&["UIDPLUS"]);
+    // Left \Deleted by another client; must survive our expunge
+    server.state.lock().unwrap().set_flags("INBOX", 1, &["\\Deleted"]);
+    let mut imap = connect(&server);
+
+    assert_eq!(imap.move_message(2, "Trash").unwrap(), Some(10), "from UIDNEXT");
+
+    assert_eq!(uids(&server, "INBOX"), [1, 3]);
+    assert_eq!(uids(&server, "Trash"), [9, 10]);
+    assert_eq!(server.commands("UID COPY"), 1);
+    assert_eq!(server.commands("UID EXPUNGE"), 1);
+    assert_eq!(server.commands("EXPUNGE"), 0);
+}
+
+#[test]
+fn moving_to_a_missing_folder_leaves_the_message()
+{
+    let server = trash_server(&["MOVE"]);
+    let mut imap = connect(&server);
+
+    assert!(matches!(imap.move_message(2, "Nope"), Err(ImapError::Rejected(_))));
+    assert_eq!(uids(&server, "INBOX"), [1, 2, 3]);
+}
+
+#[test]
+fn selecting_a_missing_mailbox_keeps_the_previous_one()
+{
+    let server = with_folders();
+    let mut imap = connect(&server);
+    assert!(matches!(imap.select("Nope"), Err(ImapError::Rejected(_))));
+    assert_eq!(imap.mailbox(), "INBOX");
+    assert_eq!(imap.fetch_inbox(10).unwrap().len(), 1);
+}
+
+#[test]
+fn summaries_carry_subject_sender_size_and_attachments()
+{
+    let with_attachment = "From: \"Carol C.\" <carol@example.com>\nSubject: report\nDate: Tue, 02 Jan 2024 10:00:00 +0000\n\
+                           Content-Type: multipart/mixed;\n boundary=b\n\n--b\nContent-Type: text/plain\n\nsee attached\n--b--\n";
+    let server = MockServer::start(vec![
+        MockMessage::new(1, "dave@example.com", "plain", 1, "one"),
+        MockMessage::with_raw(2, 2, with_attachment),
+    ]);
+    let mut imap = connect(&server);
+    let inbox = imap.fetch_inbox(10).unwrap();
+
+    assert_eq!(inbox[0].from, "carol@example.com");
+    assert_eq!(inbox[0].from_name.as_deref(), Some("Carol C."));
+    assert_eq!(inbox[0].sender(), "Carol C.");
+    assert_eq!(inbox[0].subject, "report");
+    assert_eq!(inbox[0].size as usize, server.state.lock().unwrap().mailboxes["INBOX"][1].raw.len());
+    assert!(inbox[0].has_attachments);
+
+    assert_eq!(inbox[1].sender(), "dave@example.com");
+    assert_eq!(inbox[1].subject, "plain");
+    assert!(!inbox[1].has_attachments);
+}
+
+#[test]
+fn message_details_come_from_one_fetch()
+{
+    let raw = "From: Alice <alice@example.com>\nTo: me@example.com, \"Bob B.\" <bob@example.com>\n\
+               Cc: carol@example.com\nReply-To: list@example.com\nSubject: =?UTF-8?Q?r=C3=A9sum=C3=A9?=\n\
+               Date: Tue, 02 Jan 2024 10:00:00 +0100\nMessage-ID: <m2@example.com>\nIn-Reply-To: <m1@example.com>\n\
+               References: <m0@example.com> <m1@example.com>\nList-Id: Team <team.example.com>\n\
+               List-Unsubscribe: <mailto:leave@example.com>\n\
+               Content-Type: multipart/mixed; boundary=b\n\n\
+               --b\nContent-Type: text/plain; charset=utf-8\n\nsee attached\n\
+               --b\nContent-Type: application/pdf; name=cv.pdf\nContent-Disposition: attachment; filename=\"cv.pdf\"\n\
+               Content-Transfer-Encoding: base64\n\nJVBERi0xLjQ=\n--b--\n";
+    let server = MockServer::start(vec![MockMessage::with_raw(2, 2, raw)]);
+    let mut imap = connect(&server);
+    imap.select("INBOX").unwrap();
+    let detail = imap.fetch_detail(2).unwrap().unwrap();
+
+    assert_eq!(detail.from[0].name.as_deref(), Some("Alice"));
+    assert_eq!(detail.to.iter().map(|a| a.address.as_str()).collect::<Vec<_>>(), ["me@example.com", "bob@example.com"]);
+    assert_eq!(detail.cc[0].address, "carol@example.com");
+    assert_eq!(detail.reply_to[0].address, "list@example.com");
+    assert_eq!(detail.subject, "résumé");
+    assert_eq!(detail.date.unwrap().to_rfc3339(), "2024-01-02T10:00:00+01:00");
+    assert_eq!(detail.message_id.as_deref(), Some("m2@example.com"));
+    assert_eq!(detail.in_reply_to, ["m1@example.com"]);
+    assert_eq!(detail.references, ["m0@example.com", "m1@example.com"]);
+    assert_eq!(detail.list_headers.len(), 2);
+    assert_eq!(detail.list_headers[0], ("List-Id".to_string(), "Team <team.example.com>".to_string()));
+    assert_eq!(detail.body.trim(), "see attached");
+    assert_eq!(detail.parts.len(), 2);
+    let attachments: Vec<_> = detail.attachments().collect();
+    assert_eq!(attachments.len(), 1);
+    assert_eq!((attachments[0].filename.as_deref(), attachments[0].content_type.as_str()), (Some("cv.pdf"), "application/pdf"));
+    assert_eq!(attachments[0].size, 8, "decoded size");
+    assert_eq!(server.commands("UID FETCH"), 1);
+    assert!(imap.fetch_detail(99).unwrap().is_none());
+}
+
+#[test]
+fn fetch_inbox_uses_one_fetch_for_a_large_mailbox()
+{
+    let server = MockServer::start((1..=20_000).map(MockMessage::numbered).collect());
+    let mut imap = connect(&server);
+
+    let started = Instant::now();
+    let inbox = imap.fetch_inbox(50).unwrap();
+    let elapsed = started.elapsed();
+
+    assert_eq!(inbox.len(), 50);
+    assert_eq!(inbox[0].uid, 20_000);
+    assert_eq!(inbox[49].uid, 19_951);
+    assert_eq!(inbox[0].from, "sender20000@example.com");
+    assert!(inbox.windows(2).all(|w| w[0].date >= w[1].date));
+
+    let st = server.state.lock().unwrap();
+    assert_eq!(st.commands("FETCH") + st.commands("UID FETCH"), 1);
+    assert!(st.log.iter().any(|l| l.starts_with("FETCH 19951:20000 ")), "{:?}", st.log);
+    assert!(elapsed < Duration::from_secs(2), "fetch_inbox took {:?}", elapsed);
+}
+
+#[test]
+fn fetch_inbox_handles_short_and_empty_mailboxes()
+{
+    let server = MockServer::start(vec![MockMessage::numbered(1), MockMessage::numbered(2)]);
+    let mut imap = connect(&server);
+    assert_eq!(imap.fetch_inbox(20).unwrap().iter().map(|m| m.uid).collect::<Vec<_>>(), [2, 1]);
+
+    let server = MockServer::start(Vec::new());
+    let mut imap = connect(&server);
+    assert!(imap.fetch_inbox(20).unwrap().is_empty());
+    assert_eq!(server.commands("FETCH"), 0);
+}
+
+#[test]
+fn refused_connection_is_a_disconnect()
+{
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let closed = ServerConfig
+    {
+        host: "127.0.0.1".into(),
+        port,
+        security: Security::Plain,
+        timeouts: Timeouts::default(),
+    };
+    let err = ImapClient::connect(&closed, "user", "pass").err().unwrap();
+    assert!(matches!(err, ImapError::Io(_)));
+    assert!(err.is_disconnect());
+}
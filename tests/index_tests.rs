@@ -0,0 +1,97 @@
+mod common;
+
+use bps_mail::cache::{snippet_parts, sync_mailbox, Cache};
+use bps_mail::imap::SearchQuery;
+use common::{connect, MockMessage, MockServer};
+
+fn mailbox() -> Vec<MockMessage>
+{
+    vec![
+        MockMessage::new(1, "Alice Archer <alice@example.com>", "quarterly report", 1, "the numbers look fine"),
+        MockMessage::new(2, "Bob <bob@example.com>", "lunch", 2, "shall we talk about the quarterly report over lunch?"),
+        MockMessage::new(3, "Carol <carol@example.com>", "holiday photos", 3, "photos from the beach"),
+    ]
+}
+
+/// `(folder, uid)` of the local hits for `query`, best first.
+fn hits(cache: &Cache, query: &str) -> Vec<(String, u32)>
+{
+    let query = SearchQuery::parse(query).unwrap();
+    cache.search(&query, 10).unwrap().into_iter().map(|h| (h.folder, h.summary.uid)).collect()
+}
+
+fn inbox(uids: &[u32]) -> Vec<(String, u32)>
+{
+    uids.iter().map(|&uid| ("INBOX".to_string(), uid)).collect()
+}
+
+#[test]
+fn synced_messages_are_searchable_without_being_opened()
+{
+    let server = MockServer::start(mailbox());
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    let mut imap = connect(&server);
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+
+    assert_eq!(hits(&cache, "from:ali"), inbox(&[1]), "prefix of a name");
+    assert_eq!(hits(&cache, "from:archer"), inbox(&[1]));
+    assert_eq!(cache.raw("INBOX", 3).unwrap(), None, "nothing was opened");
+    let query = SearchQuery::parse("beach").unwrap();
+    let found = cache.search(&query, 10).unwrap();
+    assert_eq!(found.len(), 1);
+    let snippet = found[0].snippet.as_deref().unwrap();
+    assert_eq!(snippet_parts(snippet), [("photos from the ", false), ("beach", true)]);
+    let mut to_me = hits(&cache, "to:me@example.com");
+    to_me.sort();
+    assert_eq!(to_me, inbox(&[1, 2, 3]), "recipients are indexed too");
+}
+
+#[test]
+fn bodies_are_indexed_once_from_their_first_bytes()
+{
+    let long = format!("opening words\r\n{}\r\nclosing words", "padding ".repeat(10_000));
+    let server = MockServer::start(vec![MockMessage::new(1, "Dave <dave@example.com>", "long one", 1, &long)]);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    let mut imap = connect(&server);
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+
+    assert_eq!(hits(&cache, "opening"), inbox(&[1]));
+    assert_eq!(hits(&cache, "closing"), Vec::new(), "past the indexed start");
+    assert_eq!(server.commands("UID FETCH 1 (UID BODY.PEEK[]<0."), 1, "not fetched again");
+}
+
+#[test]
+fn subject_matches_rank_above_body_matches()
+{
+    let server = MockServer::start(mailbox());
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    let mut imap = connect(&server);
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+
+    assert_eq!(hits(&cache, "quarterly report"), inbox(&[1, 2]));
+    assert_eq!(hits(&cache, "body:quarterly"), inbox(&[2]));
+    assert_eq!(hits(&cache, "quarterly before:2024-01-02"), inbox(&[1]));
+}
+
+#[test]
+fn status_filters_and_expunges_are_followed()
+{
+    let server = MockServer::start(mailbox());
+    server.state.lock().unwrap().set_flags("INBOX", 2, &["\\Seen", "\\Flagged"]);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    let mut imap = connect(&server);
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+
+    assert_eq!(hits(&cache, "is:unread"), inbox(&[3, 1]), "newest first without words to rank by");
+    assert_eq!(hits(&cache, "is:flagged lunch"), inbox(&[2]));
+
+    server.state.lock().unwrap().mailboxes.get_mut("INBOX").unwrap().retain(|m| m.uid != 1);
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+    assert_eq!(hits(&cache, "from:alice"), Vec::new());
+    assert_eq!(hits(&cache, "lunch"), inbox(&[2]));
+}
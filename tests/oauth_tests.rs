@@ -0,0 +1,183 @@
+mod common;
+
+use bps_mail::config::OAuth2Config;
+use bps_mail::imap::{ImapClient, ReconnectPolicy};
+use bps_mail::oauth::{xoauth2_response, OAuthClient, StoredToken};
+use common::http::MockHttp;
+use common::{local, MockMessage, MockServer};
+use std::time::Duration;
+
+const TOKEN_JSON: &str = r#"{"access_token":"fresh-token","token_type":"bearer","expires_in":3600}"#;
+
+fn oauth_config(http: &MockHttp) -> OAuth2Config
+{
+    OAuth2Config
+    {
+        client_id: "client-id".into(),
+        client_secret: Some("client-secret".into()),
+        auth_url: http.url("/auth"),
+        token_url: http.url("/token"),
+        device_auth_url: Some(http.url("/device")),
+        redirect_url: None,
+        scopes: vec!["https://mail.example.com/".into()],
+    }
+}
+
+#[test]
+fn refreshes_expired_token_and_persists_it()
+{
+    let http = MockHttp::start(vec![("/token", 200, TOKEN_JSON)]);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tokens").join("work.json");
+    StoredToken
+    {
+        access_token: "stale-token".into(),
+        refresh_token: Some("refresh-1".into()),
+        expires_at: Some(0),
+    }
+    .save(&path)
+    .unwrap();
+
+    let client = OAuthClient::new(oauth_config(&http), path.clone());
+    assert_eq!(client.access_token().unwrap(), "fresh-token");
+
+    let saved = StoredToken::load(&path).unwrap().unwrap();
+    assert_eq!(saved.access_token, "fresh-token");
+    assert_eq!(saved.refresh_token.as_deref(), Some("refresh-1"));
+    assert!(saved.is_fresh());
+
+    let requests = http.requests();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].1.contains("grant_type=refresh_token"));
+    assert!(requests[0].1.contains("refresh_token=refresh-1"));
+}
+
+#[test]
+fn fresh_token_is_used_without_contacting_the_server()
+{
+    let http = MockHttp::start(Vec::new());
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("work.json");
+    StoredToken
+    {
+        access_token: "still-good".into(),
+        refresh_token: None,
+        expires_at: Some(chrono::Utc::now().timestamp() + 3600),
+    }
+    .save(&path)
+    .unwrap();
+
+    let client = OAuthClient::new(oauth_config(&http), path);
+    assert_eq!(client.access_token().unwrap(), "still-good");
+    assert!(http.requests().is_empty());
+}
+
+#[test]
+fn device_flow_shows_code_and_obtains_token()
+{
+    let http = MockHttp::start(vec![
+        (
+            "/device",
+            200,
+            r#"{"device_code":"dev-1","user_code":"ABCD-EFGH","verification_uri":"https://example.com/device","expires_in":600,"interval":1}"#,
+        ),
+        ("/token", 200, r#"{"access_token":"device-token","token_type":"bearer","refresh_token":"refresh-2"}"#),
+    ]);
+    let dir = tempfile::tempdir().unwrap();
+    let client = OAuthClient::new(oauth_config(&http), dir.path().join("t.json"));
+
+    let mut shown = None;
+    let token = client
+        .device_flow(|uri, code| shown = Some((uri.to_string(), code.to_string())))
+        .unwrap();
+
+    assert_eq!(shown, Some(("https://example.com/device".into(), "ABCD-EFGH".into())));
+    assert_eq!(token.access_token, "device-token");
+    assert_eq!(token.refresh_token.as_deref(), Some("refresh-2"));
+    assert!(http.requests()[1].1.contains("device_code=dev-1"));
+}
+
+#[test]
+fn xoauth2_response_format()
+{
+    assert_eq!(
+        xoauth2_response("me@example.com", "tok"),
+        "user=me@example.com\x01auth=Bearer tok\x01\x01"
+    );
+}
+
+#[test]
+fn imap_authenticates_with_xoauth2()
+{
+    let server = MockServer::start(vec![MockMessage::new(1, "a@example.com", "hi", 1, "body")]);
+    let local = local(&server);
+
+    let mut imap = ImapClient::connect_xoauth2(&local, "user@example.com", "access-token").unwrap();
+    assert_eq!(imap.fetch_inbox(5).unwrap().len(), 1);
+    assert_eq!(server.commands("AUTHENTICATE XOAUTH2"), 1);
+
+    assert!(ImapClient::connect_xoauth2(&local, "user@example.com", "wrong-token").is_err());
+}
+
+#[test]
+fn imap_refreshes_a_short_lived_token_before_reconnecting()
+{
+    // Stale one second after it is issued, given the 60 s expiry margin.
+    let http = MockHttp::start(vec![(
+        "/token",
+        200,
+        r#"{"access_token":"token-{n}","token_type":"bearer","expires_in":61}"#,
+    )]);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("work.json");
+    StoredToken
+    {
+        access_token: "expired-token".into(),
+        refresh_token: Some("refresh-1".into()),
+        expires_at: Some(0),
+    }
+    .save(&path)
+    .unwrap();
+
+    let server = MockServer::start(vec![MockMessage::new(1, "a@example.com", "hi", 1, "body")]);
+    server.state.lock().unwrap().token = "token-1".into();
+    let local = local(&server);
+    let tokens = OAuthClient::new(oauth_config(&http), path).into_source();
+    let mut imap = ImapClient::connect_oauth2(&local, "user@example.com", tokens)
+        .unwrap()
+        .with_reconnect(ReconnectPolicy { attempts: 1, ..ReconnectPolicy::default() });
+    assert_eq!(imap.fetch_inbox(5).unwrap().len(), 1);
+    assert_eq!(http.requests().len(), 1);
+
+    // The server now only takes the token a refresh would hand out.
+    std::thread::sleep(Duration::from_millis(1100));
+    server.state.lock().unwrap().token = "token-2".into();
+    server.kill_connections();
+
+    assert_eq!(imap.fetch_inbox(5).unwrap().len(), 1);
+    assert_eq!(server.commands("AUTHENTICATE XOAUTH2"), 2);
+    let requests = http.requests();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[1].1.contains("grant_type=refresh_token"));
+}
+
+#[test]
+fn token_source_does_not_fall_back_to_interactive_authorization()
+{
+    let http = MockHttp::start(vec![("/token", 400, r#"{"error":"invalid_grant"}"#)]);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("work.json");
+    StoredToken
+    {
+        access_token: "expired-token".into(),
+        refresh_token: Some("revoked".into()),
+        expires_at: Some(0),
+    }
+    .save(&path)
+    .unwrap();
+
+    let tokens = OAuthClient::new(oauth_config(&http), path).into_source();
+    let err = tokens().unwrap_err();
+    assert!(err.to_string().contains("authorize again"), "{}", err);
+    assert_eq!(http.requests().len(), 1);
+}
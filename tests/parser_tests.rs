@@ -0,0 +1,77 @@
+mod common;
+
+use bps_mail::imap::{Address, Headers};
+use common::{connect, MockMessage, MockServer};
+
+fn address(name: Option<&str>, address: &str) -> Address
+{
+    Address { name: name.map(String::from), address: address.into() }
+}
+
+#[test]
+fn encoded_words_are_decoded_from_their_charset()
+{
+    let raw = b"Subject: =?UTF-8?B?w4ljb2xlIGQnw6l0w6k=?=\r\n\
+                X-Latin: =?iso-8859-1?Q?=A1Hola,_se=F1or!?=\r\n\
+                X-Mixed: Re: =?UTF-8?Q?caf=C3=A9?= =?UTF-8?Q?_cr=C3=A8me?= tonight\r\n\r\nbody";
+    let headers = Headers::parse(raw).unwrap();
+    assert_eq!(headers.get("subject").as_deref(), Some("École d'été"), "names are case-insensitive");
+    assert_eq!(headers.get("X-Latin").as_deref(), Some("¡Hola, señor!"));
+    assert_eq!(headers.get("X-Mixed").as_deref(), Some("Re: café crème tonight"), "adjacent words join up");
+    assert_eq!(headers.get("Body"), None, "the body is not a header");
+}
+
+#[test]
+fn folded_headers_are_unfolded()
+{
+    let raw = b"Subject: a subject that goes on\r\n and on\r\n\tand on\r\n\
+                References: <a@example.com>\r\n <b@example.com>\r\n\
+                In-Reply-To: <b@example.com>\r\n\r\n";
+    let headers = Headers::parse(raw).unwrap();
+    assert_eq!(headers.get("Subject").as_deref(), Some("a subject that goes on and on and on"));
+    assert_eq!(headers.message_ids("References"), ["a@example.com", "b@example.com"]);
+    assert_eq!(headers.message_ids("In-Reply-To"), ["b@example.com"]);
+    assert!(headers.message_ids("Message-ID").is_empty());
+}
+
+#[test]
+fn address_lists_become_names_and_addresses()
+{
+    let raw = "From: =?UTF-8?Q?M=C3=BCller=2C_Hans?= <hans@example.com>\r\n\
+               To: \"Doe, John\" <john@example.com>, bob@example.com,\r\n team: carol@example.com, Dan <dan@example.com>;\r\n\
+               Cc: undisclosed recipients\r\n\r\n";
+    let headers = Headers::parse(raw.as_bytes()).unwrap();
+    assert_eq!(headers.addresses("From"), [address(Some("Müller, Hans"), "hans@example.com")], "the comma is in the name");
+    assert_eq!(
+        headers.addresses("To"),
+        [
+            address(Some("Doe, John"), "john@example.com"),
+            address(None, "bob@example.com"),
+            address(None, "carol@example.com"),
+            address(Some("Dan"), "dan@example.com"),
+        ]
+    );
+    assert_eq!(headers.addresses("Cc"), [address(None, "undisclosed recipients")], "kept whole");
+    assert!(headers.addresses("Bcc").is_empty());
+
+    let written: Vec<String> = headers.addresses("To").iter().take(2).map(|a| a.to_string()).collect();
+    assert_eq!(written, ["\"Doe, John\" <john@example.com>", "bob@example.com"]);
+    assert_eq!(address(Some("Dan"), "dan@example.com").to_string(), "Dan <dan@example.com>");
+}
+
+#[test]
+fn summaries_show_decoded_names_and_subjects()
+{
+    let server = MockServer::start(vec![MockMessage::with_raw(
+        1,
+        1,
+        "From: =?UTF-8?B?SsO8cmdlbg==?= <jurgen@example.com>\n\
+         Subject: =?UTF-8?Q?Gr=C3=BC=C3=9Fe?=\n =?UTF-8?Q?_aus_K=C3=B6ln?=\n\
+         Date: Mon, 01 Jan 2024 10:00:00 +0000\n\nhallo\n",
+    )]);
+    let mut imap = connect(&server);
+    let list = imap.fetch_inbox(10).unwrap();
+    assert_eq!(list[0].sender(), "Jürgen");
+    assert_eq!(list[0].from, "jurgen@example.com");
+    assert_eq!(list[0].subject, "Grüße aus Köln");
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use bps_mail::config::{Security, ServerConfig, Timeouts};
+use bps_mail::imap::{ImapClient, ImapError, ReconnectPolicy};
+use common::{local, MockMessage, MockServer, MockState};
+use std::time::{Duration, Instant};
+
+fn with_read_timeout(server: &MockServer, read: Duration) -> ServerConfig
+{
+    ServerConfig { timeouts: Timeouts { connect: Duration::from_secs(1), read }, ..local(server) }
+}
+
+fn connect(server: &MockServer) -> ImapClient
+{
+    let policy = ReconnectPolicy
+    {
+        attempts: 3,
+        initial_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(50),
+    };
+    ImapClient::connect(&with_read_timeout(server, Duration::from_secs(5)), "user@example.com", "secret")
+        .unwrap()
+        .with_reconnect(policy)
+}
+
+fn two_folders() -> MockServer
+{
+    let mut state = MockState::new((1..=5).map(MockMessage::numbered).collect());
+    state.mailboxes.insert("Archive".into(), (1..=3).map(MockMessage::numbered).collect());
+    MockServer::with_state(state)
+}
+
+#[test]
+fn reconnects_and_reselects_after_the_connection_drops()
+{
+    let server = two_folders();
+    let mut imap = connect(&server);
+    imap.select("Archive").unwrap();
+    assert_eq!(imap.fetch_range(1, 3).unwrap().len(), 3);
+
+    server.kill_connections();
+
+    assert_eq!(imap.fetch_summaries("1:*").unwrap().len(), 3, "still looking at Archive");
+    assert_eq!(imap.mailbox(), "Archive");
+    assert_eq!(server.commands("LOGIN"), 2);
+    assert_eq!(server.commands("SELECT"), 2);
+}
+
+#[test]
+fn gives_up_while_the_server_is_down_and_recovers_later()
+{
+    let server = two_folders();
+    let mut imap = connect(&server);
+    imap.select("INBOX").unwrap();
+
+    server.state.lock().unwrap().down = true;
+    server.kill_connections();
+    let started = Instant::now();
+    let err = imap.fetch_range(1, 5).err().unwrap();
+    assert!(err.is_disconnect(), "{:?}", err);
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert_eq!(server.commands("LOGIN"), 1);
+
+    server.state.lock().unwrap().down = false;
+    assert_eq!(imap.fetch_range(1, 5).unwrap().len(), 5);
+    assert_eq!(server.commands("LOGIN"), 2);
+}
+
+#[test]
+fn read_timeout_replaces_a_hung_connection()
+{
+    let server = two_folders();
+    let mut imap = ImapClient::connect(&with_read_timeout(&server, Duration::from_millis(200)), "user@example.com", "secret")
+        .unwrap()
+        .with_reconnect(ReconnectPolicy { initial_delay: Duration::from_millis(10), ..ReconnectPolicy::default() });
+    imap.select("INBOX").unwrap();
+
+    server.state.lock().unwrap().stall = Some("UID".into());
+    let started = Instant::now();
+    assert_eq!(imap.fetch_summaries("1:*").unwrap().len(), 5);
+    assert!(started.elapsed() < Duration::from_secs(3));
+    assert_eq!(server.commands("LOGIN"), 2);
+}
+
+#[test]
+fn rejected_login_on_reconnect_is_not_retried()
+{
+    let server = two_folders();
+    let mut imap = connect(&server);
+
+    server.state.lock().unwrap().pass = "changed".into();
+    server.kill_connections();
+
+    assert!(matches!(imap.fetch_range(1, 5), Err(ImapError::Auth(_))));
+    assert_eq!(server.commands("LOGIN"), 2);
+}
+
+#[test]
+fn a_failed_tls_handshake_is_not_taken_for_a_dropped_connection()
+{
+    // The mock speaks plain IMAP, so the handshake fails
+    let server = two_folders();
+    let cfg = ServerConfig { security: Security::Tls, ..with_read_timeout(&server, Duration::from_secs(1)) };
+    let started = Instant::now();
+    let Err(err) = ImapClient::connect(&cfg, "user@example.com", "secret")
+    else
+    {
+        panic!("TLS to a plaintext server succeeded");
+    };
+    assert!(matches!(err, ImapError::Tls(_)), "{:?}", err);
+    assert!(!err.is_disconnect());
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn keepalive_pings_only_idle_connections()
+{
+    let server = two_folders();
+    let mut imap = connect(&server);
+
+    assert!(!imap.keepalive(Duration::from_secs(3600)).unwrap());
+    assert_eq!(server.commands("NOOP"), 0);
+    assert!(imap.keepalive(Duration::ZERO).unwrap());
+    assert_eq!(server.commands("NOOP"), 1);
+
+    // A keepalive on a dead connection quietly replaces it.
+    server.kill_connections();
+    assert!(imap.keepalive(Duration::ZERO).unwrap());
+    assert_eq!(server.commands("LOGIN"), 2);
+}
+
+#[test]
+fn a_reconnect_while_waiting_counts_as_a_change()
+{
+    let server = two_folders();
+    let mut imap = connect(&server);
+    imap.select("INBOX").unwrap();
+    assert!(!imap.wait_for_change(false, Duration::from_millis(10)).unwrap());
+
+    server.kill_connections();
+    assert!(imap.wait_for_change(false, Duration::from_millis(10)).unwrap());
+}
+
+#[test]
+fn a_drop_between_copy_and_expunge_leaves_one_copy()
+{
+    let mut state = MockState::new((1..=3).map(MockMessage::numbered).collect());
+    state.mailboxes.insert("Trash".into(), Vec::new());
+    state.drop_after = Some("UID COPY".into());
+    let server = MockServer::with_state(state);
+    let mut imap = connect(&server);
+    imap.select("INBOX").unwrap();
+
+    imap.move_message(2, "Trash").unwrap();
+
+    let state = server.state.lock().unwrap();
+    let trash: Vec<u32> = state.mailboxes["Trash"].iter().map(|m| m.uid).collect();
+    let inbox: Vec<u32> = state.mailboxes["INBOX"].iter().map(|m| m.uid).collect();
+    assert_eq!(trash, [1], "copied exactly once");
+    assert_eq!(inbox, [1, 3]);
+    assert_eq!(state.commands("UID COPY"), 1);
+    assert_eq!(state.commands("LOGIN"), 2);
+}
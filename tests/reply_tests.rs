@@ -0,0 +1,90 @@
+use bps_mail::compose::{forward, reply, Draft};
+use bps_mail::imap::{mail_detail, Headers, MailDetail};
+use lettre::message::Mailbox;
+
+const ORIGINAL: &str = "From: Alice <alice@example.com>\r\n\
+                        To: me@example.com, Bob <bob@example.com>\r\n\
+                        Cc: ME@EXAMPLE.COM, carol@example.com, bob@example.com\r\n\
+                        Reply-To: team@example.com\r\n\
+                        Subject: lunch\r\n\
+                        Date: Mon, 01 Jan 2024 10:00:00 +0000\r\n\
+                        Message-ID: <m2@example.com>\r\n\
+                        References: <m0@example.com> <m1@example.com>\r\n\
+                        In-Reply-To: <m1@example.com>\r\n\r\n\
+                        Noon?\r\n\r\n> earlier\r\n\r\n-- \r\nAlice\r\n";
+
+fn original(raw: &str) -> MailDetail
+{
+    mail_detail(1, raw.as_bytes()).unwrap()
+}
+
+fn me() -> Vec<String>
+{
+    vec!["me@example.com".into()]
+}
+
+#[test]
+fn replies_quote_the_original_and_continue_its_thread()
+{
+    let draft = reply(&original(ORIGINAL), false, &me());
+    assert_eq!(draft.to, "team@example.com", "Reply-To wins over From");
+    assert_eq!(draft.cc, "");
+    assert_eq!(draft.subject, "Re: lunch");
+    assert_eq!(draft.in_reply_to.as_deref(), Some("m2@example.com"));
+    assert_eq!(draft.references, ["m0@example.com", "m1@example.com", "m2@example.com"]);
+    assert_eq!(
+        draft.body,
+        "On Mon, 1 Jan 2024 at 10:00, Alice <alice@example.com> wrote:\n> Noon?\n>\n>> earlier\n\n",
+        "no signature, already quoted lines quoted again"
+    );
+    assert_eq!(reply(&original(&ORIGINAL.replace("lunch", "RE: lunch")), false, &me()).subject, "RE: lunch");
+
+    let message = Draft { body: "Sure".into(), ..draft }.message(Mailbox::new(None, "me@example.com".parse().unwrap())).unwrap();
+    let formatted = message.formatted();
+    let headers = Headers::parse(&formatted).unwrap();
+    assert_eq!(headers.message_ids("In-Reply-To"), ["m2@example.com"]);
+    assert_eq!(headers.message_ids("References"), ["m0@example.com", "m1@example.com", "m2@example.com"]);
+}
+
+#[test]
+fn replies_to_all_leave_us_out()
+{
+    let draft = reply(&original(ORIGINAL), true, &me());
+    assert_eq!(draft.to, "team@example.com");
+    assert_eq!(draft.cc, "Bob <bob@example.com>, carol@example.com", "each address once, ours in no spelling");
+
+    let followup = ORIGINAL.replace("Subject:", "Mail-Followup-To: list@example.com, me@example.com\r\nSubject:");
+    let draft = reply(&original(&followup), true, &me());
+    assert_eq!((draft.to.as_str(), draft.cc.as_str()), ("list@example.com", ""), "Mail-Followup-To wins");
+    assert_eq!(reply(&original(&followup), false, &me()).to, "team@example.com", "but only for replies to all");
+
+    // Answering our own message goes to its recipients again, and a
+    // message without References continues from its In-Reply-To
+    let sent = "From: Me <me@example.com>\r\nTo: bob@example.com\r\nSubject: lunch\r\n\
+                Message-ID: <m3@example.com>\r\nIn-Reply-To: <m2@example.com>\r\n\r\nNoon!\r\n";
+    let draft = reply(&original(sent), true, &me());
+    assert_eq!((draft.to.as_str(), draft.cc.as_str()), ("bob@example.com", ""));
+    assert_eq!(draft.references, ["m2@example.com", "m3@example.com"]);
+    assert!(draft.body.starts_with("Me <me@example.com> wrote:\n> Noon!"), "no date: {}", draft.body);
+}
+
+#[test]
+fn forwards_quote_the_message_or_attach_it_whole()
+{
+    let inline = forward(&original(ORIGINAL), None);
+    assert_eq!(inline.subject, "Fwd: lunch");
+    assert_eq!((inline.to.as_str(), inline.in_reply_to.as_deref()), ("", None), "a forward starts a new thread");
+    assert!(inline.body.starts_with("\n\n---------- Forwarded message ----------\nFrom: Alice <alice@example.com>\n\
+                                     Date: Mon, 1 Jan 2024 10:00:00 +0000\nSubject: lunch\n\
+                                     To: me@example.com, Bob <bob@example.com>\n"), "{}", inline.body);
+    assert!(inline.body.ends_with("\n\nNoon?\n\n> earlier\n\n-- \nAlice"), "{:?}", inline.body);
+
+    let attached = forward(&original(ORIGINAL), Some(ORIGINAL.as_bytes().to_vec()));
+    assert_eq!(attached.body, "");
+    let draft = Draft { to: "dan@example.com".into(), body: "FYI".into(), ..attached };
+    let message = draft.message(Mailbox::new(None, "me@example.com".parse().unwrap())).unwrap();
+    let text = String::from_utf8(message.formatted()).unwrap();
+    assert!(text.contains("Content-Type: message/rfc822\r\n"), "{}", text);
+    assert!(text.contains("filename=\"lunch.eml\""), "{}", text);
+    assert!(text.contains("Content-Transfer-Encoding: 7bit\r\n\r\nFrom: Alice <alice@example.com>\r\n"), "not re-encoded: {}", text);
+}
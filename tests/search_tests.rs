@@ -0,0 +1,130 @@
+mod common;
+
+use bps_mail::imap::{ImapError, SearchQuery, Term};
+use chrono::NaiveDate;
+use common::{connect, MockMessage, MockServer, MockState};
+
+fn inbox() -> Vec<MockMessage>
+{
+    vec![
+        MockMessage::new(1, "Alice <alice@example.com>", "invoice 2023", 1, "please pay"),
+        MockMessage::new(2, "Bob <bob@example.com>", "lunch?", 5, "pizza or sushi"),
+        MockMessage::new(3, "Alice <alice@example.com>", "Big Invoice", 10, "a refund is due"),
+        MockMessage::new(4, "Carol <carol@example.com>", "re: invoice", 20, "paid"),
+    ]
+}
+
+#[test]
+fn query_words_become_terms()
+{
+    let query = SearchQuery::parse(r#"from:alice subject:"big invoice" after:2025-01-01 is:unread larger:10k refund"#).unwrap();
+    assert_eq!(
+        query.terms,
+        [
+            Term::From("alice".into()),
+            Term::Subject("big invoice".into()),
+            Term::Since(NaiveDate::from_ymd_opt(2025, 1, 1).unwrap()),
+            Term::Unread,
+            Term::Larger(10 * 1024),
+            Term::Text("refund".into()),
+        ]
+    );
+    // An unknown key is just text
+    assert_eq!(SearchQuery::parse("re:lunch").unwrap().terms, [Term::Text("re:lunch".into())]);
+}
+
+#[test]
+fn query_is_turned_into_imap_criteria_and_gmail_syntax()
+{
+    let query = SearchQuery::parse(r#"from:alice subject:"big invoice" before:2025-03-05 is:flagged"#).unwrap();
+    assert_eq!(query.criteria(false), r#"FROM "alice" SUBJECT "big invoice" BEFORE 5-Mar-2025 FLAGGED"#);
+    assert_eq!(query.gmail(), r#"from:alice subject:"big invoice" before:2025/03/05 is:starred"#);
+
+    // Quoted strings are 7-bit: anything else goes in a literal
+    let query = SearchQuery::parse("from:alice subject:café").unwrap();
+    assert_eq!(query.criteria(false), "CHARSET UTF-8 FROM \"alice\" SUBJECT {5}\r\ncafé");
+    assert_eq!(query.criteria(true), "CHARSET UTF-8 FROM \"alice\" SUBJECT {5+}\r\ncafé");
+}
+
+#[test]
+fn malformed_queries_are_rejected()
+{
+    for (query, message) in [
+        ("after:yesterday", "'yesterday' is not a date"),
+        ("is:important", "unknown status 'is:important'"),
+        ("larger:huge", "'huge' is not a size"),
+        ("subject:\"open", "unmatched"),
+        ("   ", "nothing to search for"),
+    ]
+    {
+        match SearchQuery::parse(query)
+        {
+            Err(ImapError::Query(msg)) => assert!(msg.starts_with(message), "{}: {}", query, msg),
+            other => panic!("{}: {:?}", query, other),
+        }
+    }
+}
+
+#[test]
+fn search_finds_matching_uids_on_the_server()
+{
+    let server = MockServer::start(inbox());
+    server.state.lock().unwrap().set_flags("INBOX", 1, &["\\Seen"]);
+    let mut imap = connect(&server);
+    imap.select("INBOX").unwrap();
+
+    let mut search = |q: &str| imap.search(&SearchQuery::parse(q).unwrap()).unwrap();
+    assert_eq!(search("subject:invoice"), [1, 3, 4]);
+    assert_eq!(search("from:alice is:unread"), [3]);
+    assert_eq!(search("invoice after:2024-01-05 before:2024-01-15"), [3]);
+    assert_eq!(search("body:sushi"), [2]);
+    assert_eq!(search("from:nobody"), Vec::<u32>::new());
+    assert_eq!(server.commands("X-GM-RAW"), 0);
+}
+
+#[test]
+fn non_ascii_words_are_sent_as_literals()
+{
+    let messages = vec![
+        MockMessage::new(1, "Jürgen <j@example.com>", "Café au lait", 1, "x"),
+        MockMessage::new(2, "Bob <bob@example.com>", "cafe", 2, "x"),
+    ];
+    for capabilities in [&[][..], &["LITERAL+"], &["X-GM-EXT-1"]]
+    {
+        let mut state = MockState::new(messages.clone());
+        state.capabilities.extend(capabilities.iter().map(|c| c.to_string()));
+        let server = MockServer::with_state(state);
+        let mut imap = connect(&server);
+        imap.select("INBOX").unwrap();
+
+        let mut search = |q: &str| imap.search(&SearchQuery::parse(q).unwrap()).unwrap();
+        assert_eq!(search("from:jürgen"), [1], "{:?}", capabilities);
+        assert_eq!(search("café"), [1], "{:?}", capabilities);
+        assert_eq!(search("subject:cafe"), [2], "{:?}", capabilities);
+        // The connection is still in step afterwards
+        assert_eq!(search("from:bob"), [2], "{:?}", capabilities);
+
+        let log = server.state.lock().unwrap().log.clone();
+        let plus = if capabilities.contains(&"LITERAL+") { "+" } else { "" };
+        let expected = match capabilities.first()
+        {
+            Some(&"X-GM-EXT-1") => "UID SEARCH X-GM-RAW {5}\r\ncafé".to_string(),
+            _ => format!("UID SEARCH CHARSET UTF-8 TEXT {{5{}}}\r\ncafé", plus),
+        };
+        assert!(log.contains(&expected), "{:?}", log);
+    }
+}
+
+#[test]
+fn gmail_gets_its_own_search_syntax()
+{
+    let mut state = MockState::new(inbox());
+    state.capabilities.push("X-GM-EXT-1".into());
+    let server = MockServer::with_state(state);
+    let mut imap = connect(&server);
+    imap.select("INBOX").unwrap();
+
+    assert_eq!(imap.search(&SearchQuery::parse("from:carol is:unread").unwrap()).unwrap(), [4]);
+    let log = server.state.lock().unwrap().log.clone();
+    assert!(log.iter().any(|l| l.ends_with(r#"UID SEARCH X-GM-RAW "from:carol is:unread""#)), "{:?}", log);
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use bps_mail::cache::{sync_mailbox, Cache};
+use bps_mail::imap::{threads, MailSummary};
+use common::{connect, MockMessage, MockServer, MockState};
+
+/// A message with `extra` header lines, received on `day`.
+fn message(uid: u32, day: u32, subject: &str, extra: &str) -> MockMessage
+{
+    MockMessage::with_raw(
+        uid,
+        day,
+        &format!(
+            "From: Sender {uid} <s{uid}@example.com>\nSubject: {subject}\n\
+             Date: Mon, {day:02} Jan 2024 10:00:00 +0000\nMessage-ID: <m{uid}@example.com>\n{extra}\nbody {uid}\n"
+        ),
+    )
+}
+
+/// The UIDs of each thread of `list`.
+fn grouped(list: &[MailSummary]) -> Vec<Vec<u32>>
+{
+    threads(list).iter().map(|t| t.iter().map(|&i| list[i].uid).collect()).collect()
+}
+
+#[test]
+fn reply_headers_are_read_and_cached()
+{
+    let server = MockServer::start(vec![
+        message(1, 1, "lunch", ""),
+        message(2, 2, "Re: lunch", "In-Reply-To: <m1@example.com>\nReferences: <m0@example.com>\n\t<m1@example.com>\n"),
+    ]);
+    let mut imap = connect(&server);
+    let list = imap.fetch_inbox(10).unwrap();
+    assert_eq!(list[0].message_id.as_deref(), Some("m2@example.com"));
+    assert_eq!(list[0].references, ["m0@example.com", "m1@example.com"], "folded, In-Reply-To not repeated");
+    assert!(list[1].references.is_empty());
+
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+    let cached = cache.summaries("INBOX", 10).unwrap();
+    assert_eq!(cached[0].message_id, list[0].message_id);
+    assert_eq!(cached[0].references, list[0].references);
+}
+
+#[test]
+fn replies_are_grouped_into_conversations()
+{
+    let server = MockServer::start(vec![
+        message(1, 1, "lunch", ""),
+        message(2, 2, "Re: lunch", "In-Reply-To: <m1@example.com>\n"),
+        message(3, 3, "report", ""),
+        message(4, 4, "Re: lunch", "References: <m1@example.com> <m2@example.com>\n"),
+        // Both answer a message that is not in the list
+        message(5, 5, "Re: party", "References: <gone@example.com>\n"),
+        message(6, 6, "Re: Re: party", "References: <gone@example.com> <m5@example.com>\n"),
+        // No reply headers at all, only the subject and who it is to go by
+        message(7, 7, "RE: Report", "To: s3@example.com\n"),
+        // Same subject but not a reply: a conversation of its own
+        message(8, 8, "lunch", ""),
+    ]);
+    let mut imap = connect(&server);
+    let list = imap.fetch_inbox(10).unwrap();
+
+    assert_eq!(grouped(&list), [vec![8], vec![7, 3], vec![6, 5], vec![4, 2, 1]], "newest first");
+}
+
+#[test]
+fn headerless_replies_need_a_shared_participant()
+{
+    let server = MockServer::start(vec![
+        message(1, 1, "lunch", "To: me@example.com\n"),
+        // Someone else's lunch, also sent to me
+        message(2, 2, "Re: lunch", "To: me@example.com\n"),
+        // An answer to the first one
+        message(3, 3, "Re: lunch", "To: s1@example.com\n"),
+    ]);
+    let mut imap = connect(&server);
+    let list = imap.fetch_inbox(10).unwrap();
+    assert_eq!(list[0].recipients, ["s1@example.com"]);
+
+    assert_eq!(grouped(&list), [vec![3, 1], vec![2]]);
+}
+
+/// Syncs the inbox of a server with `capability` and groups the cached list.
+fn server_threads(capability: &str, messages: Vec<MockMessage>) -> (Vec<MailSummary>, MockServer)
+{
+    let mut state = MockState::new(messages);
+    state.capabilities.push(capability.into());
+    let server = MockServer::with_state(state);
+    let mut imap = connect(&server);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = Cache::open(&dir.path().join("c.sqlite")).unwrap();
+    sync_mailbox(&mut imap, &cache, "INBOX", 10).unwrap();
+    (cache.summaries("INBOX", 10).unwrap(), server)
+}
+
+/// Messages 1 and 3 belong together although no header says so; 2 names 1
+/// but the server has it elsewhere.
+fn placed_by_server() -> Vec<MockMessage>
+{
+    let mut messages = vec![
+        message(1, 1, "plans", ""),
+        message(2, 2, "Re: plans", "In-Reply-To: <m1@example.com>\n"),
+        message(3, 3, "tickets", ""),
+    ];
+    messages[2].thread = 1;
+    messages
+}
+
+#[test]
+fn gmail_thread_ids_take_precedence_over_headers()
+{
+    let mut messages = placed_by_server();
+    for m in &mut messages
+    {
+        m.thread += 9_000_000_000;
+    }
+    let (list, server) = server_threads("X-GM-EXT-1", messages);
+
+    assert_eq!(list[0].thread_id, Some(9_000_000_001));
+    assert_eq!(grouped(&list), [vec![3, 1], vec![2]]);
+    assert_eq!(server.commands("UID FETCH 1:* (UID X-GM-THRID)"), 1);
+}
+
+#[test]
+fn thread_extension_takes_precedence_over_headers()
+{
+    let (list, server) = server_threads("THREAD=REFERENCES", placed_by_server());
+
+    assert_eq!(list.iter().map(|m| m.thread_id).collect::<Vec<_>>(), [Some(1), Some(2), Some(1)]);
+    assert_eq!(grouped(&list), [vec![3, 1], vec![2]]);
+    assert_eq!(server.commands("UID THREAD REFERENCES"), 1);
+}
+
+#[test]
+fn headers_decide_without_server_threads()
+{
+    let (list, server) = server_threads("IMAP4rev1", placed_by_server());
+
+    assert!(list.iter().all(|m| m.thread_id.is_none()));
+    assert_eq!(grouped(&list), [vec![3], vec![2, 1]]);
+    assert_eq!(server.commands("UID THREAD"), 0);
+}
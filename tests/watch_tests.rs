@@ -0,0 +1,90 @@
+mod common;
+
+use bps_mail::imap::{spawn_watcher, ImapClient, WatchConfig, WatchEvent};
+use common::{local, MockMessage, MockServer, MockState};
+use std::sync::mpsc;
+use std::time::Duration;
+
+fn watch(server: &MockServer, config: WatchConfig) -> mpsc::Receiver<String>
+{
+    let cfg = local(server);
+    let (tx, rx) = mpsc::channel();
+    spawn_watcher(
+        move || ImapClient::connect(&cfg, "user@example.com", "secret"),
+        "INBOX".into(),
+        config,
+        move |event| 
+        {
+            let text = match event
+            {
+                WatchEvent::Changed => "changed".to_string(),
+                WatchEvent::Error(e) => e,
+            };
+            tx.send(text).is_ok()
+        },
+    );
+    rx
+}
+
+/// Waits until the watcher has issued `verb` at least once.
+fn wait_for_command(server: &MockServer, verb: &str)
+{
+    for _ in 0..200
+    {
+        if server.commands(verb) > 0
+        {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    panic!("watcher never sent {}", verb);
+}
+
+#[test]
+fn idle_reports_new_mail()
+{
+    let mut state = MockState::new(vec![MockMessage::numbered(1)]);
+    state.capabilities.push("IDLE".into());
+    let server = MockServer::with_state(state);
+    let config = WatchConfig { idle_renew: Duration::from_secs(30), ..WatchConfig::default() };
+    let events = watch(&server, config);
+
+    wait_for_command(&server, "IDLE");
+    server.state.lock().unwrap().mailboxes.get_mut("INBOX").unwrap().push(MockMessage::numbered(2));
+
+    assert_eq!(events.recv_timeout(Duration::from_secs(5)).unwrap(), "changed");
+    assert_eq!(server.commands("NOOP"), 0);
+}
+
+#[test]
+fn idle_reports_flag_changes()
+{
+    let mut state = MockState::new(vec![MockMessage::numbered(1)]);
+    state.capabilities.push("IDLE".into());
+    let server = MockServer::with_state(state);
+    let events = watch(&server, WatchConfig::default());
+
+    wait_for_command(&server, "IDLE");
+    server.state.lock().unwrap().set_flags("INBOX", 1, &["\\Seen"]);
+
+    assert_eq!(events.recv_timeout(Duration::from_secs(5)).unwrap(), "changed");
+}
+
+#[test]
+fn falls_back_to_noop_polling_without_idle()
+{
+    let server = MockServer::start(vec![MockMessage::numbered(1)]);
+    let config = WatchConfig { poll_interval: Duration::from_millis(50), ..WatchConfig::default() };
+    let events = watch(&server, config);
+
+    // Quiet polls report nothing.
+    wait_for_command(&server, "NOOP");
+    assert!(events.recv_timeout(Duration::from_millis(200)).is_err());
+
+    // A flag change arrives as an unsolicited FETCH, which must not break the poll.
+    server.state.lock().unwrap().set_flags("INBOX", 1, &["\\Flagged"]);
+    assert_eq!(events.recv_timeout(Duration::from_secs(5)).unwrap(), "changed");
+    server.state.lock().unwrap().mailboxes.get_mut("INBOX").unwrap().push(MockMessage::numbered(2));
+    assert_eq!(events.recv_timeout(Duration::from_secs(5)).unwrap(), "changed");
+    assert_eq!(server.commands("IDLE"), 0);
+}
@@ -0,0 +1,493 @@
+mod common;
+
+use bps_mail::compose::{Draft, Response};
+use bps_mail::config::{Config, Folders, Secret, ServerConfig};
+use bps_mail::imap::Flag;
+use bps_mail::worker::{Done, Event, Request, Worker};
+use common::{local, MockMessage, MockServer, MockState};
+use std::time::Duration;
+
+fn config(server: &MockServer) -> Config
+{
+    Config
+    {
+        name: "test".into(),
+        email: "user@example.com".into(),
+        display_name: None,
+        signature: None,
+        aliases: vec!["me@example.com".into()],
+        download_dir: std::env::temp_dir(),
+        imap_user: "user@example.com".into(),
+        imap_pass: Secret::from("secret"),
+        smtp_user: "user@example.com".into(),
+        smtp_pass: Secret::from("secret"),
+        imap: local(server),
+        smtp: ServerConfig { port: 1, ..local(server) },
+        folders: Folders::default(),
+        oauth2: None,
+    }
+}
+
+fn start(server: &MockServer, dir: &tempfile::TempDir) -> Worker
+{
+    Worker::start(config(server), None, dir.path().join("c.sqlite")).unwrap()
+}
+
+/// Collects events until request `id` finishes.
+fn until_finished(worker: &Worker, id: u64) -> (Vec<Event>, Result<Done, String>)
+{
+    let mut events = Vec::new();
+    while let Some(event) = worker.recv_timeout(Duration::from_secs(5))
+    {
+        match event
+        {
+            Event::Finished { id: done, result } if done == id => return (events, result),
+            other => events.push(other),
+        }
+    }
+    panic!("request {} never finished; got {:?}", id, events);
+}
+
+fn raw_fetches(server: &MockServer) -> usize
+{
+    server.state.lock().unwrap().log.iter().filter(|l| l.ends_with("BODY.PEEK[]")).count()
+}
+
+#[test]
+fn open_folder_shows_the_cache_then_the_synced_messages()
+{
+    let server = MockServer::start((1..=30).map(MockMessage::numbered).collect());
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+
+    let id = worker.submit(Request::OpenFolder { folder: "INBOX".into(), count: 5 });
+    let (events, result) = until_finished(&worker, id);
+
+    let lists: Vec<Vec<u32>> = events
+        .iter()
+        .filter_map(|e| match e
+        {
+            Event::Messages { folder, summaries } if folder == "INBOX" => Some(summaries.iter().map(|m| m.uid).collect()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(lists.first(), Some(&Vec::new()), "the empty cache is shown first");
+    assert_eq!(lists.last(), Some(&(26..=30).rev().collect::<Vec<_>>()));
+    assert!(events.iter().any(|e| matches!(e, Event::Folders(f) if f.contains(&"INBOX".to_string()))));
+    assert!(matches!(result, Ok(Done::Synced { report, .. }) if report.fetched == 5));
+}
+
+#[test]
+fn viewed_messages_are_fetched_once()
+{
+    let server = MockServer::start((1..=3).map(MockMessage::numbered).collect());
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+    let id = worker.submit(Request::OpenFolder { folder: "INBOX".into(), count: 3 });
+    until_finished(&worker, id).1.unwrap();
+
+    for _ in 0..2
+    {
+        let id = worker.submit(Request::View { folder: "INBOX".into(), uid: 2 });
+        match until_finished(&worker, id).1
+        {
+            Ok(Done::Viewed { detail }) =>
+            {
+                assert_eq!(detail.uid, 2);
+                assert_eq!(detail.from[0].to_string(), "Sender 2 <sender2@example.com>");
+                assert_eq!(detail.subject, "message 2");
+                assert!(detail.body.contains("body 2"));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+    assert_eq!(raw_fetches(&server), 1);
+}
+
+#[test]
+fn viewing_marks_unread_mail_as_read()
+{
+    let server = MockServer::start((1..=3).map(MockMessage::numbered).collect());
+    server.state.lock().unwrap().set_flags("INBOX", 1, &["\\Seen"]);
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+    let id = worker.submit(Request::OpenFolder { folder: "INBOX".into(), count: 3 });
+    until_finished(&worker, id).1.unwrap();
+
+    let id = worker.submit(Request::View { folder: "INBOX".into(), uid: 2 });
+    let (events, result) = until_finished(&worker, id);
+    assert!(matches!(result, Ok(Done::Viewed { ref detail }) if detail.uid == 2));
+    let Some(Event::Messages { summaries, .. }) = events.iter().rfind(|e| matches!(e, Event::Messages { .. }))
+    else
+    {
+        panic!("no message list after viewing: {:?}", events);
+    };
+    assert!(summaries.iter().find(|m| m.uid == 2).unwrap().has(Flag::Seen));
+
+    // Already read: no STORE
+    let id = worker.submit(Request::View { folder: "INBOX".into(), uid: 1 });
+    until_finished(&worker, id).1.unwrap();
+    assert_eq!(server.commands("UID STORE"), 1);
+}
+
+#[test]
+fn a_message_missing_on_the_server_is_not_cached_empty()
+{
+    let server = MockServer::start((1..=3).map(MockMessage::numbered).collect());
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+    let id = worker.submit(Request::OpenFolder { folder: "INBOX".into(), count: 3 });
+    until_finished(&worker, id).1.unwrap();
+
+    let gone =
+    {
+        let mut st = server.state.lock().unwrap();
+        let inbox = st.mailboxes.get_mut("INBOX").unwrap();
+        let at = inbox.iter().position(|m| m.uid == 2).unwrap();
+        (at, inbox.remove(at))
+    };
+    let id = worker.submit(Request::View { folder: "INBOX".into(), uid: 2 });
+    let err = until_finished(&worker, id).1.unwrap_err();
+    assert_eq!(err, "message no longer on the server");
+
+    server.state.lock().unwrap().mailboxes.get_mut("INBOX").unwrap().insert(gone.0, gone.1);
+    let id = worker.submit(Request::View { folder: "INBOX".into(), uid: 2 });
+    assert!(matches!(until_finished(&worker, id).1, Ok(Done::Viewed { detail }) if detail.subject == "message 2"));
+}
+
+#[test]
+fn a_conversation_is_loaded_in_one_request()
+{
+    let server = MockServer::start((1..=3).map(MockMessage::numbered).collect());
+    server.state.lock().unwrap().set_flags("INBOX", 1, &["\\Seen"]);
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+    let id = worker.submit(Request::OpenFolder { folder: "INBOX".into(), count: 3 });
+    until_finished(&worker, id).1.unwrap();
+
+    let id = worker.submit(Request::ViewThread { folder: "INBOX".into(), uids: vec![1, 3] });
+    let Ok(Done::ViewedThread { messages }) = until_finished(&worker, id).1
+    else
+    {
+        panic!("conversation not loaded");
+    };
+    assert_eq!(messages.iter().map(|m| m.uid).collect::<Vec<_>>(), [1, 3]);
+    assert!(messages[1].body.contains("body 3"));
+    assert_eq!(raw_fetches(&server), 2);
+    assert_eq!(server.commands("UID STORE"), 1, "only the unread one is marked read");
+}
+
+#[test]
+fn attachments_are_saved_to_the_download_directory()
+{
+    let server = MockServer::start(vec![MockMessage::with_raw(
+        1,
+        1,
+        "From: Alice <alice@example.com>\nSubject: minutes\nMIME-Version: 1.0\n\
+         Content-Type: multipart/mixed; boundary=\"b\"\n\n\
+         --b\nContent-Type: text/plain\n\nsee attached\n\
+         --b\nContent-Type: text/plain; name=\"minutes.txt\"\nContent-Disposition: attachment; filename=\"../minutes.txt\"\n\n\
+         we met\n--b--\n",
+    )]);
+    let dir = tempfile::tempdir().unwrap();
+    let mut cfg = config(&server);
+    cfg.download_dir = dir.path().join("downloads");
+    let mut worker = Worker::start(cfg, None, dir.path().join("c.sqlite")).unwrap();
+    let id = worker.submit(Request::OpenFolder { folder: "INBOX".into(), count: 1 });
+    until_finished(&worker, id).1.unwrap();
+
+    let mut saved = Vec::new();
+    for _ in 0..2
+    {
+        let id = worker.submit(Request::SaveAttachments { folder: "INBOX".into(), uid: 1, parts: vec![1] });
+        match until_finished(&worker, id).1
+        {
+            Ok(Done::Saved { paths }) => saved.extend(paths),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+    let downloads = dir.path().join("downloads");
+    assert_eq!(saved, [downloads.join("minutes.txt"), downloads.join("minutes (1).txt")], "nothing is overwritten");
+    assert_eq!(std::fs::read_to_string(&saved[1]).unwrap(), "we met");
+    assert_eq!(raw_fetches(&server), 1);
+
+    let id = worker.submit(Request::SaveAttachments { folder: "INBOX".into(), uid: 1, parts: vec![7] });
+    assert!(until_finished(&worker, id).1.is_err());
+}
+
+#[test]
+fn replies_and_forwards_are_drafted_from_the_message()
+{
+    let raw = "From: Alice <alice@example.com>\nTo: user@example.com\nCc: me@example.com, bob@example.com\n\
+               Subject: plans\nMessage-ID: <p1@example.com>\n\nSee you\n";
+    let server = MockServer::start(vec![MockMessage::with_raw(1, 1, raw)]);
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+    let id = worker.submit(Request::OpenFolder { folder: "INBOX".into(), count: 1 });
+    until_finished(&worker, id).1.unwrap();
+
+    let mut drafts = Vec::new();
+    for response in [Response::ReplyAll, Response::ForwardAttached]
+    {
+        let id = worker.submit(Request::Respond { folder: "INBOX".into(), uid: 1, response });
+        match until_finished(&worker, id).1
+        {
+            Ok(Done::Drafted { draft }) => drafts.push(*draft),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+    assert_eq!((drafts[0].to.as_str(), drafts[0].cc.as_str()), ("Alice <alice@example.com>", "bob@example.com"), "account and alias left out");
+    assert_eq!(drafts[0].in_reply_to.as_deref(), Some("p1@example.com"));
+    assert_eq!(drafts[1].subject, "Fwd: plans");
+    assert!(drafts[1].forwarded.as_ref().is_some_and(|raw| raw.starts_with(b"From: Alice")), "the raw message");
+    assert_eq!(raw_fetches(&server), 1);
+}
+
+#[test]
+fn flags_are_toggled_on_the_server_and_in_the_list()
+{
+    let server = MockServer::start((1..=3).map(MockMessage::numbered).collect());
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+    let id = worker.submit(Request::OpenFolder { folder: "INBOX".into(), count: 3 });
+    until_finished(&worker, id).1.unwrap();
+
+    let id = worker.submit(Request::SetFlag { folder: "INBOX".into(), uid: 3, flag: Flag::Flagged, on: true });
+    let (events, result) = until_finished(&worker, id);
+    assert!(matches!(result, Ok(Done::FlagSet { uid: 3, ref flags }) if flags == &["\\Flagged"]));
+    assert!(events.iter().any(|e| matches!(e, Event::Messages { summaries, .. }
+        if summaries.iter().any(|m| m.uid == 3 && m.has(Flag::Flagged)))));
+    assert_eq!(server.state.lock().unwrap().mailboxes["INBOX"][2].flags, ["\\Flagged"]);
+}
+
+#[test]
+fn cancelled_requests_never_finish()
+{
+    let server = MockServer::start((1..=3).map(MockMessage::numbered).collect());
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+
+    let cancelled = worker.submit(Request::View { folder: "INBOX".into(), uid: 1 });
+    worker.cancel(cancelled);
+    let id = worker.submit(Request::View { folder: "INBOX".into(), uid: 3 });
+
+    let (events, result) = until_finished(&worker, id);
+    assert!(matches!(result, Ok(Done::Viewed { ref detail }) if detail.uid == 3));
+    assert!(!events.iter().any(|e| matches!(e, Event::Finished { id, .. } if *id == cancelled)));
+}
+
+#[test]
+fn failures_are_reported_and_the_next_request_still_works()
+{
+    let server = MockServer::start((1..=3).map(MockMessage::numbered).collect());
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+
+    let id = worker.submit(Request::View { folder: "Missing".into(), uid: 1 });
+    assert!(until_finished(&worker, id).1.is_err());
+
+    let id = worker.submit(Request::Archive { folder: "INBOX".into(), uid: 3 });
+    let err = until_finished(&worker, id).1.unwrap_err();
+    assert!(err.starts_with("no Archive folder found"), "{}", err);
+
+    let id = worker.submit(Request::View { folder: "INBOX".into(), uid: 3 });
+    assert!(matches!(until_finished(&worker, id).1, Ok(Done::Viewed { detail }) if detail.uid == 3));
+}
+
+#[test]
+fn delete_moves_to_trash_and_only_trash_deletes_for_good()
+{
+    let mut state = MockState::new((1..=3).map(MockMessage::numbered).collect());
+    state.mailboxes.insert("Trash".into(), Vec::new());
+    state.mailboxes.insert("Archive".into(), Vec::new());
+    let server = MockServer::with_state(state);
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+
+    let id = worker.submit(Request::Delete { folder: "INBOX".into(), uid: 3 });
+    let (events, result) = until_finished(&worker, id);
+    assert!(matches!(result, Ok(Done::Moved { uid: 3, ref to, .. }) if to == "Trash"), "{:?}", result);
+    assert!(events.iter().any(|e| matches!(e, Event::Messages { folder, .. } if folder == "INBOX")));
+
+    let id = worker.submit(Request::Archive { folder: "INBOX".into(), uid: 1 });
+    assert!(matches!(until_finished(&worker, id).1, Ok(Done::Moved { uid: 1, ref to, .. }) if to == "Archive"));
+
+    let id = worker.submit(Request::Delete { folder: "Trash".into(), uid: 1 });
+    assert!(matches!(until_finished(&worker, id).1, Ok(Done::Deleted { uid: 1 })));
+
+    let st = server.state.lock().unwrap();
+    assert_eq!(st.mailboxes["INBOX"].iter().map(|m| m.uid).collect::<Vec<_>>(), [2]);
+    assert!(st.mailboxes["Trash"].is_empty());
+    assert_eq!(st.mailboxes["Archive"].len(), 1);
+}
+
+#[test]
+fn a_moved_message_can_be_moved_back_under_its_new_uid()
+{
+    let mut state = MockState::new((1..=3).map(MockMessage::numbered).collect());
+    state.mailboxes.insert("Trash".into(), vec![MockMessage::numbered(40)]);
+    state.capabilities.extend(["MOVE".to_string(), "UIDPLUS".to_string()]);
+    let server = MockServer::with_state(state);
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+    let id = worker.submit(Request::OpenFolder { folder: "INBOX".into(), count: 10 });
+    until_finished(&worker, id).1.unwrap();
+
+    let id = worker.submit(Request::Delete { folder: "INBOX".into(), uid: 2 });
+    let Ok(Done::Moved { new_uid: Some(new_uid), .. }) = until_finished(&worker, id).1
+    else
+    {
+        panic!("no UID reported for the moved message");
+    };
+    assert_eq!(new_uid, 41);
+
+    let id = worker.submit(Request::Move { folder: "Trash".into(), uid: new_uid, to: "INBOX".into() });
+    assert!(matches!(until_finished(&worker, id).1, Ok(Done::Moved { new_uid: Some(4), .. })));
+
+    // INBOX was opened before, so it is synced and the message listed again
+    let mut listed = false;
+    while let Some(event) = worker.recv_timeout(Duration::from_secs(2))
+    {
+        if let Event::Messages { folder, summaries } = event
+        {
+            if folder == "INBOX" && summaries.iter().any(|m| m.uid == 4 && m.subject == "message 2")
+            {
+                listed = true;
+                break;
+            }
+        }
+    }
+    assert!(listed);
+    let st = server.state.lock().unwrap();
+    assert_eq!(st.mailboxes["Trash"].iter().map(|m| m.uid).collect::<Vec<_>>(), [40]);
+}
+
+#[test]
+fn search_covers_every_folder_but_trash_and_junk()
+{
+    let mut state = MockState::new(vec![
+        MockMessage::new(1, "Alice <alice@example.com>", "invoice", 1, "x"),
+        MockMessage::new(2, "Bob <bob@example.com>", "lunch", 2, "x"),
+    ]);
+    state.mailboxes.insert("Archive".into(), vec![MockMessage::new(7, "Carol <carol@example.com>", "old invoice", 3, "x")]);
+    state.mailboxes.insert("Trash".into(), vec![MockMessage::new(9, "Dan <dan@example.com>", "invoice", 4, "x")]);
+    let server = MockServer::with_state(state);
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+
+    let id = worker.submit(Request::Search { query: "subject:invoice".into(), count: 10 });
+    let Ok(Done::Found { results, .. }) = until_finished(&worker, id).1
+    else
+    {
+        panic!("search failed");
+    };
+    let hits: Vec<(&str, u32)> = results.iter().map(|h| (h.folder.as_str(), h.summary.uid)).collect();
+    assert_eq!(hits, [("Archive", 7), ("INBOX", 1)], "newest first");
+
+    let id = worker.submit(Request::Search { query: "after:someday".into(), count: 10 });
+    let err = until_finished(&worker, id).1.unwrap_err();
+    assert!(err.starts_with("invalid search:"), "{}", err);
+}
+
+#[test]
+fn search_uses_only_all_mail_where_there_is_one()
+{
+    let mut state = MockState::new(vec![MockMessage::new(1, "Alice <alice@example.com>", "invoice", 1, "x")]);
+    state.mailboxes.insert("All Mail".into(), vec![MockMessage::new(30, "Alice <alice@example.com>", "invoice", 1, "x")]);
+    state.mailboxes.insert("Archive".into(), vec![MockMessage::new(7, "Carol <carol@example.com>", "invoice", 3, "x")]);
+    let server = MockServer::with_state(state);
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+
+    let id = worker.submit(Request::Search { query: "invoice".into(), count: 10 });
+    let Ok(Done::Found { results, .. }) = until_finished(&worker, id).1
+    else
+    {
+        panic!("search failed");
+    };
+    assert_eq!(results.iter().map(|h| (h.folder.as_str(), h.summary.uid)).collect::<Vec<_>>(), [("All Mail", 30)]);
+    assert_eq!(server.commands("UID SEARCH"), 1);
+}
+
+#[test]
+fn search_lists_a_message_once_by_its_message_id()
+{
+    let invoice = MockMessage::new(1, "Alice <alice@example.com>", "invoice", 1, "x");
+    let mut state = MockState::new(vec![invoice.clone()]);
+    // The same message under its All Mail UID, and a different one that
+    // only shares sender, subject and date with it
+    let copy = MockMessage { uid: 30, ..invoice };
+    let twin = MockMessage::new(31, "Alice <alice@example.com>", "invoice", 1, "x");
+    state.mailboxes.insert("All Mail".into(), vec![copy, twin]);
+    let server = MockServer::with_state(state);
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+    let id = worker.submit(Request::OpenFolder { folder: "INBOX".into(), count: 10 });
+    until_finished(&worker, id).1.unwrap();
+
+    let id = worker.submit(Request::Search { query: "invoice".into(), count: 10 });
+    let Ok(Done::Found { results, .. }) = until_finished(&worker, id).1
+    else
+    {
+        panic!("search failed");
+    };
+    let hits: Vec<(&str, u32)> = results.iter().map(|h| (h.folder.as_str(), h.summary.uid)).collect();
+    assert_eq!(hits, [("INBOX", 1), ("All Mail", 31)]);
+}
+
+#[test]
+fn cached_matches_come_first_and_still_work_offline()
+{
+    let server = MockServer::start(vec![
+        MockMessage::new(1, "Alice <alice@example.com>", "invoice", 1, "x"),
+        MockMessage::new(2, "Bob <bob@example.com>", "lunch", 2, "x"),
+        MockMessage::new(3, "Carol <carol@example.com>", "invoice", 3, "x"),
+    ]);
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+    let id = worker.submit(Request::OpenFolder { folder: "INBOX".into(), count: 2 });
+    until_finished(&worker, id).1.unwrap();
+
+    // Only 2 and 3 are cached; the server adds 1
+    let id = worker.submit(Request::Search { query: "invoice".into(), count: 10 });
+    let (events, result) = until_finished(&worker, id);
+    let cached: Vec<u32> = events
+        .iter()
+        .find_map(|e| match e
+        {
+            Event::Found { hits, .. } => Some(hits.iter().map(|h| h.summary.uid).collect()),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(cached, [3]);
+    let Ok(Done::Found { results, .. }) = result
+    else
+    {
+        panic!("search failed: {:?}", result);
+    };
+    assert_eq!(results.iter().map(|h| h.summary.uid).collect::<Vec<_>>(), [3, 1]);
+    assert!(results[0].snippet.is_some() && results[1].snippet.is_none());
+
+    // A new session that cannot reach the server still searches the cache
+    drop(worker);
+    server.state.lock().unwrap().down = true;
+    server.kill_connections();
+    let mut worker = start(&server, &dir);
+    let id = worker.submit(Request::Search { query: "invoice".into(), count: 10 });
+    let (events, result) = until_finished(&worker, id);
+    assert!(matches!(result, Ok(Done::Found { ref results, .. }) if results.len() == 1), "{:?}", result);
+    assert!(events.iter().any(|e| matches!(e, Event::Status(s) if s.contains("cached matches only"))));
+}
+
+#[test]
+fn bad_recipient_is_reported_without_sending()
+{
+    let server = MockServer::start(Vec::new());
+    let dir = tempfile::tempdir().unwrap();
+    let mut worker = start(&server, &dir);
+
+    let draft = Draft { to: "bob@example.com, not an address".into(), subject: "hi".into(), ..Draft::default() };
+    let id = worker.submit(Request::Send { draft, answering: None });
+    let err = until_finished(&worker, id).1.unwrap_err();
+    assert!(err.starts_with("invalid address 'not an address'"), "{}", err);
+}